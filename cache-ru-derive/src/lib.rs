@@ -0,0 +1,190 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    ext::IdentExt, parse_macro_input, Data, DeriveInput, Fields, GenericArgument, LitStr,
+    PathArguments, Type,
+};
+
+/// Derives `ToRedisArgs` and `FromRedisValue` so a struct with named fields
+/// can be stored with `hsetall` and read back with `hgetall`.
+///
+/// Every field is written as one hash field named after it. Field values use
+/// their own redis encoding, so numbers are stored as decimal text.
+///
+/// - `#[redis(rename = "name")]` stores the field under another name.
+/// - `#[redis(skip)]` leaves the field out; it is read back as `Default::default()`.
+/// - `Option<T>` fields are omitted when `None` and read as `None` when missing.
+#[proc_macro_derive(RedisHash, attributes(redis))]
+pub fn derive_redis_hash(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+struct HashField {
+    ident: syn::Ident,
+    name: LitStr,
+    ty: Type,
+    skip: bool,
+    optional: bool,
+}
+
+impl HashField {
+    fn parse(field: &syn::Field) -> syn::Result<Self> {
+        let ident = field.ident.clone().expect("named field");
+        let mut name = LitStr::new(&ident.unraw().to_string(), ident.span());
+        let mut skip = false;
+        for attr in field.attrs.iter().filter(|a| a.path().is_ident("redis")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    name = meta.value()?.parse()?;
+                    Ok(())
+                } else if meta.path.is_ident("skip") {
+                    skip = true;
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported redis attribute, expected `rename` or `skip`"))
+                }
+            })?;
+        }
+        Ok(HashField {
+            ident,
+            name,
+            ty: field.ty.clone(),
+            skip,
+            optional: is_option(&field.ty),
+        })
+    }
+
+    /// The local that collects this field while decoding; prefixed so it
+    /// cannot clash with the other locals of the generated function.
+    fn slot(&self) -> syn::Ident {
+        format_ident!("__{}", self.ident.unraw())
+    }
+}
+
+fn is_option(ty: &Type) -> bool {
+    let Type::Path(path) = ty else {
+        return false;
+    };
+    path.qself.is_none()
+        && path.path.segments.last().is_some_and(|seg| {
+            seg.ident == "Option"
+                && matches!(&seg.arguments, PathArguments::AngleBracketed(args)
+                    if matches!(args.args.first(), Some(GenericArgument::Type(_))))
+        })
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "RedisHash can only be derived for structs",
+        ));
+    };
+    let Fields::Named(named) = &data.fields else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "RedisHash requires a struct with named fields",
+        ));
+    };
+    let fields = named
+        .named
+        .iter()
+        .map(HashField::parse)
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    let ident = &input.ident;
+    let struct_name = ident.to_string();
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let writes = fields.iter().filter(|f| !f.skip).map(|f| {
+        let (ident, name) = (&f.ident, &f.name);
+        if f.optional {
+            quote! {
+                if let ::std::option::Option::Some(value) = &self.#ident {
+                    out.write_arg(#name.as_bytes());
+                    ::cache_ru::__private::redis::ToRedisArgs::write_redis_args(value, out);
+                }
+            }
+        } else {
+            quote! {
+                out.write_arg(#name.as_bytes());
+                ::cache_ru::__private::redis::ToRedisArgs::write_redis_args(&self.#ident, out);
+            }
+        }
+    });
+
+    let slots = fields.iter().filter(|f| !f.skip).map(|f| {
+        let (slot, ty) = (f.slot(), &f.ty);
+        quote! { let mut #slot: ::std::option::Option<#ty> = ::std::option::Option::None; }
+    });
+
+    let arms = fields.iter().filter(|f| !f.skip).map(|f| {
+        let (slot, name) = (f.slot(), &f.name);
+        quote! {
+            #name => #slot = ::std::option::Option::Some(
+                ::cache_ru::__private::redis::FromRedisValue::from_redis_value(value)?,
+            ),
+        }
+    });
+
+    let inits = fields.iter().map(|f| {
+        let (ident, slot, name) = (&f.ident, f.slot(), &f.name);
+        if f.skip {
+            quote! { #ident: ::std::default::Default::default(), }
+        } else if f.optional {
+            quote! { #ident: #slot.flatten(), }
+        } else {
+            quote! {
+                #ident: #slot.ok_or_else(|| {
+                    ::cache_ru::__private::redis::RedisError::from((
+                        ::cache_ru::__private::redis::ErrorKind::TypeError,
+                        "Missing hash field",
+                        ::std::format!("{}.{}", #struct_name, #name),
+                    ))
+                })?,
+            }
+        }
+    });
+
+    Ok(quote! {
+        impl #impl_generics ::cache_ru::__private::redis::ToRedisArgs for #ident #ty_generics #where_clause {
+            fn write_redis_args<W>(&self, out: &mut W)
+            where
+                W: ?Sized + ::cache_ru::__private::redis::RedisWrite,
+            {
+                #(#writes)*
+            }
+
+            fn is_single_arg(&self) -> bool {
+                false
+            }
+        }
+
+        impl #impl_generics ::cache_ru::__private::redis::FromRedisValue for #ident #ty_generics #where_clause {
+            fn from_redis_value(v: &::cache_ru::__private::redis::Value) -> ::cache_ru::__private::redis::RedisResult<Self> {
+                let items = v.as_map_iter().ok_or_else(|| {
+                    ::cache_ru::__private::redis::RedisError::from((
+                        ::cache_ru::__private::redis::ErrorKind::TypeError,
+                        "Response was of incompatible type",
+                        ::std::format!("Expected a hash for {} (response was {:?})", #struct_name, v),
+                    ))
+                })?;
+                #(#slots)*
+                for (key, value) in items {
+                    let key: ::std::string::String = ::cache_ru::__private::redis::FromRedisValue::from_redis_value(key)?;
+                    match key.as_str() {
+                        #(#arms)*
+                        _ => {}
+                    }
+                }
+                ::std::result::Result::Ok(#ident {
+                    #(#inits)*
+                })
+            }
+        }
+    })
+}
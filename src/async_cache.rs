@@ -1,34 +1,351 @@
-use redis::{aio::MultiplexedConnection, Client, FromRedisValue, RedisResult, ToRedisArgs};
-
-use crate::RedisConfig;
+use std::{future::Future, sync::Arc, time::Duration};
+
+use redis::{
+    aio::{Connection, ConnectionLike, MultiplexedConnection},
+    streams::{
+        StreamMaxlen, StreamPendingCountReply, StreamPendingReply, StreamRangeReply,
+        StreamReadOptions, StreamReadReply,
+    },
+    Direction, FromRedisValue, RedisError, RedisResult, ToRedisArgs, Value,
+};
+
+use crate::{
+    config::Connector,
+    pipeline::{transaction_conflict, TRANSACTION_ATTEMPTS},
+    single_flight::SingleFlight,
+    stream::{self, StreamAutoClaimReply},
+    IAsyncCache, Pipeline, RedisConfig, RetryPolicy, SetOptions, SetReply, Subscriber,
+};
+
+/// A connection whose `WATCH` and `MULTI` state belongs to a single caller,
+/// as `AsyncCache::transaction` requires. A `MultiplexedConnection` is shared
+/// by all of its clones, so it is not one; use `AsyncCache::dedicated`.
+pub trait Dedicated {}
+
+impl Dedicated for redis::aio::Connection {}
 
 #[derive(Clone)]
-pub struct AsyncCache {
-    con: MultiplexedConnection,
+pub struct AsyncCache<C = MultiplexedConnection> {
+    con: C,
+    flights: SingleFlight,
 }
 
 impl AsyncCache {
     pub async fn new(config: RedisConfig<'_>) -> RedisResult<Self> {
-        let client = Client::open(config)?;
-        let con = client.get_multiplexed_tokio_connection().await?;
-        let ac = AsyncCache { con };
+        let con = Connector::new(config)?.connect_async().await?;
+        let ac = AsyncCache::from_connection(con);
         Ok(ac)
     }
+
+    /// Connects a cache on a connection of its own, for `transaction`.
+    pub async fn dedicated(config: RedisConfig<'_>) -> RedisResult<AsyncCache<Connection>> {
+        let con = Connector::new(config)?.connect_dedicated_async().await?;
+        Ok(AsyncCache::from_connection(con))
+    }
+
+    /// Opens a `Subscriber` on its own connection, which reconnects and
+    /// subscribes again following the default `RetryPolicy`.
+    pub fn subscriber<T>(config: RedisConfig<'_>) -> RedisResult<Subscriber<T>> {
+        Subscriber::new(config, RetryPolicy::default())
+    }
 }
 
-impl AsyncCache {
-    pub async fn get<K, V>(&mut self, key: K) -> RedisResult<V>
+impl<C: ConnectionLike + Send> AsyncCache<C> {
+    pub fn from_connection(con: C) -> Self {
+        AsyncCache {
+            con,
+            flights: SingleFlight::new(),
+        }
+    }
+
+    pub fn pipeline(&mut self) -> Pipeline<'_, Self> {
+        Pipeline::new(self)
+    }
+
+    /// Runs the operations queued by `build` as one `MULTI`/`EXEC`
+    /// transaction and returns its replies, retrying while any of `keys`
+    /// changes in between; see `Cache::transaction`.
+    pub async fn transaction<K, T, F>(&mut self, keys: &[K], mut build: F) -> RedisResult<T>
     where
-        K: ToRedisArgs,
+        C: Dedicated,
+        K: ToRedisArgs + Sync,
+        T: FromRedisValue,
+        F: AsyncFnMut(&mut Pipeline<'_, Self>) -> RedisResult<()>,
+    {
+        for _ in 0..TRANSACTION_ATTEMPTS {
+            if !keys.is_empty() {
+                redis::cmd("WATCH")
+                    .arg(keys)
+                    .query_async::<_, ()>(&mut self.con)
+                    .await?;
+            }
+            let mut pipe = self.pipeline();
+            pipe.atomic();
+            if let Err(err) = build(&mut pipe).await {
+                redis::cmd("UNWATCH")
+                    .query_async::<_, ()>(&mut self.con)
+                    .await?;
+                return Err(err);
+            }
+            if let Some(reply) = pipe.query().await? {
+                return Ok(reply);
+            }
+        }
+        Err(transaction_conflict())
+    }
+
+    /// Like `get_or_set_with`, but concurrent calls for the same key through
+    /// this cache or its clones share a single read-load-write cycle.
+    ///
+    /// Every caller receives the outcome of that cycle, which is why the
+    /// error is handed out behind an `Arc`.
+    pub async fn get_or_set_coalesced<K, V, E, F, Fut>(
+        &mut self,
+        key: K,
+        ttl: Duration,
+        loader: F,
+    ) -> Result<V, Arc<E>>
+    where
+        K: ToRedisArgs + Send + Sync,
+        V: FromRedisValue + ToRedisArgs + Clone + Send + Sync + 'static,
+        E: From<RedisError> + Send + Sync + 'static,
+        F: FnOnce() -> Fut + Send,
+        Fut: Future<Output = Result<V, E>> + Send,
+    {
+        let flights = self.flights.clone();
+        flights
+            .work(key.to_redis_args(), || async {
+                self.get_or_set_with(&key, ttl, loader)
+                    .await
+                    .map_err(Arc::new)
+            })
+            .await
+    }
+
+    /// Collects every key matching the glob `pattern`, iterating with `SCAN`.
+    pub async fn scan_match<P, V>(&mut self, pattern: P) -> RedisResult<Vec<V>>
+    where
+        P: ToRedisArgs,
         V: FromRedisValue,
     {
-        redis::cmd("GET").arg(key).query_async(&mut self.con).await
+        let mut keys = Vec::new();
+        let mut cursor = 0u64;
+        loop {
+            let (next, batch): (u64, Vec<V>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .query_async(&mut self.con)
+                .await?;
+            keys.extend(batch);
+            if next == 0 {
+                return Ok(keys);
+            }
+            cursor = next;
+        }
     }
 
-    pub async fn set<K, V>(&mut self, key: K, value: V) -> RedisResult<()>
+    /// Publishes `message` on `channel`, returning how many subscribers
+    /// received it.
+    pub async fn publish<Ch, M>(&mut self, channel: Ch, message: M) -> RedisResult<usize>
+    where
+        Ch: ToRedisArgs,
+        M: ToRedisArgs,
+    {
+        redis::cmd("PUBLISH")
+            .arg(channel)
+            .arg(message)
+            .query_async(&mut self.con)
+            .await
+    }
+
+    /// Appends an entry to a stream and returns its id; pass `"*"` as `id`
+    /// to have the server generate one.
+    pub async fn xadd<K, ID, F, V>(
+        &mut self,
+        key: K,
+        id: ID,
+        items: &[(F, V)],
+    ) -> RedisResult<String>
     where
         K: ToRedisArgs,
+        ID: ToRedisArgs,
+        F: ToRedisArgs,
         V: ToRedisArgs,
+    {
+        stream::xadd(key, id, items)
+            .query_async(&mut self.con)
+            .await
+    }
+
+    /// Reads the entries between `start` and `end`, inclusive; `-` and `+`
+    /// stand for the first and last entry.
+    pub async fn xrange<K, S, E>(
+        &mut self,
+        key: K,
+        start: S,
+        end: E,
+        count: Option<usize>,
+    ) -> RedisResult<StreamRangeReply>
+    where
+        K: ToRedisArgs,
+        S: ToRedisArgs,
+        E: ToRedisArgs,
+    {
+        stream::xrange(key, start, end, count)
+            .query_async(&mut self.con)
+            .await
+    }
+
+    /// Reads the entries after each of `ids` from the matching stream in
+    /// `keys`, or `None` if a blocking read timed out.
+    pub async fn xread<K, ID>(
+        &mut self,
+        keys: &[K],
+        ids: &[ID],
+        options: StreamReadOptions,
+    ) -> RedisResult<Option<StreamReadReply>>
+    where
+        K: ToRedisArgs,
+        ID: ToRedisArgs,
+    {
+        stream::xread(keys, ids, &options)
+            .query_async(&mut self.con)
+            .await
+    }
+
+    /// Like `xread` as `consumer` of `group`; an id of `>` reads entries never
+    /// delivered to the group, any other id this consumer's pending entries.
+    pub async fn xreadgroup<G, Cn, K, ID>(
+        &mut self,
+        group: G,
+        consumer: Cn,
+        keys: &[K],
+        ids: &[ID],
+        options: StreamReadOptions,
+    ) -> RedisResult<Option<StreamReadReply>>
+    where
+        G: ToRedisArgs,
+        Cn: ToRedisArgs,
+        K: ToRedisArgs,
+        ID: ToRedisArgs,
+    {
+        stream::xread(keys, ids, &options.group(group, consumer))
+            .query_async(&mut self.con)
+            .await
+    }
+
+    /// Removes entries from the pending list of `group`, returning how many
+    /// were pending.
+    pub async fn xack<K, G, I>(&mut self, key: K, group: G, ids: &[I]) -> RedisResult<usize>
+    where
+        K: ToRedisArgs,
+        G: ToRedisArgs,
+        I: ToRedisArgs,
+    {
+        stream::xack(key, group, ids)
+            .query_async(&mut self.con)
+            .await
+    }
+
+    /// Summarizes the entries delivered to `group` but not acknowledged yet.
+    pub async fn xpending<K, G>(&mut self, key: K, group: G) -> RedisResult<StreamPendingReply>
+    where
+        K: ToRedisArgs,
+        G: ToRedisArgs,
+    {
+        stream::xpending(key, group)
+            .query_async(&mut self.con)
+            .await
+    }
+
+    /// Lists up to `count` pending entries of `group` between `start` and
+    /// `end`.
+    pub async fn xpending_count<K, G, S, E>(
+        &mut self,
+        key: K,
+        group: G,
+        start: S,
+        end: E,
+        count: usize,
+    ) -> RedisResult<StreamPendingCountReply>
+    where
+        K: ToRedisArgs,
+        G: ToRedisArgs,
+        S: ToRedisArgs,
+        E: ToRedisArgs,
+    {
+        stream::xpending_count(key, group, start, end, count)
+            .query_async(&mut self.con)
+            .await
+    }
+
+    /// Hands up to `count` entries of `group` that have been pending for at
+    /// least `min_idle` over to `consumer`, scanning from `start`.
+    pub async fn xautoclaim<K, G, Cn, S>(
+        &mut self,
+        key: K,
+        group: G,
+        consumer: Cn,
+        min_idle: Duration,
+        start: S,
+        count: usize,
+    ) -> RedisResult<StreamAutoClaimReply>
+    where
+        K: ToRedisArgs,
+        G: ToRedisArgs,
+        Cn: ToRedisArgs,
+        S: ToRedisArgs,
+    {
+        stream::xautoclaim(key, group, consumer, min_idle, start, count)
+            .query_async(&mut self.con)
+            .await
+    }
+
+    /// Drops the oldest entries beyond `maxlen`, returning how many were
+    /// removed.
+    pub async fn xtrim<K: ToRedisArgs>(
+        &mut self,
+        key: K,
+        maxlen: StreamMaxlen,
+    ) -> RedisResult<usize> {
+        stream::xtrim(key, maxlen).query_async(&mut self.con).await
+    }
+
+    /// Creates a consumer group that is delivered the entries after `id`, or
+    /// `$` for new entries only, creating the stream if needed.
+    pub async fn xgroup_create<K, G, ID>(&mut self, key: K, group: G, id: ID) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        G: ToRedisArgs,
+        ID: ToRedisArgs,
+    {
+        stream::xgroup_create(key, group, id)
+            .query_async(&mut self.con)
+            .await
+    }
+}
+
+impl<C: ConnectionLike + Send> Pipeline<'_, AsyncCache<C>> {
+    /// Sends the queued operations and reads their replies.
+    pub async fn query<T: FromRedisValue>(&mut self) -> RedisResult<T> {
+        self.pipe.query_async(&mut self.cache.con).await
+    }
+}
+
+impl<C: ConnectionLike + Send> IAsyncCache for AsyncCache<C> {
+    async fn get<K, V>(&mut self, key: K) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        redis::cmd("GET").arg(key).query_async(&mut self.con).await
+    }
+
+    async fn set<K, V>(&mut self, key: K, value: V) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Send,
     {
         redis::cmd("SET")
             .arg(key)
@@ -37,23 +354,43 @@ impl AsyncCache {
             .await
     }
 
-    pub async fn del<K>(&mut self, key: K) -> RedisResult<()>
+    async fn set_with<K, V, R>(
+        &mut self,
+        key: K,
+        value: V,
+        options: SetOptions,
+    ) -> RedisResult<SetReply<R>>
     where
-        K: ToRedisArgs,
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Send,
+        R: FromRedisValue,
+    {
+        let reply: Value = redis::cmd("SET")
+            .arg(key)
+            .arg(value)
+            .arg(&options)
+            .query_async(&mut self.con)
+            .await?;
+        SetReply::from_reply(&reply, &options)
+    }
+
+    async fn del<K>(&mut self, key: K) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
     {
         redis::cmd("DEL").arg(key).query_async(&mut self.con).await
     }
 
-    pub async fn incr<K>(&mut self, key: K) -> RedisResult<i32>
+    async fn incr<K>(&mut self, key: K) -> RedisResult<i32>
     where
-        K: ToRedisArgs,
+        K: ToRedisArgs + Send,
     {
         redis::cmd("INCR").arg(key).query_async(&mut self.con).await
     }
 
-    pub async fn exists<K>(&mut self, key: K) -> RedisResult<bool>
+    async fn exists<K>(&mut self, key: K) -> RedisResult<bool>
     where
-        K: ToRedisArgs,
+        K: ToRedisArgs + Send,
     {
         redis::cmd("EXISTS")
             .arg(key)
@@ -61,9 +398,9 @@ impl AsyncCache {
             .await
     }
 
-    pub async fn expire<K>(&mut self, key: K, sec: i32) -> RedisResult<()>
+    async fn expire<K>(&mut self, key: K, sec: i32) -> RedisResult<()>
     where
-        K: ToRedisArgs,
+        K: ToRedisArgs + Send,
     {
         redis::cmd("EXPIRE")
             .arg(key)
@@ -72,10 +409,10 @@ impl AsyncCache {
             .await
     }
 
-    pub async fn sadd<K, V>(&mut self, key: K, value: &[V]) -> RedisResult<()>
+    async fn sadd<K, V>(&mut self, key: K, value: &[V]) -> RedisResult<()>
     where
-        K: ToRedisArgs,
-        V: ToRedisArgs,
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Sync,
     {
         redis::cmd("SADD")
             .arg(key)
@@ -84,9 +421,9 @@ impl AsyncCache {
             .await
     }
 
-    pub async fn smembers<K, V>(&mut self, key: K) -> RedisResult<Vec<V>>
+    async fn smembers<K, V>(&mut self, key: K) -> RedisResult<Vec<V>>
     where
-        K: ToRedisArgs,
+        K: ToRedisArgs + Send,
         V: FromRedisValue,
     {
         redis::cmd("SMEMBERS")
@@ -95,10 +432,10 @@ impl AsyncCache {
             .await
     }
 
-    pub async fn srem<K, V>(&mut self, key: K, values: &[V]) -> RedisResult<()>
+    async fn srem<K, V>(&mut self, key: K, values: &[V]) -> RedisResult<()>
     where
-        K: ToRedisArgs,
-        V: ToRedisArgs,
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Sync,
     {
         redis::cmd("SREM")
             .arg(key)
@@ -107,9 +444,9 @@ impl AsyncCache {
             .await
     }
 
-    pub async fn scard<K>(&mut self, key: K) -> RedisResult<usize>
+    async fn scard<K>(&mut self, key: K) -> RedisResult<usize>
     where
-        K: ToRedisArgs,
+        K: ToRedisArgs + Send,
     {
         redis::cmd("SCARD")
             .arg(key)
@@ -117,10 +454,10 @@ impl AsyncCache {
             .await
     }
 
-    pub async fn sismember<K, V>(&mut self, key: K, value: V) -> RedisResult<bool>
+    async fn sismember<K, V>(&mut self, key: K, value: V) -> RedisResult<bool>
     where
-        K: ToRedisArgs,
-        V: ToRedisArgs,
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Send,
     {
         redis::cmd("SISMEMBER")
             .arg(key)
@@ -129,11 +466,11 @@ impl AsyncCache {
             .await
     }
 
-    pub async fn hset<K, F, V>(&mut self, key: K, field: F, value: V) -> RedisResult<()>
+    async fn hset<K, F, V>(&mut self, key: K, field: F, value: V) -> RedisResult<()>
     where
-        K: ToRedisArgs,
-        F: ToRedisArgs,
-        V: ToRedisArgs,
+        K: ToRedisArgs + Send,
+        F: ToRedisArgs + Send,
+        V: ToRedisArgs + Send,
     {
         redis::cmd("HSET")
             .arg(key)
@@ -143,10 +480,10 @@ impl AsyncCache {
             .await
     }
 
-    pub async fn hget<K, F, V>(&mut self, key: K, field: F) -> RedisResult<V>
+    async fn hget<K, F, V>(&mut self, key: K, field: F) -> RedisResult<V>
     where
-        K: ToRedisArgs,
-        F: ToRedisArgs,
+        K: ToRedisArgs + Send,
+        F: ToRedisArgs + Send,
         V: FromRedisValue,
     {
         redis::cmd("HGET")
@@ -156,11 +493,11 @@ impl AsyncCache {
             .await
     }
 
-    pub async fn hmset<K, F, V>(&mut self, key: K, values: &[(F, V)]) -> RedisResult<()>
+    async fn hmset<K, F, V>(&mut self, key: K, values: &[(F, V)]) -> RedisResult<()>
     where
-        K: ToRedisArgs,
-        F: ToRedisArgs,
-        V: ToRedisArgs,
+        K: ToRedisArgs + Send,
+        F: ToRedisArgs + Sync,
+        V: ToRedisArgs + Sync,
     {
         redis::cmd("HMSET")
             .arg(key)
@@ -169,10 +506,10 @@ impl AsyncCache {
             .await
     }
 
-    pub async fn hmget<K, F, V>(&mut self, key: K, fields: &[F]) -> RedisResult<V>
+    async fn hmget<K, F, V>(&mut self, key: K, fields: &[F]) -> RedisResult<V>
     where
-        K: ToRedisArgs,
-        F: ToRedisArgs,
+        K: ToRedisArgs + Send,
+        F: ToRedisArgs + Sync,
         V: FromRedisValue,
     {
         redis::cmd("HMGET")
@@ -182,10 +519,10 @@ impl AsyncCache {
             .await
     }
 
-    pub async fn hsetall<K, V>(&mut self, key: K, value: V) -> RedisResult<()>
+    async fn hsetall<K, V>(&mut self, key: K, value: V) -> RedisResult<()>
     where
-        K: ToRedisArgs,
-        V: ToRedisArgs,
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Send,
     {
         redis::cmd("HMSET")
             .arg(key)
@@ -194,9 +531,9 @@ impl AsyncCache {
             .await
     }
 
-    pub async fn hgetall<K, V>(&mut self, key: K) -> RedisResult<V>
+    async fn hgetall<K, V>(&mut self, key: K) -> RedisResult<V>
     where
-        K: ToRedisArgs,
+        K: ToRedisArgs + Send,
         V: FromRedisValue,
     {
         redis::cmd("HGETALL")
@@ -205,10 +542,10 @@ impl AsyncCache {
             .await
     }
 
-    pub async fn hexists<K, F>(&mut self, key: K, field: F) -> RedisResult<bool>
+    async fn hexists<K, F>(&mut self, key: K, field: F) -> RedisResult<bool>
     where
-        K: ToRedisArgs,
-        F: ToRedisArgs,
+        K: ToRedisArgs + Send,
+        F: ToRedisArgs + Send,
     {
         redis::cmd("HEXISTS")
             .arg(key)
@@ -217,10 +554,10 @@ impl AsyncCache {
             .await
     }
 
-    pub async fn hdel<K, F>(&mut self, key: K, fields: &[F]) -> RedisResult<()>
+    async fn hdel<K, F>(&mut self, key: K, fields: &[F]) -> RedisResult<()>
     where
-        K: ToRedisArgs,
-        F: ToRedisArgs,
+        K: ToRedisArgs + Send,
+        F: ToRedisArgs + Sync,
     {
         redis::cmd("HDEL")
             .arg(key)
@@ -229,11 +566,11 @@ impl AsyncCache {
             .await
     }
 
-    pub async fn zadd<K, S, M>(&mut self, key: K, items: &[(S, M)]) -> RedisResult<()>
+    async fn zadd<K, S, M>(&mut self, key: K, items: &[(S, M)]) -> RedisResult<()>
     where
-        K: ToRedisArgs,
-        S: ToRedisArgs,
-        M: ToRedisArgs,
+        K: ToRedisArgs + Send,
+        S: ToRedisArgs + Sync,
+        M: ToRedisArgs + Sync,
     {
         redis::cmd("ZADD")
             .arg(key)
@@ -242,10 +579,192 @@ impl AsyncCache {
             .await
     }
 
-    pub async fn zrange_by_score<K, M, V>(&mut self, key: K, min: M, max: M) -> RedisResult<Vec<V>>
+    async fn lpush<K, V>(&mut self, key: K, values: &[V]) -> RedisResult<()>
     where
-        K: ToRedisArgs,
-        M: ToRedisArgs,
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Sync,
+    {
+        redis::cmd("LPUSH")
+            .arg(key)
+            .arg(values)
+            .query_async(&mut self.con)
+            .await
+    }
+
+    async fn rpush<K, V>(&mut self, key: K, values: &[V]) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Sync,
+    {
+        redis::cmd("RPUSH")
+            .arg(key)
+            .arg(values)
+            .query_async(&mut self.con)
+            .await
+    }
+
+    async fn lpop<K, V>(&mut self, key: K) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        redis::cmd("LPOP").arg(key).query_async(&mut self.con).await
+    }
+
+    async fn rpop<K, V>(&mut self, key: K) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        redis::cmd("RPOP").arg(key).query_async(&mut self.con).await
+    }
+
+    async fn lrange<K, V>(&mut self, key: K, start: isize, stop: isize) -> RedisResult<Vec<V>>
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        redis::cmd("LRANGE")
+            .arg(key)
+            .arg(start)
+            .arg(stop)
+            .query_async(&mut self.con)
+            .await
+    }
+
+    async fn ltrim<K>(&mut self, key: K, start: isize, stop: isize) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+    {
+        redis::cmd("LTRIM")
+            .arg(key)
+            .arg(start)
+            .arg(stop)
+            .query_async(&mut self.con)
+            .await
+    }
+
+    async fn lindex<K, V>(&mut self, key: K, index: isize) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        redis::cmd("LINDEX")
+            .arg(key)
+            .arg(index)
+            .query_async(&mut self.con)
+            .await
+    }
+
+    async fn linsert_before<K, P, V>(&mut self, key: K, pivot: P, value: V) -> RedisResult<isize>
+    where
+        K: ToRedisArgs + Send,
+        P: ToRedisArgs + Send,
+        V: ToRedisArgs + Send,
+    {
+        redis::cmd("LINSERT")
+            .arg(key)
+            .arg("BEFORE")
+            .arg(pivot)
+            .arg(value)
+            .query_async(&mut self.con)
+            .await
+    }
+
+    async fn linsert_after<K, P, V>(&mut self, key: K, pivot: P, value: V) -> RedisResult<isize>
+    where
+        K: ToRedisArgs + Send,
+        P: ToRedisArgs + Send,
+        V: ToRedisArgs + Send,
+    {
+        redis::cmd("LINSERT")
+            .arg(key)
+            .arg("AFTER")
+            .arg(pivot)
+            .arg(value)
+            .query_async(&mut self.con)
+            .await
+    }
+
+    async fn llen<K>(&mut self, key: K) -> RedisResult<usize>
+    where
+        K: ToRedisArgs + Send,
+    {
+        redis::cmd("LLEN").arg(key).query_async(&mut self.con).await
+    }
+
+    async fn blpop<K, V>(&mut self, key: K, timeout: Duration) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        redis::cmd("BLPOP")
+            .arg(key)
+            .arg(timeout.as_secs_f64())
+            .query_async(&mut self.con)
+            .await
+    }
+
+    async fn brpop<K, V>(&mut self, key: K, timeout: Duration) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        redis::cmd("BRPOP")
+            .arg(key)
+            .arg(timeout.as_secs_f64())
+            .query_async(&mut self.con)
+            .await
+    }
+
+    async fn lmove<K, D, V>(
+        &mut self,
+        key: K,
+        destination: D,
+        from: Direction,
+        to: Direction,
+    ) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        D: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        redis::cmd("LMOVE")
+            .arg(key)
+            .arg(destination)
+            .arg(from)
+            .arg(to)
+            .query_async(&mut self.con)
+            .await
+    }
+
+    async fn blmove<K, D, V>(
+        &mut self,
+        key: K,
+        destination: D,
+        from: Direction,
+        to: Direction,
+        timeout: Duration,
+    ) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        D: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        redis::cmd("BLMOVE")
+            .arg(key)
+            .arg(destination)
+            .arg(from)
+            .arg(to)
+            .arg(timeout.as_secs_f64())
+            .query_async(&mut self.con)
+            .await
+    }
+
+    async fn zrange_by_score<K, M, V>(&mut self, key: K, min: M, max: M) -> RedisResult<Vec<V>>
+    where
+        K: ToRedisArgs + Send,
+        M: ToRedisArgs + Send,
         V: FromRedisValue,
     {
         redis::cmd("ZRANGEBYSCORE")
@@ -256,15 +775,10 @@ impl AsyncCache {
             .await
     }
 
-    pub async fn zrevrange_by_score<K, M, V>(
-        &mut self,
-        key: K,
-        max: M,
-        min: M,
-    ) -> RedisResult<Vec<V>>
+    async fn zrevrange_by_score<K, M, V>(&mut self, key: K, max: M, min: M) -> RedisResult<Vec<V>>
     where
-        K: ToRedisArgs,
-        M: ToRedisArgs,
+        K: ToRedisArgs + Send,
+        M: ToRedisArgs + Send,
         V: FromRedisValue,
     {
         redis::cmd("ZREVRANGEBYSCORE")
@@ -275,10 +789,10 @@ impl AsyncCache {
             .await
     }
 
-    pub async fn zrem<K, M>(&mut self, key: K, items: &[M]) -> RedisResult<()>
+    async fn zrem<K, M>(&mut self, key: K, items: &[M]) -> RedisResult<()>
     where
-        K: ToRedisArgs,
-        M: ToRedisArgs,
+        K: ToRedisArgs + Send,
+        M: ToRedisArgs + Sync,
     {
         redis::cmd("ZREM")
             .arg(key)
@@ -296,13 +810,11 @@ mod tests_async_cache {
     };
 
     use super::*;
-
-    const ADDR: &str = "192.168.100.5:6379";
-    const DB: u8 = 1;
+    use crate::MemoryConnection;
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_basic() {
-        let mut ca = AsyncCache::new(RedisConfig::new(ADDR, DB)).await.unwrap();
+        let mut ca = AsyncCache::from_connection(MemoryConnection::new());
         ca.set("k1", "v1").await.unwrap();
         if let Ok(v1) = ca.get::<&str, String>("k1").await {
             println!("k1: {v1}");
@@ -322,7 +834,7 @@ mod tests_async_cache {
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_incr() {
-        let mut ca = AsyncCache::new(RedisConfig::new(ADDR, DB)).await.unwrap();
+        let mut ca = AsyncCache::from_connection(MemoryConnection::new());
         let count: i32 = ca.incr("count").await.unwrap();
         println!("count: {count}");
         let count: i32 = ca.incr("count").await.unwrap();
@@ -349,7 +861,7 @@ mod tests_async_cache {
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_set() {
-        let mut ca = AsyncCache::new(RedisConfig::new(ADDR, DB)).await.unwrap();
+        let mut ca = AsyncCache::from_connection(MemoryConnection::new());
         ca.sadd("my_set", &["abc", "def"]).await.unwrap();
         ca.sadd("my_set", &["abc", "ghi"]).await.unwrap();
 
@@ -367,7 +879,7 @@ mod tests_async_cache {
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_hash() {
-        let mut ca = AsyncCache::new(RedisConfig::new(ADDR, DB)).await.unwrap();
+        let mut ca = AsyncCache::from_connection(MemoryConnection::new());
         ca.hset("my_hash", "f1", "v1").await.unwrap();
         let v1: String = ca.hget("my_hash", "f1").await.unwrap();
         println!("v1: {v1}");
@@ -387,7 +899,7 @@ mod tests_async_cache {
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_sorted() {
-        let mut ca = AsyncCache::new(RedisConfig::new(ADDR, DB)).await.unwrap();
+        let mut ca = AsyncCache::from_connection(MemoryConnection::new());
         ca.zadd("my_sorted", &[(123, "abc"), (456, "def")])
             .await
             .unwrap();
@@ -1,21 +1,258 @@
-use redis::{Client, Connection, FromRedisValue, RedisResult, ToRedisArgs};
-
-use crate::{config::RedisConfig, ICache};
-
-pub struct Cache {
-    con: Connection,
+use std::time::Duration;
+
+use redis::{
+    streams::{
+        StreamMaxlen, StreamPendingCountReply, StreamPendingReply, StreamRangeReply,
+        StreamReadOptions, StreamReadReply,
+    },
+    Connection, ConnectionLike, Direction, FromRedisValue, RedisResult, ToRedisArgs, Value,
+};
+
+use crate::{
+    config::{Connector, RedisConfig},
+    pipeline::{transaction_conflict, TRANSACTION_ATTEMPTS},
+    stream::{self, StreamAutoClaimReply},
+    ICache, Pipeline, SetOptions, SetReply,
+};
+
+pub struct Cache<C = Connection> {
+    con: C,
 }
 
 impl Cache {
     pub fn new(config: RedisConfig) -> RedisResult<Self> {
-        let client = Client::open(config)?;
-        let con = client.get_connection()?;
+        let con = Connector::new(config)?.connect()?;
         let ca = Cache { con };
         Ok(ca)
     }
 }
 
-impl ICache for Cache {
+impl<C: ConnectionLike> Cache<C> {
+    pub fn from_connection(con: C) -> Self {
+        Cache { con }
+    }
+
+    pub fn into_connection(self) -> C {
+        self.con
+    }
+
+    pub fn pipeline(&mut self) -> Pipeline<'_, Self> {
+        Pipeline::new(self)
+    }
+
+    /// Runs the operations queued by `build` as one `MULTI`/`EXEC`
+    /// transaction and returns its replies.
+    ///
+    /// `keys` are watched before `build` is called, so values it reads
+    /// through `Pipeline::cache` stay valid: if another client changes any
+    /// of them before `EXEC`, the transaction is dropped and `build` runs
+    /// again, up to 16 times before an `ExecAbortError` is returned.
+    pub fn transaction<K, T, F>(&mut self, keys: &[K], mut build: F) -> RedisResult<T>
+    where
+        K: ToRedisArgs,
+        T: FromRedisValue,
+        F: FnMut(&mut Pipeline<'_, Self>) -> RedisResult<()>,
+    {
+        for _ in 0..TRANSACTION_ATTEMPTS {
+            if !keys.is_empty() {
+                redis::cmd("WATCH").arg(keys).query::<()>(&mut self.con)?;
+            }
+            let mut pipe = self.pipeline();
+            pipe.atomic();
+            if let Err(err) = build(&mut pipe) {
+                redis::cmd("UNWATCH").query::<()>(&mut self.con)?;
+                return Err(err);
+            }
+            if let Some(reply) = pipe.query()? {
+                return Ok(reply);
+            }
+        }
+        Err(transaction_conflict())
+    }
+
+    /// Collects every key matching the glob `pattern`, iterating with `SCAN`.
+    pub fn scan_match<P, V>(&mut self, pattern: P) -> RedisResult<Vec<V>>
+    where
+        P: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        let mut keys = Vec::new();
+        let mut cursor = 0u64;
+        loop {
+            let (next, batch): (u64, Vec<V>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .query(&mut self.con)?;
+            keys.extend(batch);
+            if next == 0 {
+                return Ok(keys);
+            }
+            cursor = next;
+        }
+    }
+
+    /// Publishes `message` on `channel`, returning how many subscribers
+    /// received it.
+    pub fn publish<Ch, M>(&mut self, channel: Ch, message: M) -> RedisResult<usize>
+    where
+        Ch: ToRedisArgs,
+        M: ToRedisArgs,
+    {
+        redis::cmd("PUBLISH")
+            .arg(channel)
+            .arg(message)
+            .query(&mut self.con)
+    }
+
+    /// Appends an entry to a stream and returns its id; pass `"*"` as `id`
+    /// to have the server generate one.
+    pub fn xadd<K, ID, F, V>(&mut self, key: K, id: ID, items: &[(F, V)]) -> RedisResult<String>
+    where
+        K: ToRedisArgs,
+        ID: ToRedisArgs,
+        F: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        stream::xadd(key, id, items).query(&mut self.con)
+    }
+
+    /// Reads the entries between `start` and `end`, inclusive; `-` and `+`
+    /// stand for the first and last entry.
+    pub fn xrange<K, S, E>(
+        &mut self,
+        key: K,
+        start: S,
+        end: E,
+        count: Option<usize>,
+    ) -> RedisResult<StreamRangeReply>
+    where
+        K: ToRedisArgs,
+        S: ToRedisArgs,
+        E: ToRedisArgs,
+    {
+        stream::xrange(key, start, end, count).query(&mut self.con)
+    }
+
+    /// Reads the entries after each of `ids` from the matching stream in
+    /// `keys`, or `None` if a blocking read timed out.
+    pub fn xread<K, ID>(
+        &mut self,
+        keys: &[K],
+        ids: &[ID],
+        options: StreamReadOptions,
+    ) -> RedisResult<Option<StreamReadReply>>
+    where
+        K: ToRedisArgs,
+        ID: ToRedisArgs,
+    {
+        stream::xread(keys, ids, &options).query(&mut self.con)
+    }
+
+    /// Like `xread` as `consumer` of `group`; an id of `>` reads entries never
+    /// delivered to the group, any other id this consumer's pending entries.
+    pub fn xreadgroup<G, Cn, K, ID>(
+        &mut self,
+        group: G,
+        consumer: Cn,
+        keys: &[K],
+        ids: &[ID],
+        options: StreamReadOptions,
+    ) -> RedisResult<Option<StreamReadReply>>
+    where
+        G: ToRedisArgs,
+        Cn: ToRedisArgs,
+        K: ToRedisArgs,
+        ID: ToRedisArgs,
+    {
+        stream::xread(keys, ids, &options.group(group, consumer)).query(&mut self.con)
+    }
+
+    /// Removes entries from the pending list of `group`, returning how many
+    /// were pending.
+    pub fn xack<K, G, I>(&mut self, key: K, group: G, ids: &[I]) -> RedisResult<usize>
+    where
+        K: ToRedisArgs,
+        G: ToRedisArgs,
+        I: ToRedisArgs,
+    {
+        stream::xack(key, group, ids).query(&mut self.con)
+    }
+
+    /// Summarizes the entries delivered to `group` but not acknowledged yet.
+    pub fn xpending<K, G>(&mut self, key: K, group: G) -> RedisResult<StreamPendingReply>
+    where
+        K: ToRedisArgs,
+        G: ToRedisArgs,
+    {
+        stream::xpending(key, group).query(&mut self.con)
+    }
+
+    /// Lists up to `count` pending entries of `group` between `start` and
+    /// `end`.
+    pub fn xpending_count<K, G, S, E>(
+        &mut self,
+        key: K,
+        group: G,
+        start: S,
+        end: E,
+        count: usize,
+    ) -> RedisResult<StreamPendingCountReply>
+    where
+        K: ToRedisArgs,
+        G: ToRedisArgs,
+        S: ToRedisArgs,
+        E: ToRedisArgs,
+    {
+        stream::xpending_count(key, group, start, end, count).query(&mut self.con)
+    }
+
+    /// Hands up to `count` entries of `group` that have been pending for at
+    /// least `min_idle` over to `consumer`, scanning from `start`.
+    pub fn xautoclaim<K, G, Cn, S>(
+        &mut self,
+        key: K,
+        group: G,
+        consumer: Cn,
+        min_idle: Duration,
+        start: S,
+        count: usize,
+    ) -> RedisResult<StreamAutoClaimReply>
+    where
+        K: ToRedisArgs,
+        G: ToRedisArgs,
+        Cn: ToRedisArgs,
+        S: ToRedisArgs,
+    {
+        stream::xautoclaim(key, group, consumer, min_idle, start, count).query(&mut self.con)
+    }
+
+    /// Drops the oldest entries beyond `maxlen`, returning how many were
+    /// removed.
+    pub fn xtrim<K: ToRedisArgs>(&mut self, key: K, maxlen: StreamMaxlen) -> RedisResult<usize> {
+        stream::xtrim(key, maxlen).query(&mut self.con)
+    }
+
+    /// Creates a consumer group that is delivered the entries after `id`, or
+    /// `$` for new entries only, creating the stream if needed.
+    pub fn xgroup_create<K, G, ID>(&mut self, key: K, group: G, id: ID) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        G: ToRedisArgs,
+        ID: ToRedisArgs,
+    {
+        stream::xgroup_create(key, group, id).query(&mut self.con)
+    }
+}
+
+impl<C: ConnectionLike> Pipeline<'_, Cache<C>> {
+    /// Sends the queued operations and reads their replies.
+    pub fn query<T: FromRedisValue>(&mut self) -> RedisResult<T> {
+        self.pipe.query(&mut self.cache.con)
+    }
+}
+
+impl<C: ConnectionLike> ICache for Cache<C> {
     fn get<K, V>(&mut self, key: K) -> RedisResult<V>
     where
         K: ToRedisArgs,
@@ -32,6 +269,25 @@ impl ICache for Cache {
         redis::cmd("SET").arg(key).arg(value).query(&mut self.con)
     }
 
+    fn set_with<K, V, R>(
+        &mut self,
+        key: K,
+        value: V,
+        options: SetOptions,
+    ) -> RedisResult<SetReply<R>>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+        R: FromRedisValue,
+    {
+        let reply: Value = redis::cmd("SET")
+            .arg(key)
+            .arg(value)
+            .arg(&options)
+            .query(&mut self.con)?;
+        SetReply::from_reply(&reply, &options)
+    }
+
     fn del<K>(&mut self, key: K) -> RedisResult<()>
     where
         K: ToRedisArgs,
@@ -225,21 +481,190 @@ impl ICache for Cache {
     {
         redis::cmd("ZREM").arg(key).arg(items).query(&mut self.con)
     }
+
+    fn lpush<K, V>(&mut self, key: K, values: &[V]) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        redis::cmd("LPUSH")
+            .arg(key)
+            .arg(values)
+            .query(&mut self.con)
+    }
+
+    fn rpush<K, V>(&mut self, key: K, values: &[V]) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        redis::cmd("RPUSH")
+            .arg(key)
+            .arg(values)
+            .query(&mut self.con)
+    }
+
+    fn lpop<K, V>(&mut self, key: K) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        redis::cmd("LPOP").arg(key).query(&mut self.con)
+    }
+
+    fn rpop<K, V>(&mut self, key: K) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        redis::cmd("RPOP").arg(key).query(&mut self.con)
+    }
+
+    fn lrange<K, V>(&mut self, key: K, start: isize, stop: isize) -> RedisResult<Vec<V>>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        redis::cmd("LRANGE")
+            .arg(key)
+            .arg(start)
+            .arg(stop)
+            .query(&mut self.con)
+    }
+
+    fn ltrim<K>(&mut self, key: K, start: isize, stop: isize) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+    {
+        redis::cmd("LTRIM")
+            .arg(key)
+            .arg(start)
+            .arg(stop)
+            .query(&mut self.con)
+    }
+
+    fn lindex<K, V>(&mut self, key: K, index: isize) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        redis::cmd("LINDEX")
+            .arg(key)
+            .arg(index)
+            .query(&mut self.con)
+    }
+
+    fn linsert_before<K, P, V>(&mut self, key: K, pivot: P, value: V) -> RedisResult<isize>
+    where
+        K: ToRedisArgs,
+        P: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        redis::cmd("LINSERT")
+            .arg(key)
+            .arg("BEFORE")
+            .arg(pivot)
+            .arg(value)
+            .query(&mut self.con)
+    }
+
+    fn linsert_after<K, P, V>(&mut self, key: K, pivot: P, value: V) -> RedisResult<isize>
+    where
+        K: ToRedisArgs,
+        P: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        redis::cmd("LINSERT")
+            .arg(key)
+            .arg("AFTER")
+            .arg(pivot)
+            .arg(value)
+            .query(&mut self.con)
+    }
+
+    fn llen<K>(&mut self, key: K) -> RedisResult<usize>
+    where
+        K: ToRedisArgs,
+    {
+        redis::cmd("LLEN").arg(key).query(&mut self.con)
+    }
+
+    fn blpop<K, V>(&mut self, key: K, timeout: Duration) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        redis::cmd("BLPOP")
+            .arg(key)
+            .arg(timeout.as_secs_f64())
+            .query(&mut self.con)
+    }
+
+    fn brpop<K, V>(&mut self, key: K, timeout: Duration) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        redis::cmd("BRPOP")
+            .arg(key)
+            .arg(timeout.as_secs_f64())
+            .query(&mut self.con)
+    }
+
+    fn lmove<K, D, V>(
+        &mut self,
+        key: K,
+        destination: D,
+        from: Direction,
+        to: Direction,
+    ) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        D: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        redis::cmd("LMOVE")
+            .arg(key)
+            .arg(destination)
+            .arg(from)
+            .arg(to)
+            .query(&mut self.con)
+    }
+
+    fn blmove<K, D, V>(
+        &mut self,
+        key: K,
+        destination: D,
+        from: Direction,
+        to: Direction,
+        timeout: Duration,
+    ) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        D: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        redis::cmd("BLMOVE")
+            .arg(key)
+            .arg(destination)
+            .arg(from)
+            .arg(to)
+            .arg(timeout.as_secs_f64())
+            .query(&mut self.con)
+    }
 }
 
 #[cfg(test)]
 mod tests_cache {
     use super::*;
+    use crate::MemoryConnection;
     use std::collections::{HashMap, HashSet};
     use std::thread;
     use std::time::Duration;
 
-    const ADDR: &str = "192.168.100.5:6379";
-    const DB: u8 = 1;
-
     #[test]
     fn test_basic() {
-        let mut ca = Cache::new(RedisConfig::new(ADDR, DB)).unwrap();
+        let mut ca = Cache::from_connection(MemoryConnection::new());
         ca.set("k1", "v1").unwrap();
         if let Ok(v1) = ca.get::<&str, String>("k1") {
             println!("k1: {v1}");
@@ -259,7 +684,7 @@ mod tests_cache {
 
     #[test]
     fn test_incr() {
-        let mut ca = Cache::new(RedisConfig::new(ADDR, DB)).unwrap();
+        let mut ca = Cache::from_connection(MemoryConnection::new());
         let count: i32 = ca.incr("count").unwrap();
         println!("count: {count}");
         let count: i32 = ca.incr("count").unwrap();
@@ -286,7 +711,7 @@ mod tests_cache {
 
     #[test]
     fn test_set() {
-        let mut ca = Cache::new(RedisConfig::new(ADDR, DB)).unwrap();
+        let mut ca = Cache::from_connection(MemoryConnection::new());
         ca.sadd("my_set", &["abc", "def"]).unwrap();
         ca.sadd("my_set", &["abc", "ghi"]).unwrap();
 
@@ -304,7 +729,7 @@ mod tests_cache {
 
     #[test]
     fn test_hash() {
-        let mut ca = Cache::new(RedisConfig::new(ADDR, DB)).unwrap();
+        let mut ca = Cache::from_connection(MemoryConnection::new());
         ca.hset("my_hash", "f1", "v1").unwrap();
         let v1: String = ca.hget("my_hash", "f1").unwrap();
         println!("v1: {v1}");
@@ -322,7 +747,7 @@ mod tests_cache {
 
     #[test]
     fn test_sorted() {
-        let mut ca = Cache::new(RedisConfig::new(ADDR, DB)).unwrap();
+        let mut ca = Cache::from_connection(MemoryConnection::new());
         ca.zadd("my_sorted", &[(123, "abc"), (456, "def")]).unwrap();
         let list: Vec<String> = ca.zrange_by_score("my_sorted", 0, 1000).unwrap();
         println!("list: {:?}", list);
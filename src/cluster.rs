@@ -0,0 +1,339 @@
+use redis::{
+    aio, cluster, cluster::ClusterClient, cluster_async, Cmd, ConnectionLike, ErrorKind, Pipeline,
+    RedisError, RedisFuture, RedisResult, Value,
+};
+
+use crate::{
+    resp::{command_keys, parse_commands},
+    AsyncCache, Cache,
+};
+
+pub type ClusterCache = Cache<ClusterConnection>;
+pub type AsyncClusterCache = AsyncCache<AsyncClusterConnection>;
+
+/// The nodes of a Redis Cluster to discover the rest of it from.
+pub struct ClusterConfig<'a> {
+    /// Node addresses as `host:port`.
+    pub nodes: Vec<&'a str>,
+    pub username: &'a str,
+    pub password: &'a str,
+    /// Send reads to replicas instead of the primary of each slot.
+    pub read_from_replicas: bool,
+}
+
+impl<'a> ClusterConfig<'a> {
+    pub fn new(nodes: Vec<&'a str>) -> Self {
+        ClusterConfig {
+            nodes,
+            username: "",
+            password: "",
+            read_from_replicas: false,
+        }
+    }
+    pub fn set_username(&mut self, username: &'a str) {
+        self.username = username;
+    }
+    pub fn set_password(&mut self, password: &'a str) {
+        self.password = password;
+    }
+    pub fn set_read_from_replicas(&mut self, read_from_replicas: bool) {
+        self.read_from_replicas = read_from_replicas;
+    }
+
+    fn client(self) -> RedisResult<ClusterClient> {
+        let urls = self.nodes.iter().map(|node| format!("redis://{node}"));
+        let mut builder = ClusterClient::builder(urls.collect::<Vec<_>>());
+        if !self.username.is_empty() {
+            builder = builder.username(self.username.to_string());
+        }
+        if !self.password.is_empty() {
+            builder = builder.password(self.password.to_string());
+        }
+        if self.read_from_replicas {
+            builder = builder.read_from_replicas();
+        }
+        builder.build()
+    }
+}
+
+impl Cache<ClusterConnection> {
+    pub fn cluster(config: ClusterConfig) -> RedisResult<Self> {
+        let con = config.client()?.get_connection()?;
+        Ok(Cache::from_connection(ClusterConnection(con)))
+    }
+}
+
+impl AsyncCache<AsyncClusterConnection> {
+    pub async fn cluster(config: ClusterConfig<'_>) -> RedisResult<Self> {
+        let con = config.client()?.get_async_connection().await?;
+        Ok(AsyncCache::from_connection(AsyncClusterConnection(con)))
+    }
+}
+
+/// A blocking cluster connection. Commands are routed to the node owning
+/// the slot of their key, following `MOVED` and `ASK` redirects.
+///
+/// Every key of a command has to hash to the same slot, which can be forced
+/// with a `{hash tag}`; otherwise the command is rejected with a `CrossSlot`
+/// error before it is sent. Pipelines whose commands are in different slots
+/// are sent one command at a time. Transactions are not supported.
+pub struct ClusterConnection(cluster::ClusterConnection);
+
+impl ConnectionLike for ClusterConnection {
+    fn req_packed_command(&mut self, cmd: &[u8]) -> RedisResult<Value> {
+        check_slots(cmd, false)?;
+        self.0.req_packed_command(cmd)
+    }
+
+    fn req_packed_commands(
+        &mut self,
+        cmd: &[u8],
+        offset: usize,
+        count: usize,
+    ) -> RedisResult<Vec<Value>> {
+        match check_slots(cmd, false)? {
+            Some(cmds) => cmds
+                .iter()
+                .map(|cmd| self.0.req_packed_command(&cmd.get_packed_command()))
+                .collect(),
+            None => self.0.req_packed_commands(cmd, offset, count),
+        }
+    }
+
+    fn get_db(&self) -> i64 {
+        0
+    }
+
+    fn check_connection(&mut self) -> bool {
+        self.0.check_connection()
+    }
+
+    fn is_open(&self) -> bool {
+        self.0.is_open()
+    }
+}
+
+/// The async counterpart of `ClusterConnection`. Unlike it, it sends atomic
+/// pipelines, `MULTI`/`EXEC` without `WATCH`, as long as all their keys are
+/// in one slot.
+///
+/// It is multiplexed, so `WATCH` would be shared with every other caller on
+/// the same node connection; it does not implement `Dedicated`, and
+/// `AsyncCache::transaction` is not available on an `AsyncClusterCache`.
+#[derive(Clone)]
+pub struct AsyncClusterConnection(cluster_async::ClusterConnection);
+
+impl aio::ConnectionLike for AsyncClusterConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        if let Err(err) = check_slots(&cmd.get_packed_command(), true) {
+            return Box::pin(std::future::ready(Err(err)));
+        }
+        self.0.req_packed_command(cmd)
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        let cmds = match check_slots(&cmd.get_packed_pipeline(), true) {
+            Ok(Some(cmds)) => cmds,
+            Ok(None) => return self.0.req_packed_commands(cmd, offset, count),
+            Err(err) => return Box::pin(std::future::ready(Err(err))),
+        };
+        Box::pin(async move {
+            let mut values = Vec::with_capacity(cmds.len());
+            for cmd in &cmds {
+                values.push(self.0.req_packed_command(cmd).await?);
+            }
+            Ok(values)
+        })
+    }
+
+    fn get_db(&self) -> i64 {
+        0
+    }
+}
+
+/// Rejects commands, and transactions, whose keys live in more than one
+/// slot. A plain pipeline spanning slots is returned as separate commands,
+/// as the cluster client sends a whole pipeline to one node.
+fn check_slots(packed: &[u8], transactions: bool) -> RedisResult<Option<Vec<Cmd>>> {
+    let commands = parse_commands(packed)?;
+    let atomic = commands
+        .first()
+        .and_then(|args| args.first())
+        .is_some_and(|name| name.eq_ignore_ascii_case(b"MULTI"));
+    if atomic && !transactions {
+        return Err((
+            ErrorKind::ClientError,
+            "Transactions are not supported by the blocking cluster client",
+        )
+            .into());
+    }
+    let mut first: Option<(&[u8], u16)> = None;
+    let mut spread = false;
+    for args in &commands {
+        let Some(name) = args.first() else {
+            continue;
+        };
+        let name = name.to_ascii_uppercase();
+        let mut command: Option<(&[u8], u16)> = None;
+        for key in command_keys(&name, args) {
+            let slot = key_slot(key);
+            match command {
+                None => command = Some((key, slot)),
+                Some((_, first_slot)) if first_slot == slot => {}
+                Some((first_key, first_slot)) => {
+                    return Err(cross_slot(first_key, first_slot, key, slot))
+                }
+            }
+        }
+        match (first, command) {
+            (_, None) => {}
+            (None, command) => first = command,
+            (Some((_, first_slot)), Some((_, slot))) if first_slot == slot => {}
+            (Some((first_key, first_slot)), Some((key, slot))) if atomic => {
+                return Err(cross_slot(first_key, first_slot, key, slot))
+            }
+            (Some(_), Some(_)) => spread = true,
+        }
+    }
+    Ok(spread.then(|| {
+        commands
+            .iter()
+            .map(|args| {
+                let mut cmd = Cmd::new();
+                args.iter().for_each(|arg| {
+                    cmd.arg(arg.as_slice());
+                });
+                cmd
+            })
+            .collect()
+    }))
+}
+
+fn cross_slot(first_key: &[u8], first_slot: u16, key: &[u8], slot: u16) -> RedisError {
+    (
+        ErrorKind::CrossSlot,
+        "Keys in request don't hash to the same slot",
+        format!(
+            "'{}' is in slot {first_slot} but '{}' is in slot {slot}; use a {{hash tag}} to keep them together",
+            String::from_utf8_lossy(first_key),
+            String::from_utf8_lossy(key),
+        ),
+    )
+        .into()
+}
+
+/// The cluster slot of `key`: CRC16 of its hash tag, or of the whole key if
+/// it has none.
+pub fn key_slot(key: &[u8]) -> u16 {
+    let tagged = key.iter().position(|&b| b == b'{').and_then(|open| {
+        let rest = &key[open + 1..];
+        let close = rest.iter().position(|&b| b == b'}')?;
+        (close > 0).then(|| &rest[..close])
+    });
+    crc16(tagged.unwrap_or(key)) % 16384
+}
+
+/// CRC16/XMODEM, as used for cluster slots.
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |mut crc, &byte| {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+#[cfg(test)]
+mod tests_cluster {
+    use super::*;
+
+    #[test]
+    fn test_key_slot() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+        assert_eq!(key_slot(b"foo"), 12182);
+        assert_eq!(key_slot(b"{user1000}.following"), key_slot(b"user1000"));
+        assert_eq!(key_slot(b"foo{}{bar}"), key_slot(b"foo{}{bar}"));
+        assert_ne!(key_slot(b"foo{}{bar}"), key_slot(b"bar"));
+        assert_eq!(key_slot(b"foo{{bar}}zap"), key_slot(b"{bar"));
+    }
+
+    #[test]
+    fn test_check_slots() {
+        let packed = |cmd: &mut Cmd| cmd.get_packed_command();
+        assert!(check_slots(&packed(redis::cmd("GET").arg("foo")), false).is_ok());
+        let del = packed(redis::cmd("DEL").arg(&["{user}.a", "{user}.b"]));
+        assert!(check_slots(&del, false).is_ok());
+
+        let err = check_slots(&packed(redis::cmd("DEL").arg(&["a", "b"])), false)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::CrossSlot);
+        assert!(err.detail().unwrap().contains("'a' is in slot 15495"));
+
+        let pipe = redis::pipe()
+            .get("{u}.a")
+            .get("{u}.b")
+            .get_packed_pipeline();
+        assert!(check_slots(&pipe, false).unwrap().is_none());
+        let pipe = redis::pipe().get("a").set("b", 1).get_packed_pipeline();
+        let cmds = check_slots(&pipe, false).unwrap().unwrap();
+        assert_eq!(cmds.len(), 2);
+        assert_eq!(
+            cmds[1].get_packed_command(),
+            packed(redis::cmd("SET").arg("b").arg(1))
+        );
+        let pipe = redis::pipe()
+            .get("a")
+            .del(&["a", "b"])
+            .get_packed_pipeline();
+        assert_eq!(
+            check_slots(&pipe, false).err().unwrap().kind(),
+            ErrorKind::CrossSlot
+        );
+
+        let tx = redis::pipe()
+            .atomic()
+            .incr("{u}.a", 1)
+            .incr("{u}.b", 1)
+            .get_packed_pipeline();
+        assert!(check_slots(&tx, true).unwrap().is_none());
+        assert_eq!(
+            check_slots(&tx, false).err().unwrap().kind(),
+            ErrorKind::ClientError
+        );
+        let lmove = packed(redis::cmd("LMOVE").arg(&["{q}.a", "{q}.b", "LEFT", "RIGHT"]));
+        assert!(check_slots(&lmove, false).is_ok());
+        for name in ["LMOVE", "BLMOVE"] {
+            let cmd = packed(redis::cmd(name).arg(&["a", "b", "LEFT", "RIGHT", "0"]));
+            let err = check_slots(&cmd, false).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::CrossSlot);
+        }
+        let blpop = packed(redis::cmd("BLPOP").arg(&["{q}.a", "{q}.b", "0"]));
+        assert!(check_slots(&blpop, false).is_ok());
+        for name in ["BLPOP", "BRPOP"] {
+            let cmd = packed(redis::cmd(name).arg(&["a", "b", "0"]));
+            let err = check_slots(&cmd, false).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::CrossSlot);
+        }
+
+        let tx = redis::pipe()
+            .atomic()
+            .incr("a", 1)
+            .incr("b", 1)
+            .get_packed_pipeline();
+        assert_eq!(
+            check_slots(&tx, true).err().unwrap().kind(),
+            ErrorKind::CrossSlot
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use redis::RedisResult;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Turns serde values into the bytes stored under a key and back.
+pub trait Codec {
+    fn encode<T>(value: &T) -> RedisResult<Vec<u8>>
+    where
+        T: Serialize + ?Sized;
+    fn decode<T>(bytes: &[u8]) -> RedisResult<T>
+    where
+        T: DeserializeOwned;
+}
+
+#[cfg(feature = "json")]
+pub struct JsonCodec;
+
+#[cfg(feature = "json")]
+impl Codec for JsonCodec {
+    fn encode<T>(value: &T) -> RedisResult<Vec<u8>>
+    where
+        T: Serialize + ?Sized,
+    {
+        serde_json::to_vec(value).map_err(encode_error)
+    }
+
+    fn decode<T>(bytes: &[u8]) -> RedisResult<T>
+    where
+        T: DeserializeOwned,
+    {
+        serde_json::from_slice(bytes).map_err(decode_error)
+    }
+}
+
+#[cfg(feature = "msgpack")]
+pub struct MsgPackCodec;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MsgPackCodec {
+    fn encode<T>(value: &T) -> RedisResult<Vec<u8>>
+    where
+        T: Serialize + ?Sized,
+    {
+        rmp_serde::to_vec_named(value).map_err(encode_error)
+    }
+
+    fn decode<T>(bytes: &[u8]) -> RedisResult<T>
+    where
+        T: DeserializeOwned,
+    {
+        rmp_serde::from_slice(bytes).map_err(decode_error)
+    }
+}
+
+#[cfg(feature = "bincode")]
+pub struct BincodeCodec;
+
+#[cfg(feature = "bincode")]
+impl Codec for BincodeCodec {
+    fn encode<T>(value: &T) -> RedisResult<Vec<u8>>
+    where
+        T: Serialize + ?Sized,
+    {
+        bincode::serialize(value).map_err(encode_error)
+    }
+
+    fn decode<T>(bytes: &[u8]) -> RedisResult<T>
+    where
+        T: DeserializeOwned,
+    {
+        bincode::deserialize(bytes).map_err(decode_error)
+    }
+}
+
+#[cfg(any(feature = "json", feature = "msgpack", feature = "bincode"))]
+fn encode_error(err: impl ToString) -> redis::RedisError {
+    (
+        redis::ErrorKind::ClientError,
+        "Failed to encode value",
+        err.to_string(),
+    )
+        .into()
+}
+
+#[cfg(any(feature = "json", feature = "msgpack", feature = "bincode"))]
+fn decode_error(err: impl ToString) -> redis::RedisError {
+    (
+        redis::ErrorKind::TypeError,
+        "Failed to decode value",
+        err.to_string(),
+    )
+        .into()
+}
+
+#[cfg(all(test, any(feature = "json", feature = "msgpack", feature = "bincode")))]
+mod tests_codec {
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::{Cache, ICache, MemoryConnection};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct User {
+        id: String,
+        name: String,
+        age: i32,
+        tags: Vec<String>,
+    }
+
+    fn user() -> User {
+        User {
+            id: "123".into(),
+            name: "abc".into(),
+            age: 321,
+            tags: vec!["x".into(), "y".into()],
+        }
+    }
+
+    fn round_trip<C: Codec>() {
+        let mut ca = Cache::from_connection(MemoryConnection::new());
+        ca.set_encoded::<C, _, _>("user", &user()).unwrap();
+        let u: Option<User> = ca.get_encoded::<C, _, _>("user").unwrap();
+        assert_eq!(u, Some(user()));
+        let missing: Option<User> = ca.get_encoded::<C, _, _>("missing").unwrap();
+        assert_eq!(missing, None);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json() {
+        round_trip::<JsonCodec>();
+
+        let mut ca = Cache::from_connection(MemoryConnection::new());
+        ca.set_json("user", &user()).unwrap();
+        let raw: String = ca.get("user").unwrap();
+        assert_eq!(
+            raw,
+            r#"{"id":"123","name":"abc","age":321,"tags":["x","y"]}"#
+        );
+
+        ca.set("user", "not json").unwrap();
+        let err = ca.get_json::<_, User>("user").unwrap_err();
+        assert_eq!(err.kind(), redis::ErrorKind::TypeError);
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_msgpack() {
+        round_trip::<MsgPackCodec>();
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn test_bincode() {
+        round_trip::<BincodeCodec>();
+    }
+
+    #[cfg(feature = "json")]
+    #[tokio::test]
+    async fn test_async_json() {
+        use crate::{AsyncCache, IAsyncCache};
+
+        let mut ca = AsyncCache::from_connection(MemoryConnection::new());
+        ca.set_json("user", &user()).await.unwrap();
+        let u: Option<User> = ca.get_json("user").await.unwrap();
+        assert_eq!(u, Some(user()));
+        let u: Option<User> = ca.get_encoded::<JsonCodec, _, _>("missing").await.unwrap();
+        assert_eq!(u, None);
+    }
+}
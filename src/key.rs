@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+use redis::{FromRedisValue, ToRedisArgs};
+
+/// A key bound to the type of the value stored under it.
+///
+/// Read and write it with `get_typed` and `set_typed`, which only accept
+/// values of `Self::Value`.
+pub trait CacheKey {
+    type Value: FromRedisValue + ToRedisArgs;
+
+    /// The Redis key, formatted from the fields of `self`.
+    fn key(&self) -> String;
+
+    /// How long values written with `set_typed` live; `None` keeps them
+    /// until deleted.
+    fn ttl(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests_key {
+    use super::*;
+    use crate::{AsyncCache, Cache, IAsyncCache, ICache, MemoryConnection};
+
+    struct VisitsKey<'a>(&'a str);
+
+    impl CacheKey for VisitsKey<'_> {
+        type Value = u64;
+
+        fn key(&self) -> String {
+            format!("visits:{}", self.0)
+        }
+
+        fn ttl(&self) -> Option<Duration> {
+            Some(Duration::from_secs(60))
+        }
+    }
+
+    struct NameKey;
+
+    impl CacheKey for NameKey {
+        type Value = String;
+
+        fn key(&self) -> String {
+            "name".into()
+        }
+    }
+
+    struct FlashKey;
+
+    impl CacheKey for FlashKey {
+        type Value = String;
+
+        fn key(&self) -> String {
+            "flash".into()
+        }
+
+        fn ttl(&self) -> Option<Duration> {
+            Some(Duration::ZERO)
+        }
+    }
+
+    #[test]
+    fn test_typed() {
+        let mut con = MemoryConnection::new();
+        let mut ca = Cache::from_connection(con.clone());
+        assert_eq!(ca.get_typed(&VisitsKey("home")).unwrap(), None);
+        ca.set_typed(&VisitsKey("home"), &3).unwrap();
+        assert_eq!(ca.get_typed(&VisitsKey("home")).unwrap(), Some(3));
+        let ttl: i64 = redis::cmd("TTL")
+            .arg("visits:home")
+            .query(&mut con)
+            .unwrap();
+        assert_eq!(ttl, 60);
+
+        ca.set_typed(&NameKey, &"abc".to_string()).unwrap();
+        let ttl: i64 = redis::cmd("TTL").arg("name").query(&mut con).unwrap();
+        assert_eq!(ttl, -1);
+
+        let err = ca.set_typed(&FlashKey, &"abc".to_string()).unwrap_err();
+        assert_eq!(err.kind(), redis::ErrorKind::InvalidClientConfig);
+        assert!(!ca.exists("flash").unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_async_typed() {
+        let mut ca = AsyncCache::from_connection(MemoryConnection::new());
+        ca.set_typed(&NameKey, &"abc".to_string()).await.unwrap();
+        assert_eq!(
+            ca.get_typed(&NameKey).await.unwrap().as_deref(),
+            Some("abc")
+        );
+        ca.set("visits:a", "many").await.unwrap();
+        assert!(ca.get_typed(&VisitsKey("a")).await.is_err());
+    }
+}
@@ -1,12 +1,76 @@
-use redis::{FromRedisValue, RedisResult, ToRedisArgs};
+use std::{future::Future, time::Duration};
+
+use redis::{Direction, FromRedisValue, RedisError, RedisResult, SetExpiry, ToRedisArgs};
+use serde::{de::DeserializeOwned, Serialize};
+
+// Lets the derive macros name `cache_ru` from inside this crate as well.
+extern crate self as cache_ru;
 
 pub mod async_cache;
 pub mod cache;
+#[cfg(feature = "cluster")]
+pub mod cluster;
+pub mod codec;
 pub mod config;
+pub mod key;
+pub mod memory;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod namespace;
+pub mod near_cache;
+pub mod options;
+pub mod pipeline;
+pub mod pool;
+pub mod pubsub;
+mod resp;
+pub mod retry;
+#[cfg(feature = "sentinel")]
+pub mod sentinel;
+pub mod single_flight;
+pub mod stream;
+#[cfg(feature = "tracing")]
+pub mod traced;
 
-pub use async_cache::AsyncCache;
+pub use async_cache::{AsyncCache, Dedicated};
 pub use cache::Cache;
-pub use config::RedisConfig;
+#[cfg(feature = "derive")]
+pub use cache_ru_derive::RedisHash;
+#[cfg(feature = "cluster")]
+pub use cluster::{
+    AsyncClusterCache, AsyncClusterConnection, ClusterCache, ClusterConfig, ClusterConnection,
+};
+#[cfg(feature = "bincode")]
+pub use codec::BincodeCodec;
+pub use codec::Codec;
+#[cfg(feature = "json")]
+pub use codec::JsonCodec;
+#[cfg(feature = "msgpack")]
+pub use codec::MsgPackCodec;
+pub use config::{OwnedRedisConfig, RedisConfig};
+pub use key::CacheKey;
+pub use memory::{AsyncMemoryCache, MemoryCache, MemoryConnection};
+#[cfg(feature = "metrics")]
+pub use metrics::{Metered, Metrics};
+pub use namespace::Namespaced;
+pub use near_cache::{NearCache, Tracking};
+pub use options::{SetOptions, SetReply};
+pub use pipeline::Pipeline;
+pub use pool::{CachePool, PoolConfig, PooledCache};
+pub use pubsub::{Message, Subscriber, Topic};
+pub use retry::{AsyncReconnecting, Reconnecting, RetryPolicy};
+#[cfg(feature = "sentinel")]
+pub use sentinel::SentinelConfig;
+pub use single_flight::SingleFlight;
+pub use stream::{StreamAutoClaimReply, StreamWorker};
+#[cfg(feature = "tracing")]
+pub use traced::Traced;
+
+/// Paths used by the code the derive macros generate, so that crates using
+/// them need no direct `redis` dependency.
+#[doc(hidden)]
+pub mod __private {
+    pub use redis;
+}
 
 pub trait ICache {
     fn get<K, V>(&mut self, key: K) -> RedisResult<V>
@@ -17,6 +81,16 @@ pub trait ICache {
     where
         K: ToRedisArgs,
         V: ToRedisArgs;
+    fn set_with<K, V, R>(
+        &mut self,
+        key: K,
+        value: V,
+        options: SetOptions,
+    ) -> RedisResult<SetReply<R>>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+        R: FromRedisValue;
     fn del<K>(&mut self, key: K) -> RedisResult<()>
     where
         K: ToRedisArgs;
@@ -103,4 +177,691 @@ pub trait ICache {
     where
         K: ToRedisArgs,
         M: ToRedisArgs;
+    fn lpush<K, V>(&mut self, key: K, values: &[V]) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs;
+    fn rpush<K, V>(&mut self, key: K, values: &[V]) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs;
+    fn lpop<K, V>(&mut self, key: K) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue;
+    fn rpop<K, V>(&mut self, key: K) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue;
+    fn lrange<K, V>(&mut self, key: K, start: isize, stop: isize) -> RedisResult<Vec<V>>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue;
+    fn ltrim<K>(&mut self, key: K, start: isize, stop: isize) -> RedisResult<()>
+    where
+        K: ToRedisArgs;
+    fn lindex<K, V>(&mut self, key: K, index: isize) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue;
+    /// Returns the new length of the list, or -1 if `pivot` was not found.
+    fn linsert_before<K, P, V>(&mut self, key: K, pivot: P, value: V) -> RedisResult<isize>
+    where
+        K: ToRedisArgs,
+        P: ToRedisArgs,
+        V: ToRedisArgs;
+    /// Returns the new length of the list, or -1 if `pivot` was not found.
+    fn linsert_after<K, P, V>(&mut self, key: K, pivot: P, value: V) -> RedisResult<isize>
+    where
+        K: ToRedisArgs,
+        P: ToRedisArgs,
+        V: ToRedisArgs;
+    fn llen<K>(&mut self, key: K) -> RedisResult<usize>
+    where
+        K: ToRedisArgs;
+    /// Pops from the head of the first non-empty list of `key`, waiting up
+    /// to `timeout` for one, or forever if it is zero. The reply is a
+    /// `(key, value)` pair, or nil on timeout.
+    fn blpop<K, V>(&mut self, key: K, timeout: Duration) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue;
+    /// Like `blpop`, popping from the tail.
+    fn brpop<K, V>(&mut self, key: K, timeout: Duration) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue;
+    /// Moves an item from one end of the list at `key` to one end of
+    /// `destination` and returns it, or nil if the list is empty.
+    fn lmove<K, D, V>(
+        &mut self,
+        key: K,
+        destination: D,
+        from: Direction,
+        to: Direction,
+    ) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        D: ToRedisArgs,
+        V: FromRedisValue;
+    /// Like `lmove`, waiting up to `timeout` for the list to have an item,
+    /// or forever if it is zero.
+    fn blmove<K, D, V>(
+        &mut self,
+        key: K,
+        destination: D,
+        from: Direction,
+        to: Direction,
+        timeout: Duration,
+    ) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        D: ToRedisArgs,
+        V: FromRedisValue;
+
+    fn get_encoded<C, K, V>(&mut self, key: K) -> RedisResult<Option<V>>
+    where
+        C: Codec,
+        K: ToRedisArgs,
+        V: DeserializeOwned,
+    {
+        let bytes: Option<Vec<u8>> = self.get(key)?;
+        bytes.map(|b| C::decode(&b)).transpose()
+    }
+    fn set_encoded<C, K, V>(&mut self, key: K, value: &V) -> RedisResult<()>
+    where
+        C: Codec,
+        K: ToRedisArgs,
+        V: Serialize + ?Sized,
+    {
+        self.set(key, C::encode(value)?)
+    }
+    #[cfg(feature = "json")]
+    fn get_json<K, V>(&mut self, key: K) -> RedisResult<Option<V>>
+    where
+        K: ToRedisArgs,
+        V: DeserializeOwned,
+    {
+        self.get_encoded::<JsonCodec, K, V>(key)
+    }
+    #[cfg(feature = "json")]
+    fn set_json<K, V>(&mut self, key: K, value: &V) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        V: Serialize + ?Sized,
+    {
+        self.set_encoded::<JsonCodec, K, V>(key, value)
+    }
+    /// Reads the value stored under a typed key, `None` when it is missing.
+    fn get_typed<K>(&mut self, key: &K) -> RedisResult<Option<K::Value>>
+    where
+        K: CacheKey,
+    {
+        self.get(key.key())
+    }
+    /// Stores `value` under a typed key, expiring after the key's TTL.
+    fn set_typed<K>(&mut self, key: &K, value: &K::Value) -> RedisResult<()>
+    where
+        K: CacheKey,
+    {
+        match key.ttl() {
+            Some(ttl) => {
+                let options = ttl_options(ttl)?;
+                let _: SetReply<()> = self.set_with(key.key(), value, options)?;
+                Ok(())
+            }
+            None => self.set(key.key(), value),
+        }
+    }
+    /// Reads `key`, or on a miss runs `loader` and stores its value with `ttl`.
+    ///
+    /// Only a nil reply counts as a miss; a stored value that fails to
+    /// decode is returned as an error and left untouched.
+    fn get_or_set_with<K, V, E, F>(&mut self, key: K, ttl: Duration, loader: F) -> Result<V, E>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue + ToRedisArgs,
+        E: From<RedisError>,
+        F: FnOnce() -> Result<V, E>,
+    {
+        let options = ttl_options(ttl)?;
+        if let Some(value) = self.get::<_, Option<V>>(&key)? {
+            return Ok(value);
+        }
+        let value = loader()?;
+        let _: SetReply<()> = self.set_with(&key, &value, options)?;
+        Ok(value)
+    }
+}
+
+pub trait IAsyncCache: Send {
+    fn get<K, V>(&mut self, key: K) -> impl Future<Output = RedisResult<V>> + Send
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue;
+    fn set<K, V>(&mut self, key: K, value: V) -> impl Future<Output = RedisResult<()>> + Send
+    where
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Send;
+    fn set_with<K, V, R>(
+        &mut self,
+        key: K,
+        value: V,
+        options: SetOptions,
+    ) -> impl Future<Output = RedisResult<SetReply<R>>> + Send
+    where
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Send,
+        R: FromRedisValue;
+    fn del<K>(&mut self, key: K) -> impl Future<Output = RedisResult<()>> + Send
+    where
+        K: ToRedisArgs + Send;
+    fn incr<K>(&mut self, key: K) -> impl Future<Output = RedisResult<i32>> + Send
+    where
+        K: ToRedisArgs + Send;
+    fn exists<K>(&mut self, key: K) -> impl Future<Output = RedisResult<bool>> + Send
+    where
+        K: ToRedisArgs + Send;
+    fn expire<K>(&mut self, key: K, sec: i32) -> impl Future<Output = RedisResult<()>> + Send
+    where
+        K: ToRedisArgs + Send;
+    fn sadd<K, V>(&mut self, key: K, values: &[V]) -> impl Future<Output = RedisResult<()>> + Send
+    where
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Sync;
+    fn smembers<K, V>(&mut self, key: K) -> impl Future<Output = RedisResult<Vec<V>>> + Send
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue;
+    fn srem<K, V>(&mut self, key: K, values: &[V]) -> impl Future<Output = RedisResult<()>> + Send
+    where
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Sync;
+    fn scard<K>(&mut self, key: K) -> impl Future<Output = RedisResult<usize>> + Send
+    where
+        K: ToRedisArgs + Send;
+    fn sismember<K, V>(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> impl Future<Output = RedisResult<bool>> + Send
+    where
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Send;
+    fn hset<K, F, V>(
+        &mut self,
+        key: K,
+        field: F,
+        value: V,
+    ) -> impl Future<Output = RedisResult<()>> + Send
+    where
+        K: ToRedisArgs + Send,
+        F: ToRedisArgs + Send,
+        V: ToRedisArgs + Send;
+    fn hget<K, F, V>(&mut self, key: K, field: F) -> impl Future<Output = RedisResult<V>> + Send
+    where
+        K: ToRedisArgs + Send,
+        F: ToRedisArgs + Send,
+        V: FromRedisValue;
+    fn hmset<K, F, V>(
+        &mut self,
+        key: K,
+        values: &[(F, V)],
+    ) -> impl Future<Output = RedisResult<()>> + Send
+    where
+        K: ToRedisArgs + Send,
+        F: ToRedisArgs + Sync,
+        V: ToRedisArgs + Sync;
+    fn hmget<K, F, V>(
+        &mut self,
+        key: K,
+        fields: &[F],
+    ) -> impl Future<Output = RedisResult<V>> + Send
+    where
+        K: ToRedisArgs + Send,
+        F: ToRedisArgs + Sync,
+        V: FromRedisValue;
+    fn hsetall<K, V>(&mut self, key: K, value: V) -> impl Future<Output = RedisResult<()>> + Send
+    where
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Send;
+    fn hgetall<K, V>(&mut self, key: K) -> impl Future<Output = RedisResult<V>> + Send
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue;
+    fn hexists<K, F>(&mut self, key: K, field: F) -> impl Future<Output = RedisResult<bool>> + Send
+    where
+        K: ToRedisArgs + Send,
+        F: ToRedisArgs + Send;
+    fn hdel<K, F>(&mut self, key: K, fields: &[F]) -> impl Future<Output = RedisResult<()>> + Send
+    where
+        K: ToRedisArgs + Send,
+        F: ToRedisArgs + Sync;
+    fn zadd<K, S, M>(
+        &mut self,
+        key: K,
+        items: &[(S, M)],
+    ) -> impl Future<Output = RedisResult<()>> + Send
+    where
+        K: ToRedisArgs + Send,
+        S: ToRedisArgs + Sync,
+        M: ToRedisArgs + Sync;
+    fn zrange_by_score<K, M, V>(
+        &mut self,
+        key: K,
+        min: M,
+        max: M,
+    ) -> impl Future<Output = RedisResult<Vec<V>>> + Send
+    where
+        K: ToRedisArgs + Send,
+        M: ToRedisArgs + Send,
+        V: FromRedisValue;
+    fn zrevrange_by_score<K, M, V>(
+        &mut self,
+        key: K,
+        max: M,
+        min: M,
+    ) -> impl Future<Output = RedisResult<Vec<V>>> + Send
+    where
+        K: ToRedisArgs + Send,
+        M: ToRedisArgs + Send,
+        V: FromRedisValue;
+    fn zrem<K, M>(&mut self, key: K, items: &[M]) -> impl Future<Output = RedisResult<()>> + Send
+    where
+        K: ToRedisArgs + Send,
+        M: ToRedisArgs + Sync;
+    fn lpush<K, V>(&mut self, key: K, values: &[V]) -> impl Future<Output = RedisResult<()>> + Send
+    where
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Sync;
+    fn rpush<K, V>(&mut self, key: K, values: &[V]) -> impl Future<Output = RedisResult<()>> + Send
+    where
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Sync;
+    fn lpop<K, V>(&mut self, key: K) -> impl Future<Output = RedisResult<V>> + Send
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue;
+    fn rpop<K, V>(&mut self, key: K) -> impl Future<Output = RedisResult<V>> + Send
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue;
+    fn lrange<K, V>(
+        &mut self,
+        key: K,
+        start: isize,
+        stop: isize,
+    ) -> impl Future<Output = RedisResult<Vec<V>>> + Send
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue;
+    fn ltrim<K>(
+        &mut self,
+        key: K,
+        start: isize,
+        stop: isize,
+    ) -> impl Future<Output = RedisResult<()>> + Send
+    where
+        K: ToRedisArgs + Send;
+    fn lindex<K, V>(&mut self, key: K, index: isize) -> impl Future<Output = RedisResult<V>> + Send
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue;
+    /// Returns the new length of the list, or -1 if `pivot` was not found.
+    fn linsert_before<K, P, V>(
+        &mut self,
+        key: K,
+        pivot: P,
+        value: V,
+    ) -> impl Future<Output = RedisResult<isize>> + Send
+    where
+        K: ToRedisArgs + Send,
+        P: ToRedisArgs + Send,
+        V: ToRedisArgs + Send;
+    /// Returns the new length of the list, or -1 if `pivot` was not found.
+    fn linsert_after<K, P, V>(
+        &mut self,
+        key: K,
+        pivot: P,
+        value: V,
+    ) -> impl Future<Output = RedisResult<isize>> + Send
+    where
+        K: ToRedisArgs + Send,
+        P: ToRedisArgs + Send,
+        V: ToRedisArgs + Send;
+    fn llen<K>(&mut self, key: K) -> impl Future<Output = RedisResult<usize>> + Send
+    where
+        K: ToRedisArgs + Send;
+    /// Pops from the head of the first non-empty list of `key`, waiting up
+    /// to `timeout` for one, or forever if it is zero. The reply is a
+    /// `(key, value)` pair, or nil on timeout.
+    ///
+    /// The wait holds up every other command on a multiplexed connection,
+    /// so blocking pops are best sent on a connection of their own.
+    fn blpop<K, V>(
+        &mut self,
+        key: K,
+        timeout: Duration,
+    ) -> impl Future<Output = RedisResult<V>> + Send
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue;
+    /// Like `blpop`, popping from the tail.
+    fn brpop<K, V>(
+        &mut self,
+        key: K,
+        timeout: Duration,
+    ) -> impl Future<Output = RedisResult<V>> + Send
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue;
+    /// Moves an item from one end of the list at `key` to one end of
+    /// `destination` and returns it, or nil if the list is empty.
+    fn lmove<K, D, V>(
+        &mut self,
+        key: K,
+        destination: D,
+        from: Direction,
+        to: Direction,
+    ) -> impl Future<Output = RedisResult<V>> + Send
+    where
+        K: ToRedisArgs + Send,
+        D: ToRedisArgs + Send,
+        V: FromRedisValue;
+    /// Like `lmove`, waiting up to `timeout` for the list to have an item,
+    /// or forever if it is zero.
+    fn blmove<K, D, V>(
+        &mut self,
+        key: K,
+        destination: D,
+        from: Direction,
+        to: Direction,
+        timeout: Duration,
+    ) -> impl Future<Output = RedisResult<V>> + Send
+    where
+        K: ToRedisArgs + Send,
+        D: ToRedisArgs + Send,
+        V: FromRedisValue;
+
+    fn get_encoded<C, K, V>(
+        &mut self,
+        key: K,
+    ) -> impl Future<Output = RedisResult<Option<V>>> + Send
+    where
+        C: Codec,
+        K: ToRedisArgs + Send,
+        V: DeserializeOwned,
+    {
+        async move {
+            let bytes: Option<Vec<u8>> = self.get(key).await?;
+            bytes.map(|b| C::decode(&b)).transpose()
+        }
+    }
+    fn set_encoded<C, K, V>(
+        &mut self,
+        key: K,
+        value: &V,
+    ) -> impl Future<Output = RedisResult<()>> + Send
+    where
+        C: Codec,
+        K: ToRedisArgs + Send,
+        V: Serialize + ?Sized,
+    {
+        let value = C::encode(value);
+        async move { self.set(key, value?).await }
+    }
+    #[cfg(feature = "json")]
+    fn get_json<K, V>(&mut self, key: K) -> impl Future<Output = RedisResult<Option<V>>> + Send
+    where
+        K: ToRedisArgs + Send,
+        V: DeserializeOwned,
+    {
+        self.get_encoded::<JsonCodec, K, V>(key)
+    }
+    #[cfg(feature = "json")]
+    fn set_json<K, V>(&mut self, key: K, value: &V) -> impl Future<Output = RedisResult<()>> + Send
+    where
+        K: ToRedisArgs + Send,
+        V: Serialize + ?Sized,
+    {
+        self.set_encoded::<JsonCodec, K, V>(key, value)
+    }
+    /// Reads the value stored under a typed key, `None` when it is missing.
+    fn get_typed<K>(
+        &mut self,
+        key: &K,
+    ) -> impl Future<Output = RedisResult<Option<K::Value>>> + Send
+    where
+        K: CacheKey,
+    {
+        self.get(key.key())
+    }
+    /// Stores `value` under a typed key, expiring after the key's TTL.
+    fn set_typed<K>(
+        &mut self,
+        key: &K,
+        value: &K::Value,
+    ) -> impl Future<Output = RedisResult<()>> + Send
+    where
+        K: CacheKey,
+        K::Value: Sync,
+    {
+        let (name, ttl) = (key.key(), key.ttl());
+        async move {
+            match ttl {
+                Some(ttl) => {
+                    let options = ttl_options(ttl)?;
+                    let _: SetReply<()> = self.set_with(name, value, options).await?;
+                    Ok(())
+                }
+                None => self.set(name, value).await,
+            }
+        }
+    }
+    /// Reads `key`, or on a miss awaits `loader` and stores its value with `ttl`.
+    ///
+    /// Only a nil reply counts as a miss; a stored value that fails to
+    /// decode is returned as an error and left untouched.
+    fn get_or_set_with<K, V, E, F, Fut>(
+        &mut self,
+        key: K,
+        ttl: Duration,
+        loader: F,
+    ) -> impl Future<Output = Result<V, E>> + Send
+    where
+        K: ToRedisArgs + Send + Sync,
+        V: FromRedisValue + ToRedisArgs + Send + Sync,
+        E: From<RedisError> + Send,
+        F: FnOnce() -> Fut + Send,
+        Fut: Future<Output = Result<V, E>> + Send,
+    {
+        async move {
+            let options = ttl_options(ttl)?;
+            if let Some(value) = self.get::<_, Option<V>>(&key).await? {
+                return Ok(value);
+            }
+            let value = loader().await?;
+            let _: SetReply<()> = self.set_with(&key, &value, options).await?;
+            Ok(value)
+        }
+    }
+}
+
+/// `PX` takes whole milliseconds and rejects zero, so shorter TTLs are
+/// refused here rather than after the value was loaded.
+fn ttl_options(ttl: Duration) -> RedisResult<SetOptions> {
+    match ttl.as_millis() {
+        0 => Err((
+            redis::ErrorKind::InvalidClientConfig,
+            "TTL must be at least one millisecond",
+        )
+            .into()),
+        millis => Ok(SetOptions::default().with_expiration(SetExpiry::PX(millis as usize))),
+    }
+}
+
+#[cfg(test)]
+mod tests_get_or_set {
+    use std::cell::Cell;
+
+    use super::*;
+
+    #[test]
+    fn test_get_or_set_with() {
+        let mut con = MemoryConnection::new();
+        let mut ca = Cache::from_connection(con.clone());
+        let calls = Cell::new(0);
+        let loader = || {
+            calls.set(calls.get() + 1);
+            Ok::<_, RedisError>(42)
+        };
+
+        let v: i32 = ca
+            .get_or_set_with("num", Duration::from_secs(60), loader)
+            .unwrap();
+        assert_eq!(v, 42);
+        let ttl: i64 = redis::cmd("TTL").arg("num").query(&mut con).unwrap();
+        assert_eq!(ttl, 60);
+
+        let v: i32 = ca
+            .get_or_set_with("num", Duration::from_secs(60), loader)
+            .unwrap();
+        assert_eq!(v, 42);
+        assert_eq!(calls.get(), 1);
+
+        ca.set("num", "not a number").unwrap();
+        let err = ca
+            .get_or_set_with("num", Duration::from_secs(60), loader)
+            .unwrap_err();
+        assert_eq!(err.kind(), redis::ErrorKind::TypeError);
+        assert_eq!(calls.get(), 1);
+
+        let err = ca
+            .get_or_set_with("short", Duration::from_micros(500), loader)
+            .unwrap_err();
+        assert_eq!(err.kind(), redis::ErrorKind::InvalidClientConfig);
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_async_get_or_set_with() {
+        #[derive(Debug)]
+        enum LoadError {
+            NotFound,
+            Redis,
+        }
+
+        impl From<RedisError> for LoadError {
+            fn from(_: RedisError) -> Self {
+                LoadError::Redis
+            }
+        }
+
+        let mut ca = AsyncCache::from_connection(MemoryConnection::new());
+        let v: String = ca
+            .get_or_set_with("name", Duration::from_secs(60), || async {
+                Ok::<_, LoadError>("abc".to_string())
+            })
+            .await
+            .unwrap();
+        assert_eq!(v, "abc");
+        assert_eq!(ca.get::<_, String>("name").await.unwrap(), "abc");
+
+        let err = ca
+            .get_or_set_with("missing", Duration::from_secs(60), || async {
+                Err::<String, _>(LoadError::NotFound)
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(err, LoadError::NotFound));
+        assert!(!ca.exists("missing").await.unwrap());
+    }
+}
+
+#[cfg(all(test, feature = "derive"))]
+mod tests_derive {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[derive(Debug, Default, PartialEq, RedisHash)]
+    struct User {
+        id: String,
+        #[redis(rename = "user_name")]
+        name: String,
+        age: i32,
+        score: f64,
+        nickname: Option<String>,
+        #[redis(skip)]
+        session: Vec<u8>,
+    }
+
+    fn user() -> User {
+        User {
+            id: "123".into(),
+            name: "abc".into(),
+            age: -321,
+            score: 1.5,
+            nickname: None,
+            session: vec![1, 2, 3],
+        }
+    }
+
+    #[test]
+    fn test_hash_round_trip() {
+        let mut ca = Cache::from_connection(MemoryConnection::new());
+        ca.hsetall("user", user()).unwrap();
+        let raw: HashMap<String, String> = ca.hgetall("user").unwrap();
+        assert_eq!(
+            raw,
+            HashMap::from([
+                ("id".into(), "123".into()),
+                ("user_name".into(), "abc".into()),
+                ("age".into(), "-321".into()),
+                ("score".into(), "1.5".into()),
+            ])
+        );
+        let u: User = ca.hgetall("user").unwrap();
+        assert_eq!(
+            u,
+            User {
+                session: Vec::new(),
+                ..user()
+            }
+        );
+
+        ca.hset("user", "nickname", "nick").unwrap();
+        let u: User = ca.hgetall("user").unwrap();
+        assert_eq!(u.nickname.as_deref(), Some("nick"));
+    }
+
+    #[derive(Debug, PartialEq, RedisHash)]
+    struct Event {
+        r#type: String,
+    }
+
+    #[test]
+    fn test_raw_field_names() {
+        let mut ca = Cache::from_connection(MemoryConnection::new());
+        let event = Event {
+            r#type: "click".into(),
+        };
+        ca.hsetall("event", &event).unwrap();
+        let raw: HashMap<String, String> = ca.hgetall("event").unwrap();
+        assert_eq!(raw, HashMap::from([("type".into(), "click".into())]));
+        assert_eq!(ca.hgetall::<_, Event>("event").unwrap(), event);
+    }
+
+    #[test]
+    fn test_hash_errors() {
+        let mut ca = Cache::from_connection(MemoryConnection::new());
+        ca.hsetall("user", user()).unwrap();
+        ca.hset("user", "age", "old").unwrap();
+        let err = ca.hgetall::<_, User>("user").unwrap_err();
+        assert_eq!(err.kind(), redis::ErrorKind::TypeError);
+
+        ca.hdel("user", &["age"]).unwrap();
+        let err = ca.hgetall::<_, User>("user").unwrap_err();
+        assert_eq!(err.detail(), Some("User.age"));
+    }
 }
@@ -0,0 +1,2189 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    future,
+    ops::Bound,
+    sync::{Arc, Mutex, PoisonError},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use redis::{
+    aio, Cmd, ConnectionLike, ErrorKind, Msg, Pipeline, RedisError, RedisFuture, RedisResult, Value,
+};
+use tokio::sync::mpsc;
+
+use crate::{resp::parse_commands, AsyncCache, Cache, Dedicated, RetryPolicy, Subscriber, Topic};
+
+pub type MemoryCache = Cache<MemoryConnection>;
+pub type AsyncMemoryCache = AsyncCache<MemoryConnection>;
+
+/// How often a blocking command checks for new items.
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// An in-process stand-in for a redis server.
+///
+/// Commands are decoded from the same packed form that would go over the
+/// wire, so `Cache<MemoryConnection>` and `AsyncCache<MemoryConnection>`
+/// behave like their networked counterparts. Clones share the same data but
+/// act as separate clients for `MULTI` and `WATCH`. `PUBLISH` delivers to the
+/// subscribers opened with `subscriber`.
+#[derive(Default)]
+pub struct MemoryConnection {
+    store: Arc<Mutex<Store>>,
+    session: Session,
+}
+
+impl MemoryConnection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens a `Subscriber` to the messages published through this
+    /// connection or its clones. Must be called from within a Tokio runtime.
+    pub fn subscriber<T>(&self) -> Subscriber<T> {
+        let store = self.store.clone();
+        Subscriber::with_connect(RetryPolicy::default(), move |topics| {
+            let (sender, mut receiver) = mpsc::unbounded_channel();
+            let mut store = store.lock().unwrap_or_else(PoisonError::into_inner);
+            store.subscribers.push((topics, sender));
+            let messages = futures_util::stream::poll_fn(move |cx| receiver.poll_recv(cx));
+            future::ready(Ok(messages))
+        })
+    }
+
+    fn run(&mut self, packed: &[u8]) -> RedisResult<Vec<RedisResult<Value>>> {
+        let commands = parse_commands(packed)?;
+        let mut store = self.store.lock().unwrap_or_else(PoisonError::into_inner);
+        Ok(commands
+            .into_iter()
+            .map(|args| self.session.execute(&mut store, args))
+            .collect())
+    }
+
+    /// Runs a single command, or returns `None` if it is a blocking command
+    /// that found nothing and should be tried again.
+    fn try_command(
+        &mut self,
+        packed: &[u8],
+        deadline: Option<Instant>,
+    ) -> Option<RedisResult<Value>> {
+        let reply = match self.run(packed) {
+            Ok(replies) => replies.into_iter().next().unwrap_or(Ok(Value::Nil)),
+            Err(err) => Err(err),
+        };
+        match deadline {
+            Some(deadline) if matches!(reply, Ok(Value::Nil)) && Instant::now() < deadline => None,
+            _ => Some(reply),
+        }
+    }
+
+    /// Replaces the `$` ids of an `XREAD` with the current last ids, so that
+    /// retries of a blocking read wait for entries added after the first.
+    fn pin_last_ids(&mut self, packed: &[u8]) -> Vec<u8> {
+        let Ok(mut commands) = parse_commands(packed) else {
+            return packed.to_vec();
+        };
+        let [args] = commands.as_mut_slice() else {
+            return packed.to_vec();
+        };
+        let streams = args
+            .iter()
+            .position(|arg| arg.eq_ignore_ascii_case(b"STREAMS"));
+        let (Some(at), true) = (streams, args[0].eq_ignore_ascii_case(b"XREAD")) else {
+            return packed.to_vec();
+        };
+        let streams = &mut args[at + 1..];
+        let (keys, ids) = streams.split_at_mut(streams.len() / 2);
+        let mut store = self.store.lock().unwrap_or_else(PoisonError::into_inner);
+        for (key, id) in keys.iter().zip(ids) {
+            if id == b"$" {
+                let last_id = store.stream(key).ok().flatten().map(|s| s.last_id);
+                let (millis, seq) = last_id.unwrap_or_default();
+                *id = format!("{millis}-{seq}").into_bytes();
+            }
+        }
+        let mut cmd = Cmd::new();
+        for arg in args.iter() {
+            cmd.arg(arg.as_slice());
+        }
+        cmd.get_packed_command()
+    }
+}
+
+impl Clone for MemoryConnection {
+    fn clone(&self) -> Self {
+        MemoryConnection {
+            store: self.store.clone(),
+            session: Session::default(),
+        }
+    }
+}
+
+// Clones are separate clients, each with its own `WATCH` and `MULTI` state.
+impl Dedicated for MemoryConnection {}
+
+impl Drop for MemoryConnection {
+    fn drop(&mut self) {
+        let mut store = self.store.lock().unwrap_or_else(PoisonError::into_inner);
+        self.session.unwatch(&mut store);
+    }
+}
+
+impl ConnectionLike for MemoryConnection {
+    fn req_packed_command(&mut self, cmd: &[u8]) -> RedisResult<Value> {
+        let deadline = blocking_deadline(cmd);
+        let pinned;
+        let cmd = match deadline {
+            Some(_) => {
+                pinned = self.pin_last_ids(cmd);
+                &pinned
+            }
+            None => cmd,
+        };
+        loop {
+            match self.try_command(cmd, deadline) {
+                Some(reply) => return reply,
+                None => thread::sleep(POLL_INTERVAL),
+            }
+        }
+    }
+
+    fn req_packed_commands(
+        &mut self,
+        cmd: &[u8],
+        offset: usize,
+        count: usize,
+    ) -> RedisResult<Vec<Value>> {
+        let mut first_err = None;
+        let mut rv = Vec::with_capacity(count);
+        for (idx, reply) in self.run(cmd)?.into_iter().take(offset + count).enumerate() {
+            match reply {
+                Ok(item) if idx >= offset => rv.push(item),
+                Ok(_) => {}
+                Err(err) => {
+                    first_err.get_or_insert(err);
+                }
+            }
+        }
+        first_err.map_or(Ok(rv), Err)
+    }
+
+    fn get_db(&self) -> i64 {
+        0
+    }
+
+    fn check_connection(&mut self) -> bool {
+        true
+    }
+
+    fn is_open(&self) -> bool {
+        true
+    }
+}
+
+impl aio::ConnectionLike for MemoryConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        let packed = cmd.get_packed_command();
+        let deadline = blocking_deadline(&packed);
+        if deadline.is_none() {
+            return Box::pin(future::ready(ConnectionLike::req_packed_command(
+                self, &packed,
+            )));
+        }
+        let packed = self.pin_last_ids(&packed);
+        Box::pin(async move {
+            loop {
+                match self.try_command(&packed, deadline) {
+                    Some(reply) => return reply,
+                    None => tokio::time::sleep(POLL_INTERVAL).await,
+                }
+            }
+        })
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        let replies =
+            ConnectionLike::req_packed_commands(self, &cmd.get_packed_pipeline(), offset, count);
+        Box::pin(future::ready(replies))
+    }
+
+    fn get_db(&self) -> i64 {
+        0
+    }
+}
+
+enum Object {
+    String(Vec<u8>),
+    Set(BTreeSet<Vec<u8>>),
+    Hash(BTreeMap<Vec<u8>, Vec<u8>>),
+    SortedSet(BTreeMap<Vec<u8>, f64>),
+    List(VecDeque<Vec<u8>>),
+    Stream(Stream),
+}
+
+struct Entry {
+    object: Object,
+    expires_at: Option<Instant>,
+}
+
+impl Entry {
+    fn new(object: Object) -> Self {
+        Entry {
+            object,
+            expires_at: None,
+        }
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+}
+
+/// A stream entry id, as milliseconds and sequence number.
+type StreamId = (u64, u64);
+
+/// The fields and values of each entry, interleaved.
+type StreamEntries = BTreeMap<StreamId, Vec<Vec<u8>>>;
+
+#[derive(Default)]
+struct Stream {
+    entries: StreamEntries,
+    last_id: StreamId,
+    groups: HashMap<Vec<u8>, Group>,
+}
+
+impl Stream {
+    /// Drops the oldest entries beyond `maxlen` and returns how many.
+    fn trim(&mut self, maxlen: usize) -> usize {
+        let excess = self.entries.len().saturating_sub(maxlen);
+        for _ in 0..excess {
+            self.entries.pop_first();
+        }
+        excess
+    }
+}
+
+struct Group {
+    last_delivered: StreamId,
+    pending: BTreeMap<StreamId, Pending>,
+}
+
+/// An entry delivered to a consumer and not acknowledged yet.
+struct Pending {
+    consumer: Vec<u8>,
+    delivered_at: Instant,
+    deliveries: i64,
+}
+
+impl Pending {
+    fn idle_millis(&self, now: Instant) -> i64 {
+        now.duration_since(self.delivered_at).as_millis() as i64
+    }
+}
+
+/// The transaction state of one client.
+#[derive(Default)]
+struct Session {
+    queued: Option<Vec<Vec<Vec<u8>>>>,
+    watched: Vec<(Vec<u8>, Watched)>,
+}
+
+impl Session {
+    fn execute(&mut self, store: &mut Store, args: Vec<Vec<u8>>) -> RedisResult<Value> {
+        let name = args
+            .first()
+            .map(|name| String::from_utf8_lossy(name).to_ascii_uppercase())
+            .unwrap_or_default();
+        let in_multi = self.queued.is_some();
+        match name.as_str() {
+            "MULTI" if in_multi => Err(reply_error("ERR MULTI calls can not be nested")),
+            "MULTI" => {
+                self.queued = Some(Vec::new());
+                Ok(Value::Okay)
+            }
+            "EXEC" | "DISCARD" if !in_multi => {
+                Err(reply_error(&format!("ERR {name} without MULTI")))
+            }
+            "EXEC" => {
+                let queued = self.queued.take().unwrap_or_default();
+                let aborted = self
+                    .watched
+                    .iter()
+                    .any(|(key, watched)| store.changed(key, watched));
+                self.unwatch(store);
+                if aborted {
+                    return Ok(Value::Nil);
+                }
+                // Every command is applied even if one fails, as on a server.
+                // `Value` has no error variant in this version of redis, and
+                // its parser turns an error inside the `EXEC` array into an
+                // error for the whole reply, so that is what callers see from
+                // a server too.
+                let replies: Vec<_> = queued.iter().map(|args| store.apply(args)).collect();
+                replies
+                    .into_iter()
+                    .collect::<RedisResult<_>>()
+                    .map(Value::Bulk)
+            }
+            "DISCARD" => {
+                self.queued = None;
+                self.unwatch(store);
+                Ok(Value::Okay)
+            }
+            "WATCH" if in_multi => Err(reply_error("ERR WATCH inside MULTI is not allowed")),
+            "WATCH" => {
+                at_least(&name, &args[1..], 1)?;
+                for key in &args[1..] {
+                    let watched = store.watch(key);
+                    self.watched.push((key.clone(), watched));
+                }
+                Ok(Value::Okay)
+            }
+            "UNWATCH" if !in_multi => {
+                self.unwatch(store);
+                Ok(Value::Okay)
+            }
+            _ => match &mut self.queued {
+                Some(queued) => {
+                    queued.push(args);
+                    Ok(Value::Status("QUEUED".into()))
+                }
+                None => store.apply(&args),
+            },
+        }
+    }
+
+    fn unwatch(&mut self, store: &mut Store) {
+        for (key, _) in self.watched.drain(..) {
+            store.unwatch(&key);
+        }
+    }
+}
+
+/// What a client saw of a key when it started watching it.
+struct Watched {
+    version: u64,
+    alive: bool,
+}
+
+/// Counts the writes to a key while at least one client watches it.
+struct Watch {
+    watchers: usize,
+    version: u64,
+}
+
+#[derive(Default)]
+struct Store {
+    keys: HashMap<Vec<u8>, Entry>,
+    watches: HashMap<Vec<u8>, Watch>,
+    subscribers: Vec<(Vec<Topic>, mpsc::UnboundedSender<Msg>)>,
+}
+
+impl Store {
+    /// Executes a command and records the writes to watched keys.
+    fn apply(&mut self, args: &[Vec<u8>]) -> RedisResult<Value> {
+        let reply = self.execute(args)?;
+        if self.watches.is_empty() {
+            return Ok(reply);
+        }
+        let name = args[0].to_ascii_uppercase();
+        let written = match name.as_slice() {
+            b"FLUSHDB" | b"FLUSHALL" => {
+                self.watches.values_mut().for_each(|w| w.version += 1);
+                &[]
+            }
+            b"DEL" => &args[1..],
+            b"SET" | b"INCR" | b"DECR" | b"INCRBY" | b"DECRBY" | b"EXPIRE" | b"PEXPIRE"
+            | b"PERSIST" | b"SADD" | b"SREM" | b"HSET" | b"HMSET" | b"HDEL" | b"ZADD" | b"ZREM"
+            | b"LPUSH" | b"RPUSH" | b"LTRIM" | b"LINSERT" | b"XADD" | b"XTRIM" | b"XACK"
+            | b"XAUTOCLAIM" => &args[1..2],
+            b"XGROUP" => &args[2..3],
+            // Pops only write when they found an item.
+            _ if reply == Value::Nil => &[],
+            b"LPOP" | b"RPOP" => &args[1..2],
+            b"LMOVE" | b"BLMOVE" => &args[1..3],
+            b"BLPOP" | b"BRPOP" => &args[1..args.len() - 1],
+            _ => &[],
+        };
+        for key in written {
+            if let Some(watch) = self.watches.get_mut(key) {
+                watch.version += 1;
+            }
+        }
+        Ok(reply)
+    }
+
+    fn watch(&mut self, key: &[u8]) -> Watched {
+        let alive = self.live(key).is_some();
+        let watch = self.watches.entry(key.to_vec()).or_insert(Watch {
+            watchers: 0,
+            version: 0,
+        });
+        watch.watchers += 1;
+        Watched {
+            version: watch.version,
+            alive,
+        }
+    }
+
+    fn unwatch(&mut self, key: &[u8]) {
+        if let Some(watch) = self.watches.get_mut(key) {
+            watch.watchers -= 1;
+            if watch.watchers == 0 {
+                self.watches.remove(key);
+            }
+        }
+    }
+
+    /// Whether a watched key was written to or has expired since.
+    fn changed(&mut self, key: &[u8], watched: &Watched) -> bool {
+        let version = self.watches.get(key).map_or(0, |w| w.version);
+        version != watched.version || (watched.alive && self.live(key).is_none())
+    }
+
+    fn execute(&mut self, args: &[Vec<u8>]) -> RedisResult<Value> {
+        let Some((name, args)) = args.split_first() else {
+            return Err(reply_error("ERR empty command"));
+        };
+        let name = String::from_utf8_lossy(name).to_ascii_uppercase();
+        match name.as_str() {
+            "PING" => Ok(Value::Status("PONG".into())),
+            "FLUSHDB" | "FLUSHALL" => {
+                self.keys.clear();
+                Ok(Value::Okay)
+            }
+            "GET" => {
+                let [key] = exact(&name, args)?;
+                Ok(self
+                    .string(key)?
+                    .map_or(Value::Nil, |v| Value::Data(v.clone())))
+            }
+            "SET" => {
+                at_least(&name, args, 2)?;
+                self.set_string(&args[0], &args[1], &args[2..])
+            }
+            "DEL" => {
+                at_least(&name, args, 1)?;
+                let removed = args.iter().filter(|key| self.remove(key)).count();
+                Ok(Value::Int(removed as i64))
+            }
+            "EXISTS" => {
+                at_least(&name, args, 1)?;
+                let found = args.iter().filter(|key| self.live(key).is_some()).count();
+                Ok(Value::Int(found as i64))
+            }
+            "INCR" | "DECR" => {
+                let [key] = exact(&name, args)?;
+                self.incr_by(key, if name == "INCR" { 1 } else { -1 })
+            }
+            "INCRBY" | "DECRBY" => {
+                let [key, delta] = exact(&name, args)?;
+                let delta = parse_int(delta)?;
+                self.incr_by(key, if name == "INCRBY" { delta } else { -delta })
+            }
+            "EXPIRE" | "PEXPIRE" => {
+                let [key, ttl] = exact(&name, args)?;
+                let ttl = parse_int(ttl)?;
+                let millis = if name == "EXPIRE" {
+                    ttl.saturating_mul(1000)
+                } else {
+                    ttl
+                };
+                Ok(Value::Int(self.expire(key, millis) as i64))
+            }
+            "PERSIST" => {
+                let [key] = exact(&name, args)?;
+                let persisted = match self.live(key) {
+                    Some(entry) => entry.expires_at.take().is_some(),
+                    None => false,
+                };
+                Ok(Value::Int(persisted as i64))
+            }
+            "TTL" | "PTTL" => {
+                let [key] = exact(&name, args)?;
+                let ttl = match self.live(key) {
+                    None => -2,
+                    Some(Entry {
+                        expires_at: None, ..
+                    }) => -1,
+                    Some(Entry {
+                        expires_at: Some(at),
+                        ..
+                    }) => {
+                        let millis =
+                            at.saturating_duration_since(Instant::now()).as_millis() as i64;
+                        if name == "TTL" {
+                            (millis + 500) / 1000
+                        } else {
+                            millis
+                        }
+                    }
+                };
+                Ok(Value::Int(ttl))
+            }
+            "SADD" => {
+                at_least(&name, args, 2)?;
+                let set = self.set_or_default(&args[0])?;
+                let added = args[1..]
+                    .iter()
+                    .filter(|member| set.insert(member.to_vec()))
+                    .count();
+                Ok(Value::Int(added as i64))
+            }
+            "SMEMBERS" => {
+                let [key] = exact(&name, args)?;
+                let members = self.set(key)?.map_or_else(Vec::new, |set| {
+                    set.iter().map(|m| Value::Data(m.clone())).collect()
+                });
+                Ok(Value::Bulk(members))
+            }
+            "SREM" => {
+                at_least(&name, args, 2)?;
+                let removed = match self.set(&args[0])? {
+                    Some(set) => args[1..].iter().filter(|m| set.remove(*m)).count(),
+                    None => 0,
+                };
+                self.remove_if_empty(&args[0]);
+                Ok(Value::Int(removed as i64))
+            }
+            "SCARD" => {
+                let [key] = exact(&name, args)?;
+                Ok(Value::Int(self.set(key)?.map_or(0, |set| set.len()) as i64))
+            }
+            "SISMEMBER" => {
+                let [key, member] = exact(&name, args)?;
+                let found = self.set(key)?.is_some_and(|set| set.contains(member));
+                Ok(Value::Int(found as i64))
+            }
+            "HSET" | "HMSET" => {
+                if args.len() < 3 || args.len() % 2 == 0 {
+                    return Err(wrong_arity(&name));
+                }
+                let hash = self.hash_or_default(&args[0])?;
+                let added = args[1..]
+                    .chunks(2)
+                    .filter(|pair| hash.insert(pair[0].clone(), pair[1].clone()).is_none())
+                    .count();
+                if name == "HSET" {
+                    Ok(Value::Int(added as i64))
+                } else {
+                    Ok(Value::Okay)
+                }
+            }
+            "HGET" => {
+                let [key, field] = exact(&name, args)?;
+                let value = self.hash(key)?.and_then(|hash| hash.get(field));
+                Ok(value.map_or(Value::Nil, |v| Value::Data(v.clone())))
+            }
+            "HMGET" => {
+                at_least(&name, args, 2)?;
+                let hash = self.hash(&args[0])?;
+                let values = args[1..]
+                    .iter()
+                    .map(|field| match hash.as_ref().and_then(|h| h.get(field)) {
+                        Some(v) => Value::Data(v.clone()),
+                        None => Value::Nil,
+                    })
+                    .collect();
+                Ok(Value::Bulk(values))
+            }
+            "HGETALL" => {
+                let [key] = exact(&name, args)?;
+                let items = self.hash(key)?.map_or_else(Vec::new, |hash| {
+                    hash.iter()
+                        .flat_map(|(f, v)| [Value::Data(f.clone()), Value::Data(v.clone())])
+                        .collect()
+                });
+                Ok(Value::Bulk(items))
+            }
+            "HEXISTS" => {
+                let [key, field] = exact(&name, args)?;
+                let found = self.hash(key)?.is_some_and(|hash| hash.contains_key(field));
+                Ok(Value::Int(found as i64))
+            }
+            "HDEL" => {
+                at_least(&name, args, 2)?;
+                let removed = match self.hash(&args[0])? {
+                    Some(hash) => args[1..]
+                        .iter()
+                        .filter(|f| hash.remove(*f).is_some())
+                        .count(),
+                    None => 0,
+                };
+                self.remove_if_empty(&args[0]);
+                Ok(Value::Int(removed as i64))
+            }
+            "HLEN" => {
+                let [key] = exact(&name, args)?;
+                Ok(Value::Int(
+                    self.hash(key)?.map_or(0, |hash| hash.len()) as i64
+                ))
+            }
+            "ZADD" => {
+                if args.len() < 3 || args.len() % 2 == 0 {
+                    return Err(wrong_arity(&name));
+                }
+                let items = args[1..]
+                    .chunks(2)
+                    .map(|pair| Ok((parse_float(&pair[0])?, pair[1].clone())))
+                    .collect::<RedisResult<Vec<_>>>()?;
+                let zset = self.sorted_set_or_default(&args[0])?;
+                let added = items
+                    .into_iter()
+                    .filter(|(score, member)| zset.insert(member.clone(), *score).is_none())
+                    .count();
+                Ok(Value::Int(added as i64))
+            }
+            "ZRANGEBYSCORE" | "ZREVRANGEBYSCORE" => {
+                at_least(&name, args, 3)?;
+                let rev = name == "ZREVRANGEBYSCORE";
+                let (min, max) = if rev {
+                    (&args[2], &args[1])
+                } else {
+                    (&args[1], &args[2])
+                };
+                let (min, max) = (ScoreBound::parse(min)?, ScoreBound::parse(max)?);
+                let (mut with_scores, mut limit) = (false, None);
+                let mut options = args[3..].iter();
+                while let Some(option) = options.next() {
+                    match option.to_ascii_uppercase().as_slice() {
+                        b"WITHSCORES" => with_scores = true,
+                        b"LIMIT" => match (options.next(), options.next()) {
+                            (Some(offset), Some(count)) => {
+                                limit = Some((parse_int(offset)?, parse_int(count)?))
+                            }
+                            _ => return Err(syntax_error()),
+                        },
+                        _ => return Err(syntax_error()),
+                    }
+                }
+                let mut items: Vec<(f64, Vec<u8>)> =
+                    self.sorted_set(&args[0])?.map_or_else(Vec::new, |zset| {
+                        zset.iter()
+                            .filter(|(_, score)| min.below(**score) && max.above(**score))
+                            .map(|(member, score)| (*score, member.clone()))
+                            .collect()
+                    });
+                items.sort_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.cmp(&b.1)));
+                if rev {
+                    items.reverse();
+                }
+                let items: Box<dyn Iterator<Item = _>> = match limit {
+                    Some((offset, count)) if offset >= 0 => {
+                        let items = items.into_iter().skip(offset as usize);
+                        if count >= 0 {
+                            Box::new(items.take(count as usize))
+                        } else {
+                            Box::new(items)
+                        }
+                    }
+                    Some(_) => Box::new(std::iter::empty()),
+                    None => Box::new(items.into_iter()),
+                };
+                let mut reply = Vec::new();
+                for (score, member) in items {
+                    reply.push(Value::Data(member));
+                    if with_scores {
+                        reply.push(Value::Data(score.to_string().into_bytes()));
+                    }
+                }
+                Ok(Value::Bulk(reply))
+            }
+            "ZREM" => {
+                at_least(&name, args, 2)?;
+                let removed = match self.sorted_set(&args[0])? {
+                    Some(zset) => args[1..]
+                        .iter()
+                        .filter(|m| zset.remove(*m).is_some())
+                        .count(),
+                    None => 0,
+                };
+                self.remove_if_empty(&args[0]);
+                Ok(Value::Int(removed as i64))
+            }
+            "ZCARD" => {
+                let [key] = exact(&name, args)?;
+                Ok(Value::Int(
+                    self.sorted_set(key)?.map_or(0, |zset| zset.len()) as i64,
+                ))
+            }
+            "ZSCORE" => {
+                let [key, member] = exact(&name, args)?;
+                let score = self.sorted_set(key)?.and_then(|zset| zset.get(member));
+                Ok(score.map_or(Value::Nil, |s| Value::Data(s.to_string().into_bytes())))
+            }
+            "LPUSH" | "RPUSH" => {
+                at_least(&name, args, 2)?;
+                let list = self.list_or_default(&args[0])?;
+                for value in &args[1..] {
+                    if name == "LPUSH" {
+                        list.push_front(value.clone());
+                    } else {
+                        list.push_back(value.clone());
+                    }
+                }
+                Ok(Value::Int(list.len() as i64))
+            }
+            "LPOP" | "RPOP" => {
+                let [key] = exact(&name, args)?;
+                let popped = self.pop(key, name == "LPOP")?;
+                Ok(popped.map_or(Value::Nil, Value::Data))
+            }
+            "BLPOP" | "BRPOP" => {
+                let Some((timeout, keys @ [_, ..])) = args.split_last() else {
+                    return Err(wrong_arity(&name));
+                };
+                parse_timeout(timeout)?;
+                for key in keys {
+                    if let Some(value) = self.pop(key, name == "BLPOP")? {
+                        return Ok(Value::Bulk(vec![
+                            Value::Data(key.clone()),
+                            Value::Data(value),
+                        ]));
+                    }
+                }
+                Ok(Value::Nil)
+            }
+            "LMOVE" | "BLMOVE" => {
+                let (source, destination, from, to) = match args {
+                    [source, destination, from, to] if name == "LMOVE" => {
+                        (source, destination, from, to)
+                    }
+                    [source, destination, from, to, timeout] if name == "BLMOVE" => {
+                        parse_timeout(timeout)?;
+                        (source, destination, from, to)
+                    }
+                    _ => return Err(wrong_arity(&name)),
+                };
+                let (from, to) = (parse_direction(from)?, parse_direction(to)?);
+                self.list(destination)?;
+                let Some(value) = self.pop(source, from)? else {
+                    return Ok(Value::Nil);
+                };
+                let list = self.list_or_default(destination)?;
+                if to {
+                    list.push_front(value.clone());
+                } else {
+                    list.push_back(value.clone());
+                }
+                Ok(Value::Data(value))
+            }
+            "LRANGE" => {
+                let [key, start, stop] = exact(&name, args)?;
+                let (start, stop) = (parse_int(start)?, parse_int(stop)?);
+                let items = self.list(key)?.map_or_else(Vec::new, |list| {
+                    list.range(list_range(list.len(), start, stop))
+                        .map(|v| Value::Data(v.clone()))
+                        .collect()
+                });
+                Ok(Value::Bulk(items))
+            }
+            "LTRIM" => {
+                let [key, start, stop] = exact(&name, args)?;
+                let (start, stop) = (parse_int(start)?, parse_int(stop)?);
+                if let Some(list) = self.list(key)? {
+                    let range = list_range(list.len(), start, stop);
+                    list.truncate(range.end);
+                    list.drain(..range.start);
+                }
+                self.remove_if_empty(key);
+                Ok(Value::Okay)
+            }
+            "LINDEX" => {
+                let [key, index] = exact(&name, args)?;
+                let index = parse_int(index)?;
+                let value = self.list(key)?.and_then(|list| {
+                    let index = if index < 0 {
+                        list.len() as i64 + index
+                    } else {
+                        index
+                    };
+                    usize::try_from(index).ok().and_then(|i| list.get(i))
+                });
+                Ok(value.map_or(Value::Nil, |v| Value::Data(v.clone())))
+            }
+            "LINSERT" => {
+                let [key, place, pivot, value] = exact(&name, args)?;
+                let after = match place.to_ascii_uppercase().as_slice() {
+                    b"BEFORE" => false,
+                    b"AFTER" => true,
+                    _ => return Err(syntax_error()),
+                };
+                let Some(list) = self.list(key)? else {
+                    return Ok(Value::Int(0));
+                };
+                let Some(at) = list.iter().position(|item| item == pivot) else {
+                    return Ok(Value::Int(-1));
+                };
+                list.insert(at + after as usize, value.clone());
+                Ok(Value::Int(list.len() as i64))
+            }
+            "LLEN" => {
+                let [key] = exact(&name, args)?;
+                Ok(Value::Int(
+                    self.list(key)?.map_or(0, |list| list.len()) as i64
+                ))
+            }
+            "PUBLISH" => {
+                let [channel, payload] = exact(&name, args)?;
+                self.subscribers.retain(|(_, sender)| !sender.is_closed());
+                let mut received = 0;
+                for (topics, sender) in &self.subscribers {
+                    for topic in topics {
+                        let kind = match topic {
+                            Topic::Channel(name) if name.as_bytes() == channel.as_slice() => {
+                                vec![b"message".to_vec()]
+                            }
+                            Topic::Pattern(pattern) if glob_match(pattern.as_bytes(), channel) => {
+                                vec![b"pmessage".to_vec(), pattern.as_bytes().to_vec()]
+                            }
+                            _ => continue,
+                        };
+                        let values = kind
+                            .into_iter()
+                            .chain([channel.clone(), payload.clone()])
+                            .map(Value::Data)
+                            .collect();
+                        if let Some(msg) = Msg::from_value(&Value::Bulk(values)) {
+                            received += i64::from(sender.send(msg).is_ok());
+                        }
+                    }
+                }
+                Ok(Value::Int(received))
+            }
+            "XADD" => {
+                at_least(&name, args, 4)?;
+                let key = &args[0];
+                let mut rest = &args[1..];
+                let mut create = true;
+                let mut maxlen = None;
+                loop {
+                    match rest.first().map(|arg| arg.to_ascii_uppercase()).as_deref() {
+                        Some(b"NOMKSTREAM") => {
+                            create = false;
+                            rest = &rest[1..];
+                        }
+                        Some(b"MAXLEN") => {
+                            let (len, used) = parse_maxlen(rest)?;
+                            maxlen = Some(len);
+                            rest = &rest[used..];
+                        }
+                        _ => break,
+                    }
+                }
+                let Some((id, fields)) = rest.split_first() else {
+                    return Err(wrong_arity(&name));
+                };
+                if fields.is_empty() || fields.len() % 2 != 0 {
+                    return Err(wrong_arity(&name));
+                }
+                let id = match id.as_slice() {
+                    b"*" => None,
+                    id => Some(parse_stream_id(id, 0)?),
+                };
+                if !create && self.stream(key)?.is_none() {
+                    return Ok(Value::Nil);
+                }
+                let stream = self.stream_or_default(key)?;
+                let id = id.unwrap_or_else(|| {
+                    let millis = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_millis() as u64;
+                    match stream.last_id {
+                        (last, seq) if last >= millis => (last, seq + 1),
+                        _ => (millis, 0),
+                    }
+                });
+                if id <= stream.last_id {
+                    return Err(reply_error(
+                        "ERR The ID specified in XADD is equal or smaller than the target stream top item",
+                    ));
+                }
+                stream.entries.insert(id, fields.to_vec());
+                stream.last_id = id;
+                if let Some(maxlen) = maxlen {
+                    stream.trim(maxlen);
+                }
+                Ok(stream_id_value(id))
+            }
+            "XTRIM" => {
+                at_least(&name, args, 3)?;
+                if !args[1].eq_ignore_ascii_case(b"MAXLEN") {
+                    return Err(syntax_error());
+                }
+                let (maxlen, used) = parse_maxlen(&args[1..])?;
+                if used + 1 != args.len() {
+                    return Err(syntax_error());
+                }
+                let trimmed = self
+                    .stream(&args[0])?
+                    .map_or(0, |stream| stream.trim(maxlen));
+                Ok(Value::Int(trimmed as i64))
+            }
+            "XLEN" => {
+                let [key] = exact(&name, args)?;
+                Ok(Value::Int(
+                    self.stream(key)?.map_or(0, |stream| stream.entries.len()) as i64,
+                ))
+            }
+            "XRANGE" => {
+                let (key, start, end, count) = match args {
+                    [key, start, end] => (key, start, end, usize::MAX),
+                    [key, start, end, option, count] if option.eq_ignore_ascii_case(b"COUNT") => {
+                        (key, start, end, parse_count(count)?)
+                    }
+                    [_, _, _, ..] => return Err(syntax_error()),
+                    _ => return Err(wrong_arity(&name)),
+                };
+                let (start, end) = (parse_stream_id(start, 0)?, parse_stream_id(end, u64::MAX)?);
+                let entries = match self.stream(key)? {
+                    Some(stream) if start <= end => stream
+                        .entries
+                        .range(start..=end)
+                        .take(count)
+                        .map(|(id, fields)| stream_entry(*id, fields))
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                Ok(Value::Bulk(entries))
+            }
+            "XGROUP" => {
+                let Some((subcommand, args)) = args.split_first() else {
+                    return Err(wrong_arity(&name));
+                };
+                if !subcommand.eq_ignore_ascii_case(b"CREATE") {
+                    return Err(reply_error(&format!(
+                        "ERR unknown subcommand '{}'",
+                        String::from_utf8_lossy(subcommand)
+                    )));
+                }
+                let (key, group, id, create) = match args {
+                    [key, group, id] => (key, group, id, false),
+                    [key, group, id, option] if option.eq_ignore_ascii_case(b"MKSTREAM") => {
+                        (key, group, id, true)
+                    }
+                    _ => return Err(syntax_error()),
+                };
+                if !create && self.stream(key)?.is_none() {
+                    return Err(reply_error(
+                        "ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.",
+                    ));
+                }
+                let stream = self.stream_or_default(key)?;
+                if stream.groups.contains_key(group) {
+                    return Err(reply_error("BUSYGROUP Consumer Group name already exists"));
+                }
+                let last_delivered = match id.as_slice() {
+                    b"$" => stream.last_id,
+                    id => parse_stream_id(id, 0)?,
+                };
+                stream.groups.insert(
+                    group.clone(),
+                    Group {
+                        last_delivered,
+                        pending: BTreeMap::new(),
+                    },
+                );
+                Ok(Value::Okay)
+            }
+            "XREAD" | "XREADGROUP" => {
+                let mut group = None;
+                let mut count = usize::MAX;
+                let mut noack = false;
+                let mut rest = args;
+                loop {
+                    let Some((option, tail)) = rest.split_first() else {
+                        return Err(syntax_error());
+                    };
+                    rest = tail;
+                    match option.to_ascii_uppercase().as_slice() {
+                        b"COUNT" => {
+                            let (value, tail) = rest.split_first().ok_or_else(syntax_error)?;
+                            count = parse_count(value)?;
+                            rest = tail;
+                        }
+                        b"BLOCK" => {
+                            let (value, tail) = rest.split_first().ok_or_else(syntax_error)?;
+                            parse_int(value)?;
+                            rest = tail;
+                        }
+                        b"NOACK" if name == "XREADGROUP" => noack = true,
+                        b"GROUP" if name == "XREADGROUP" => {
+                            let [group_name, consumer, tail @ ..] = rest else {
+                                return Err(syntax_error());
+                            };
+                            group = Some((group_name, consumer));
+                            rest = tail;
+                        }
+                        b"STREAMS" => break,
+                        _ => return Err(syntax_error()),
+                    }
+                }
+                if rest.is_empty() || rest.len() % 2 != 0 {
+                    return Err(reply_error(
+                        "ERR Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified.",
+                    ));
+                }
+                if name == "XREADGROUP" && group.is_none() {
+                    return Err(reply_error("ERR Missing GROUP option for XREADGROUP"));
+                }
+                let (keys, ids) = rest.split_at(rest.len() / 2);
+                let mut replies = Vec::new();
+                for (key, id) in keys.iter().zip(ids) {
+                    let entries = match group {
+                        None => self.read_stream(key, id, count)?,
+                        Some((group, consumer)) => {
+                            self.read_group(key, group, consumer, id, count, noack)?
+                        }
+                    };
+                    if let Some(entries) = entries {
+                        replies.push(Value::Bulk(vec![
+                            Value::Data(key.clone()),
+                            Value::Bulk(entries),
+                        ]));
+                    }
+                }
+                match replies.is_empty() {
+                    true => Ok(Value::Nil),
+                    false => Ok(Value::Bulk(replies)),
+                }
+            }
+            "XACK" => {
+                at_least(&name, args, 3)?;
+                let ids = args[2..]
+                    .iter()
+                    .map(|id| parse_stream_id(id, 0))
+                    .collect::<RedisResult<Vec<_>>>()?;
+                let group = self
+                    .stream(&args[0])?
+                    .and_then(|stream| stream.groups.get_mut(&args[1]));
+                let acked = group.map_or(0, |group| {
+                    ids.iter()
+                        .filter(|id| group.pending.remove(id).is_some())
+                        .count()
+                });
+                Ok(Value::Int(acked as i64))
+            }
+            "XPENDING" => {
+                at_least(&name, args, 2)?;
+                let (_, group) = self.group(&args[0], &args[1])?;
+                let rest = &args[2..];
+                if rest.is_empty() {
+                    let (Some(first), Some(last)) = (
+                        group.pending.keys().next(),
+                        group.pending.keys().next_back(),
+                    ) else {
+                        return Ok(Value::Bulk(vec![
+                            Value::Int(0),
+                            Value::Nil,
+                            Value::Nil,
+                            Value::Nil,
+                        ]));
+                    };
+                    let mut consumers = BTreeMap::<&[u8], usize>::new();
+                    for pending in group.pending.values() {
+                        *consumers.entry(&pending.consumer).or_default() += 1;
+                    }
+                    let consumers = consumers
+                        .into_iter()
+                        .map(|(consumer, count)| {
+                            Value::Bulk(vec![
+                                Value::Data(consumer.to_vec()),
+                                Value::Data(count.to_string().into_bytes()),
+                            ])
+                        })
+                        .collect();
+                    return Ok(Value::Bulk(vec![
+                        Value::Int(group.pending.len() as i64),
+                        stream_id_value(*first),
+                        stream_id_value(*last),
+                        Value::Bulk(consumers),
+                    ]));
+                }
+                let (min_idle, rest) = match rest {
+                    [option, idle, rest @ ..] if option.eq_ignore_ascii_case(b"IDLE") => {
+                        (parse_int(idle)?, rest)
+                    }
+                    _ => (0, rest),
+                };
+                let (start, end, count, consumer) = match rest {
+                    [start, end, count] => (start, end, count, None),
+                    [start, end, count, consumer] => (start, end, count, Some(consumer)),
+                    _ => return Err(syntax_error()),
+                };
+                let (start, end) = (parse_stream_id(start, 0)?, parse_stream_id(end, u64::MAX)?);
+                let count = parse_count(count)?;
+                if start > end {
+                    return Ok(Value::Bulk(Vec::new()));
+                }
+                let now = Instant::now();
+                let pending = group
+                    .pending
+                    .range(start..=end)
+                    .map(|(id, pending)| (id, pending, pending.idle_millis(now)))
+                    .filter(|(_, pending, idle)| {
+                        *idle >= min_idle && consumer.is_none_or(|c| *c == pending.consumer)
+                    })
+                    .take(count)
+                    .map(|(id, pending, idle)| {
+                        Value::Bulk(vec![
+                            stream_id_value(*id),
+                            Value::Data(pending.consumer.clone()),
+                            Value::Int(idle),
+                            Value::Int(pending.deliveries),
+                        ])
+                    })
+                    .collect();
+                Ok(Value::Bulk(pending))
+            }
+            "XAUTOCLAIM" => {
+                let [key, group, consumer, min_idle, start, options @ ..] = args else {
+                    return Err(wrong_arity(&name));
+                };
+                let mut count = 100;
+                let mut justid = false;
+                let mut options = options.iter();
+                while let Some(option) = options.next() {
+                    match option.to_ascii_uppercase().as_slice() {
+                        b"COUNT" => count = parse_count(options.next().ok_or_else(syntax_error)?)?,
+                        b"JUSTID" => justid = true,
+                        _ => return Err(syntax_error()),
+                    }
+                }
+                let min_idle = parse_int(min_idle)?;
+                let start = parse_stream_id(start, 0)?;
+                let (entries, group) = self.group(key, group)?;
+                let now = Instant::now();
+                let mut next = (0, 0);
+                let mut claimed = Vec::new();
+                let mut deleted = Vec::new();
+                let ids: Vec<StreamId> = group.pending.range(start..).map(|(id, _)| *id).collect();
+                for id in ids {
+                    if claimed.len() == count {
+                        next = id;
+                        break;
+                    }
+                    let Some(pending) = group.pending.get_mut(&id) else {
+                        continue;
+                    };
+                    if pending.idle_millis(now) < min_idle {
+                        continue;
+                    }
+                    let Some(fields) = entries.get(&id) else {
+                        group.pending.remove(&id);
+                        deleted.push(stream_id_value(id));
+                        continue;
+                    };
+                    pending.consumer = consumer.clone();
+                    pending.delivered_at = now;
+                    if justid {
+                        claimed.push(stream_id_value(id));
+                    } else {
+                        pending.deliveries += 1;
+                        claimed.push(stream_entry(id, fields));
+                    }
+                }
+                Ok(Value::Bulk(vec![
+                    stream_id_value(next),
+                    Value::Bulk(claimed),
+                    Value::Bulk(deleted),
+                ]))
+            }
+            "SCAN" => {
+                at_least(&name, args, 1)?;
+                parse_int(&args[0])?;
+                let mut pattern: &[u8] = b"*";
+                let mut options = args[1..].iter();
+                while let Some(option) = options.next() {
+                    let value = options.next().ok_or_else(syntax_error)?;
+                    match option.to_ascii_uppercase().as_slice() {
+                        b"MATCH" => pattern = value,
+                        b"COUNT" => {
+                            parse_int(value)?;
+                        }
+                        _ => return Err(syntax_error()),
+                    }
+                }
+                let now = Instant::now();
+                self.keys.retain(|_, entry| !entry.is_expired(now));
+                let keys = self
+                    .keys
+                    .keys()
+                    .filter(|key| glob_match(pattern, key))
+                    .map(|key| Value::Data(key.clone()))
+                    .collect();
+                // Every key is returned in one pass.
+                Ok(Value::Bulk(vec![
+                    Value::Data(b"0".to_vec()),
+                    Value::Bulk(keys),
+                ]))
+            }
+            _ => Err(reply_error(&format!(
+                "ERR unknown command '{}'",
+                name.to_ascii_lowercase()
+            ))),
+        }
+    }
+
+    fn live(&mut self, key: &[u8]) -> Option<&mut Entry> {
+        let now = Instant::now();
+        if self
+            .keys
+            .get(key)
+            .is_some_and(|entry| entry.is_expired(now))
+        {
+            self.keys.remove(key);
+        }
+        self.keys.get_mut(key)
+    }
+
+    fn live_or_insert(&mut self, key: &[u8], object: fn() -> Object) -> &mut Entry {
+        self.live(key);
+        self.keys
+            .entry(key.to_vec())
+            .or_insert_with(|| Entry::new(object()))
+    }
+
+    fn remove(&mut self, key: &[u8]) -> bool {
+        self.live(key).is_some() && self.keys.remove(key).is_some()
+    }
+
+    fn remove_if_empty(&mut self, key: &[u8]) {
+        let empty = match self.keys.get(key).map(|entry| &entry.object) {
+            Some(Object::Set(set)) => set.is_empty(),
+            Some(Object::Hash(hash)) => hash.is_empty(),
+            Some(Object::SortedSet(zset)) => zset.is_empty(),
+            Some(Object::List(list)) => list.is_empty(),
+            _ => false,
+        };
+        if empty {
+            self.keys.remove(key);
+        }
+    }
+
+    fn expire(&mut self, key: &[u8], millis: i64) -> bool {
+        if millis <= 0 {
+            return self.remove(key);
+        }
+        match self.live(key) {
+            Some(entry) => {
+                entry.expires_at = Some(Instant::now() + Duration::from_millis(millis as u64));
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn set_string(&mut self, key: &[u8], value: &[u8], options: &[Vec<u8>]) -> RedisResult<Value> {
+        let (mut condition, mut get, mut keep_ttl, mut expires_at) = (None, false, false, None);
+        let mut options = options.iter();
+        while let Some(option) = options.next() {
+            match option.to_ascii_uppercase().as_slice() {
+                check @ (b"NX" | b"XX") if condition.is_none() => condition = Some(check == b"NX"),
+                b"GET" => get = true,
+                b"KEEPTTL" if expires_at.is_none() => keep_ttl = true,
+                unit @ (b"EX" | b"PX" | b"EXAT" | b"PXAT") if expires_at.is_none() && !keep_ttl => {
+                    let time = parse_int(options.next().ok_or_else(syntax_error)?)?;
+                    if time <= 0 {
+                        return Err(reply_error("ERR invalid expire time in 'set' command"));
+                    }
+                    let time = time as u64;
+                    expires_at = Some(match unit {
+                        b"EX" => Instant::now() + Duration::from_secs(time),
+                        b"PX" => Instant::now() + Duration::from_millis(time),
+                        b"EXAT" => instant_at(Duration::from_secs(time)),
+                        _ => instant_at(Duration::from_millis(time)),
+                    });
+                }
+                _ => return Err(syntax_error()),
+            }
+        }
+
+        let previous = if get {
+            self.string(key)?.cloned()
+        } else {
+            None
+        };
+        let current = self.live(key).map(|entry| entry.expires_at);
+        let write = match condition {
+            Some(nx) => current.is_some() != nx,
+            None => true,
+        };
+        if write {
+            let mut entry = Entry::new(Object::String(value.to_vec()));
+            entry.expires_at = if keep_ttl {
+                current.flatten()
+            } else {
+                expires_at
+            };
+            self.keys.insert(key.to_vec(), entry);
+        }
+        Ok(match (get, previous) {
+            (true, previous) => previous.map_or(Value::Nil, Value::Data),
+            (false, _) if write => Value::Okay,
+            (false, _) => Value::Nil,
+        })
+    }
+
+    fn incr_by(&mut self, key: &[u8], delta: i64) -> RedisResult<Value> {
+        let current = match self.string(key)? {
+            Some(value) => parse_int(value)?,
+            None => 0,
+        };
+        let next = current
+            .checked_add(delta)
+            .ok_or_else(|| reply_error("ERR increment or decrement would overflow"))?;
+        let entry = self.live_or_insert(key, || Object::String(Vec::new()));
+        entry.object = Object::String(next.to_string().into_bytes());
+        Ok(Value::Int(next))
+    }
+
+    fn string(&mut self, key: &[u8]) -> RedisResult<Option<&mut Vec<u8>>> {
+        match self.live(key).map(|entry| &mut entry.object) {
+            None => Ok(None),
+            Some(Object::String(value)) => Ok(Some(value)),
+            Some(_) => Err(wrong_type()),
+        }
+    }
+
+    fn set(&mut self, key: &[u8]) -> RedisResult<Option<&mut BTreeSet<Vec<u8>>>> {
+        match self.live(key).map(|entry| &mut entry.object) {
+            None => Ok(None),
+            Some(Object::Set(set)) => Ok(Some(set)),
+            Some(_) => Err(wrong_type()),
+        }
+    }
+
+    fn set_or_default(&mut self, key: &[u8]) -> RedisResult<&mut BTreeSet<Vec<u8>>> {
+        match &mut self
+            .live_or_insert(key, || Object::Set(BTreeSet::new()))
+            .object
+        {
+            Object::Set(set) => Ok(set),
+            _ => Err(wrong_type()),
+        }
+    }
+
+    fn hash(&mut self, key: &[u8]) -> RedisResult<Option<&mut BTreeMap<Vec<u8>, Vec<u8>>>> {
+        match self.live(key).map(|entry| &mut entry.object) {
+            None => Ok(None),
+            Some(Object::Hash(hash)) => Ok(Some(hash)),
+            Some(_) => Err(wrong_type()),
+        }
+    }
+
+    fn hash_or_default(&mut self, key: &[u8]) -> RedisResult<&mut BTreeMap<Vec<u8>, Vec<u8>>> {
+        match &mut self
+            .live_or_insert(key, || Object::Hash(BTreeMap::new()))
+            .object
+        {
+            Object::Hash(hash) => Ok(hash),
+            _ => Err(wrong_type()),
+        }
+    }
+
+    fn sorted_set(&mut self, key: &[u8]) -> RedisResult<Option<&mut BTreeMap<Vec<u8>, f64>>> {
+        match self.live(key).map(|entry| &mut entry.object) {
+            None => Ok(None),
+            Some(Object::SortedSet(zset)) => Ok(Some(zset)),
+            Some(_) => Err(wrong_type()),
+        }
+    }
+
+    fn list(&mut self, key: &[u8]) -> RedisResult<Option<&mut VecDeque<Vec<u8>>>> {
+        match self.live(key).map(|entry| &mut entry.object) {
+            None => Ok(None),
+            Some(Object::List(list)) => Ok(Some(list)),
+            Some(_) => Err(wrong_type()),
+        }
+    }
+
+    fn list_or_default(&mut self, key: &[u8]) -> RedisResult<&mut VecDeque<Vec<u8>>> {
+        match &mut self
+            .live_or_insert(key, || Object::List(VecDeque::new()))
+            .object
+        {
+            Object::List(list) => Ok(list),
+            _ => Err(wrong_type()),
+        }
+    }
+
+    fn stream(&mut self, key: &[u8]) -> RedisResult<Option<&mut Stream>> {
+        match self.live(key).map(|entry| &mut entry.object) {
+            None => Ok(None),
+            Some(Object::Stream(stream)) => Ok(Some(stream)),
+            Some(_) => Err(wrong_type()),
+        }
+    }
+
+    fn stream_or_default(&mut self, key: &[u8]) -> RedisResult<&mut Stream> {
+        match &mut self
+            .live_or_insert(key, || Object::Stream(Stream::default()))
+            .object
+        {
+            Object::Stream(stream) => Ok(stream),
+            _ => Err(wrong_type()),
+        }
+    }
+
+    /// The entries of a stream along with one of its consumer groups.
+    fn group(&mut self, key: &[u8], group: &[u8]) -> RedisResult<(&StreamEntries, &mut Group)> {
+        let no_group = || {
+            reply_error(&format!(
+                "NOGROUP No such key '{}' or consumer group '{}'",
+                String::from_utf8_lossy(key),
+                String::from_utf8_lossy(group)
+            ))
+        };
+        let Stream {
+            entries, groups, ..
+        } = self.stream(key)?.ok_or_else(no_group)?;
+        Ok((entries, groups.get_mut(group).ok_or_else(no_group)?))
+    }
+
+    /// The entries after `id` for `XREAD`, or `None` if there are none.
+    fn read_stream(
+        &mut self,
+        key: &[u8],
+        id: &[u8],
+        count: usize,
+    ) -> RedisResult<Option<Vec<Value>>> {
+        let Some(stream) = self.stream(key)? else {
+            return Ok(None);
+        };
+        let after = match id {
+            b"$" => stream.last_id,
+            id => parse_stream_id(id, 0)?,
+        };
+        let entries: Vec<_> = stream
+            .entries
+            .range((Bound::Excluded(after), Bound::Unbounded))
+            .take(count)
+            .map(|(id, fields)| stream_entry(*id, fields))
+            .collect();
+        Ok((!entries.is_empty()).then_some(entries))
+    }
+
+    /// The entries for `XREADGROUP`: with an id of `>` the ones never
+    /// delivered to the group, or `None` if there are none, and otherwise
+    /// the consumer's pending entries after `id`.
+    fn read_group(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        consumer: &[u8],
+        id: &[u8],
+        count: usize,
+        noack: bool,
+    ) -> RedisResult<Option<Vec<Value>>> {
+        let (entries, group) = self.group(key, group)?;
+        if id != b">" {
+            let after = parse_stream_id(id, 0)?;
+            let history = group
+                .pending
+                .range((Bound::Excluded(after), Bound::Unbounded))
+                .filter(|(_, pending)| pending.consumer == consumer)
+                .filter_map(|(id, _)| entries.get(id).map(|fields| stream_entry(*id, fields)))
+                .take(count)
+                .collect();
+            return Ok(Some(history));
+        }
+        let now = Instant::now();
+        let mut delivered = Vec::new();
+        for (id, fields) in entries
+            .range((Bound::Excluded(group.last_delivered), Bound::Unbounded))
+            .take(count)
+        {
+            group.last_delivered = *id;
+            if !noack {
+                let pending = Pending {
+                    consumer: consumer.to_vec(),
+                    delivered_at: now,
+                    deliveries: 1,
+                };
+                group.pending.insert(*id, pending);
+            }
+            delivered.push(stream_entry(*id, fields));
+        }
+        Ok((!delivered.is_empty()).then_some(delivered))
+    }
+
+    /// Pops from the head or the tail of a list, dropping it once empty.
+    fn pop(&mut self, key: &[u8], head: bool) -> RedisResult<Option<Vec<u8>>> {
+        let popped = self.list(key)?.and_then(|list| match head {
+            true => list.pop_front(),
+            false => list.pop_back(),
+        });
+        self.remove_if_empty(key);
+        Ok(popped)
+    }
+
+    fn sorted_set_or_default(&mut self, key: &[u8]) -> RedisResult<&mut BTreeMap<Vec<u8>, f64>> {
+        match &mut self
+            .live_or_insert(key, || Object::SortedSet(BTreeMap::new()))
+            .object
+        {
+            Object::SortedSet(zset) => Ok(zset),
+            _ => Err(wrong_type()),
+        }
+    }
+}
+
+struct ScoreBound {
+    score: f64,
+    exclusive: bool,
+}
+
+impl ScoreBound {
+    fn parse(arg: &[u8]) -> RedisResult<Self> {
+        let (exclusive, score) = match arg.strip_prefix(b"(") {
+            Some(rest) => (true, rest),
+            None => (false, arg),
+        };
+        let score = parse_float(score).map_err(|_| reply_error("ERR min or max is not a float"))?;
+        Ok(ScoreBound { score, exclusive })
+    }
+
+    fn below(&self, score: f64) -> bool {
+        if self.exclusive {
+            self.score < score
+        } else {
+            self.score <= score
+        }
+    }
+
+    fn above(&self, score: f64) -> bool {
+        if self.exclusive {
+            self.score > score
+        } else {
+            self.score >= score
+        }
+    }
+}
+
+/// Converts a unix timestamp into the matching point on the monotonic clock.
+fn instant_at(since_epoch: Duration) -> Instant {
+    let remaining = (UNIX_EPOCH + since_epoch)
+        .duration_since(SystemTime::now())
+        .unwrap_or_default();
+    Instant::now() + remaining
+}
+
+fn exact<'a, const N: usize>(name: &str, args: &'a [Vec<u8>]) -> RedisResult<&'a [Vec<u8>; N]> {
+    args.try_into().map_err(|_| wrong_arity(name))
+}
+
+fn at_least(name: &str, args: &[Vec<u8>], n: usize) -> RedisResult<()> {
+    if args.len() < n {
+        return Err(wrong_arity(name));
+    }
+    Ok(())
+}
+
+/// Matches `text` against a glob `pattern` with `*`, `?`, `[...]` classes
+/// and `\` escapes, like `KEYS` and `SCAN`.
+pub(crate) fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => (0..=text.len()).any(|i| glob_match(rest, &text[i..])),
+        Some((b'?', rest)) => !text.is_empty() && glob_match(rest, &text[1..]),
+        Some((b'[', class)) => {
+            let Some((&c, text)) = text.split_first() else {
+                return false;
+            };
+            let (negate, class) = match class.split_first() {
+                Some((b'^', class)) => (true, class),
+                _ => (false, class),
+            };
+            let (mut i, mut matched) = (0, false);
+            while i < class.len() && class[i] != b']' {
+                if class[i] == b'\\' && i + 1 < class.len() {
+                    matched |= class[i + 1] == c;
+                    i += 2;
+                } else if i + 2 < class.len() && class[i + 1] == b'-' && class[i + 2] != b']' {
+                    let (lo, hi) = (class[i].min(class[i + 2]), class[i].max(class[i + 2]));
+                    matched |= (lo..=hi).contains(&c);
+                    i += 3;
+                } else {
+                    matched |= class[i] == c;
+                    i += 1;
+                }
+            }
+            let rest = class.get(i + 1..).unwrap_or_default();
+            matched != negate && glob_match(rest, text)
+        }
+        Some((b'\\', [escaped, rest @ ..])) => {
+            text.first() == Some(escaped) && glob_match(rest, &text[1..])
+        }
+        Some((p, rest)) => text.first() == Some(p) && glob_match(rest, &text[1..]),
+    }
+}
+
+/// The indexes from `start` to `stop` inclusive of a list of `len` items,
+/// counting negative ones from the end, as in `LRANGE`.
+fn list_range(len: usize, start: i64, stop: i64) -> std::ops::Range<usize> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        len + stop
+    } else {
+        stop.min(len - 1)
+    };
+    if start > stop {
+        return 0..0;
+    }
+    start as usize..stop as usize + 1
+}
+
+/// Whether `LEFT` (true) or `RIGHT` was given.
+fn parse_direction(arg: &[u8]) -> RedisResult<bool> {
+    match arg.to_ascii_uppercase().as_slice() {
+        b"LEFT" => Ok(true),
+        b"RIGHT" => Ok(false),
+        _ => Err(syntax_error()),
+    }
+}
+
+/// The timeout of a blocking command, zero meaning forever.
+fn parse_timeout(arg: &[u8]) -> RedisResult<Duration> {
+    parse_float(arg)
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or_else(|| reply_error("ERR timeout is not a float or out of range"))
+}
+
+/// Until when a blocking command in `packed` waits for an item, or `None`
+/// if `packed` is not a single blocking command.
+fn blocking_deadline(packed: &[u8]) -> Option<Instant> {
+    let commands = parse_commands(packed).ok()?;
+    let [args] = commands.as_slice() else {
+        return None;
+    };
+    let timeout = match args.first()?.to_ascii_uppercase().as_slice() {
+        b"BLPOP" | b"BRPOP" | b"BLMOVE" => parse_timeout(args.last()?).ok()?,
+        b"XREAD" | b"XREADGROUP" => {
+            let options = args
+                .iter()
+                .take_while(|arg| !arg.eq_ignore_ascii_case(b"STREAMS"));
+            let at = options
+                .into_iter()
+                .position(|arg| arg.eq_ignore_ascii_case(b"BLOCK"))?;
+            Duration::from_millis(parse_int(args.get(at + 1)?).ok()?.try_into().ok()?)
+        }
+        _ => return None,
+    };
+    let forever = Duration::from_secs(u32::MAX.into());
+    let now = Instant::now();
+    match timeout.is_zero() {
+        true => now.checked_add(forever),
+        false => now.checked_add(timeout),
+    }
+}
+
+/// Parses a stream id, where `-` and `+` stand for the smallest and largest
+/// ids and a missing sequence number defaults to `seq`.
+fn parse_stream_id(arg: &[u8], seq: u64) -> RedisResult<StreamId> {
+    match arg {
+        b"-" => return Ok((0, 0)),
+        b"+" => return Ok((u64::MAX, u64::MAX)),
+        _ => {}
+    }
+    let invalid = || reply_error("ERR Invalid stream ID specified as stream command argument");
+    let text = std::str::from_utf8(arg).map_err(|_| invalid())?;
+    let (millis, seq) = match text.split_once('-') {
+        Some((millis, seq)) => (millis, seq.parse().map_err(|_| invalid())?),
+        None => (text, seq),
+    };
+    Ok((millis.parse().map_err(|_| invalid())?, seq))
+}
+
+fn stream_id_value((millis, seq): StreamId) -> Value {
+    Value::Data(format!("{millis}-{seq}").into_bytes())
+}
+
+fn stream_entry(id: StreamId, fields: &[Vec<u8>]) -> Value {
+    let fields = fields.iter().map(|field| Value::Data(field.clone()));
+    Value::Bulk(vec![stream_id_value(id), Value::Bulk(fields.collect())])
+}
+
+/// Parses `MAXLEN [=|~] len`, returning the length and the number of
+/// arguments it took up.
+fn parse_maxlen(args: &[Vec<u8>]) -> RedisResult<(usize, usize)> {
+    let (len, used) = match args {
+        [_, exact, len, ..] if matches!(exact.as_slice(), b"=" | b"~") => (len, 3),
+        [_, len, ..] => (len, 2),
+        _ => return Err(syntax_error()),
+    };
+    let len = usize::try_from(parse_int(len)?)
+        .map_err(|_| reply_error("ERR The MAXLEN argument must be >= 0."))?;
+    Ok((len, used))
+}
+
+/// Parses a `COUNT` option, where anything below one means no limit.
+fn parse_count(arg: &[u8]) -> RedisResult<usize> {
+    Ok(usize::try_from(parse_int(arg)?)
+        .ok()
+        .filter(|&count| count > 0)
+        .unwrap_or(usize::MAX))
+}
+
+fn parse_int(arg: &[u8]) -> RedisResult<i64> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| reply_error("ERR value is not an integer or out of range"))
+}
+
+fn parse_float(arg: &[u8]) -> RedisResult<f64> {
+    let parsed = match arg.to_ascii_lowercase().as_slice() {
+        b"inf" | b"+inf" => Some(f64::INFINITY),
+        b"-inf" => Some(f64::NEG_INFINITY),
+        arg => std::str::from_utf8(arg).ok().and_then(|s| s.parse().ok()),
+    };
+    parsed
+        .filter(|score: &f64| !score.is_nan())
+        .ok_or_else(|| reply_error("ERR value is not a valid float"))
+}
+
+fn wrong_arity(name: &str) -> RedisError {
+    reply_error(&format!(
+        "ERR wrong number of arguments for '{}' command",
+        name.to_ascii_lowercase()
+    ))
+}
+
+fn syntax_error() -> RedisError {
+    reply_error("ERR syntax error")
+}
+
+fn wrong_type() -> RedisError {
+    reply_error("WRONGTYPE Operation against a key holding the wrong kind of value")
+}
+
+/// Builds the error redis-rs would produce for the given server error line.
+fn reply_error(line: &str) -> RedisError {
+    match redis::parse_redis_value(format!("-{line}\r\n").as_bytes()) {
+        Err(err) => err,
+        Ok(_) => (
+            ErrorKind::ResponseError,
+            "An error was signalled by the server",
+        )
+            .into(),
+    }
+}
+
+#[cfg(test)]
+mod tests_memory {
+    use std::collections::{HashMap, HashSet};
+
+    use redis::{
+        streams::{StreamMaxlen, StreamPendingReply, StreamReadOptions},
+        Direction,
+    };
+
+    use super::*;
+    use crate::{IAsyncCache, ICache};
+
+    #[test]
+    fn test_basic() {
+        let mut ca = Cache::from_connection(MemoryConnection::new());
+        ca.set("k1", "v1").unwrap();
+        assert_eq!(ca.get::<_, String>("k1").unwrap(), "v1");
+        ca.del("k1").unwrap();
+        assert_eq!(ca.get::<_, Option<String>>("k1").unwrap(), None);
+
+        ca.set("num1", 1).unwrap();
+        assert_eq!(ca.get::<_, String>("num1").unwrap(), "1");
+        assert_eq!(ca.get::<_, i32>("num1").unwrap(), 1);
+        ca.del("num1").unwrap();
+        assert!(ca.get::<_, i32>("num1").is_err());
+        assert_eq!(ca.publish("news", "hello").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_incr() {
+        let mut ca = Cache::from_connection(MemoryConnection::new());
+        for i in 1..=5 {
+            assert_eq!(ca.incr("count").unwrap(), i);
+        }
+        assert!(ca.exists("count").unwrap());
+
+        ca.set("text", "abc").unwrap();
+        let err = ca.incr("text").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ResponseError);
+    }
+
+    #[test]
+    fn test_expire() {
+        let mut con = MemoryConnection::new();
+        let mut ca = Cache::from_connection(con.clone());
+        ca.set("k1", "v1").unwrap();
+        ca.expire("k1", 10).unwrap();
+        let ttl: i64 = redis::cmd("TTL").arg("k1").query(&mut con).unwrap();
+        assert_eq!(ttl, 10);
+
+        ca.set("k1", "v2").unwrap();
+        let ttl: i64 = redis::cmd("TTL").arg("k1").query(&mut con).unwrap();
+        assert_eq!(ttl, -1);
+
+        ca.expire("k1", 0).unwrap();
+        assert!(!ca.exists("k1").unwrap());
+
+        ca.set("k2", "v2").unwrap();
+        redis::cmd("PEXPIRE")
+            .arg("k2")
+            .arg(20)
+            .query::<()>(&mut con)
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(!ca.exists("k2").unwrap());
+        assert_eq!(ca.get::<_, Option<String>>("k2").unwrap(), None);
+    }
+
+    #[test]
+    fn test_set() {
+        let mut ca = Cache::from_connection(MemoryConnection::new());
+        ca.sadd("my_set", &["abc", "def"]).unwrap();
+        ca.sadd("my_set", &["abc", "ghi"]).unwrap();
+
+        let set: HashSet<String> = HashSet::from_iter(ca.smembers("my_set").unwrap());
+        assert_eq!(
+            set,
+            HashSet::from(["abc".into(), "def".into(), "ghi".into()])
+        );
+        assert_eq!(ca.scard("my_set").unwrap(), 3);
+
+        assert!(ca.sismember("my_set", "abc").unwrap());
+        ca.srem("my_set", &["abc"]).unwrap();
+        assert!(!ca.sismember("my_set", "abc").unwrap());
+        ca.srem("my_set", &["def", "ghi"]).unwrap();
+        assert!(!ca.exists("my_set").unwrap());
+    }
+
+    #[test]
+    fn test_hash() {
+        let mut ca = Cache::from_connection(MemoryConnection::new());
+        ca.hset("my_hash", "f1", "v1").unwrap();
+        assert_eq!(ca.hget::<_, _, String>("my_hash", "f1").unwrap(), "v1");
+        ca.hmset("my_hash", &[("f1", "v11"), ("f2", "v2")]).unwrap();
+        let list: Vec<Option<String>> = ca.hmget("my_hash", &["f1", "f2", "f3"]).unwrap();
+        assert_eq!(list, [Some("v11".into()), Some("v2".into()), None]);
+        let map: HashMap<String, String> = ca.hgetall("my_hash").unwrap();
+        assert_eq!(map.len(), 2);
+        assert!(!ca.hexists("my_hash", "f3").unwrap());
+        ca.hdel("my_hash", &["f2", "f3"]).unwrap();
+        let map: HashMap<String, String> = ca.hgetall("my_hash").unwrap();
+        assert_eq!(map, HashMap::from([("f1".into(), "v11".into())]));
+        ca.del("my_hash").unwrap();
+        let map: HashMap<String, String> = ca.hgetall("my_hash").unwrap();
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn test_sorted() {
+        let mut ca = Cache::from_connection(MemoryConnection::new());
+        ca.zadd("my_sorted", &[(456, "def"), (123, "abc"), (789, "ghi")])
+            .unwrap();
+        let list: Vec<String> = ca.zrange_by_score("my_sorted", 0, 500).unwrap();
+        assert_eq!(list, ["abc", "def"]);
+        let list: Vec<String> = ca.zrevrange_by_score("my_sorted", "+inf", "(123").unwrap();
+        assert_eq!(list, ["ghi", "def"]);
+        ca.zrem("my_sorted", &["abc", "def", "ghi"]).unwrap();
+        let list: Vec<String> = ca.zrange_by_score("my_sorted", "-inf", "+inf").unwrap();
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn test_wrong_type() {
+        let mut ca = Cache::from_connection(MemoryConnection::new());
+        ca.set("k1", "v1").unwrap();
+        let err = ca.sadd("k1", &["abc"]).unwrap_err();
+        assert_eq!(err.code(), Some("WRONGTYPE"));
+        let err = ca.hget::<_, _, String>("k1", "f1").unwrap_err();
+        assert_eq!(err.code(), Some("WRONGTYPE"));
+    }
+
+    #[test]
+    fn test_list() {
+        let mut ca = Cache::from_connection(MemoryConnection::new());
+        ca.rpush("jobs", &["b", "c"]).unwrap();
+        ca.lpush("jobs", &["a"]).unwrap();
+        assert_eq!(ca.llen("jobs").unwrap(), 3);
+        let all: Vec<String> = ca.lrange("jobs", 0, -1).unwrap();
+        assert_eq!(all, ["a", "b", "c"]);
+        let tail: Vec<String> = ca.lrange("jobs", -2, 10).unwrap();
+        assert_eq!(tail, ["b", "c"]);
+        assert_eq!(ca.lindex::<_, String>("jobs", -1).unwrap(), "c");
+        assert_eq!(ca.lindex::<_, Option<String>>("jobs", 3).unwrap(), None);
+
+        assert_eq!(ca.linsert_before("jobs", "b", "ab").unwrap(), 4);
+        assert_eq!(ca.linsert_after("jobs", "c", "d").unwrap(), 5);
+        assert_eq!(ca.linsert_after("jobs", "x", "y").unwrap(), -1);
+        ca.ltrim("jobs", 1, -2).unwrap();
+        let all: Vec<String> = ca.lrange("jobs", 0, -1).unwrap();
+        assert_eq!(all, ["ab", "b", "c"]);
+
+        assert_eq!(ca.lpop::<_, String>("jobs").unwrap(), "ab");
+        assert_eq!(ca.rpop::<_, String>("jobs").unwrap(), "c");
+        assert_eq!(ca.rpop::<_, String>("jobs").unwrap(), "b");
+        assert_eq!(ca.lpop::<_, Option<String>>("jobs").unwrap(), None);
+        assert!(!ca.exists("jobs").unwrap());
+
+        ca.set("k1", "v1").unwrap();
+        let err = ca.lpush("k1", &["a"]).unwrap_err();
+        assert_eq!(err.code(), Some("WRONGTYPE"));
+    }
+
+    #[test]
+    fn test_lmove() {
+        let mut ca = Cache::from_connection(MemoryConnection::new());
+        ca.rpush("queue", &["a", "b"]).unwrap();
+        let job: String = ca
+            .lmove("queue", "processing", Direction::Left, Direction::Right)
+            .unwrap();
+        assert_eq!(job, "a");
+        let job: String = ca
+            .lmove("queue", "queue", Direction::Right, Direction::Left)
+            .unwrap();
+        assert_eq!(job, "b");
+        let moved: Option<String> = ca
+            .lmove("empty", "processing", Direction::Left, Direction::Left)
+            .unwrap();
+        assert_eq!(moved, None);
+        assert_eq!(ca.lrange::<_, String>("processing", 0, -1).unwrap(), ["a"]);
+    }
+
+    #[test]
+    fn test_blocking_pop() {
+        let con = MemoryConnection::new();
+        let mut ca = Cache::from_connection(con.clone());
+        let popped: Option<(String, String)> = ca.blpop("jobs", Duration::from_millis(20)).unwrap();
+        assert_eq!(popped, None);
+
+        let pusher = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            Cache::from_connection(con).rpush("jobs", &["a"]).unwrap();
+        });
+        let popped: Option<(String, String)> =
+            ca.brpop(&["other", "jobs"], Duration::ZERO).unwrap();
+        assert_eq!(popped, Some(("jobs".into(), "a".into())));
+        pusher.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_async_blmove() {
+        let mut ca = AsyncCache::from_connection(MemoryConnection::new());
+        let mut pusher = ca.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            pusher.rpush("queue", &["a"]).await.unwrap();
+        });
+        let job: Option<String> = ca
+            .blmove(
+                "queue",
+                "processing",
+                Direction::Left,
+                Direction::Right,
+                Duration::from_secs(1),
+            )
+            .await
+            .unwrap();
+        assert_eq!(job.as_deref(), Some("a"));
+        assert_eq!(ca.llen("processing").await.unwrap(), 1);
+    }
+
+    #[test]
+    fn test_stream() {
+        let mut ca = Cache::from_connection(MemoryConnection::new());
+        assert_eq!(ca.xadd("s", "1-1", &[("f", "a")]).unwrap(), "1-1");
+        assert_eq!(ca.xadd("s", "2", &[("f", "b"), ("g", "c")]).unwrap(), "2-0");
+        assert!(ca.xadd("s", "2-0", &[("f", "x")]).is_err());
+        let id = ca.xadd("s", "*", &[("f", "c")]).unwrap();
+
+        let range = ca.xrange("s", "-", "+", None).unwrap();
+        let ids: Vec<_> = range.ids.iter().map(|entry| entry.id.as_str()).collect();
+        assert_eq!(ids, ["1-1", "2-0", id.as_str()]);
+        assert_eq!(range.ids[1].get::<String>("g").unwrap(), "c");
+        let range = ca.xrange("s", "2", "+", Some(1)).unwrap();
+        assert_eq!(range.ids[0].id, "2-0");
+
+        let read = ca
+            .xread(
+                &["s", "missing"],
+                &["1-1", "0"],
+                StreamReadOptions::default(),
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(read.keys.len(), 1);
+        assert_eq!(read.keys[0].ids.len(), 2);
+        let read = ca
+            .xread(&["s"], &["$"], StreamReadOptions::default())
+            .unwrap();
+        assert!(read.is_none());
+
+        assert_eq!(ca.xtrim("s", StreamMaxlen::Equals(1)).unwrap(), 2);
+        assert_eq!(ca.xrange("s", "-", "+", None).unwrap().ids[0].id, id);
+        ca.set("k", "v").unwrap();
+        assert!(ca.xadd("k", "*", &[("f", "v")]).is_err());
+    }
+
+    #[test]
+    fn test_consumer_group() {
+        let mut ca = Cache::from_connection(MemoryConnection::new());
+        ca.xgroup_create("s", "g", "$").unwrap();
+        let err = ca.xgroup_create("s", "g", "$").unwrap_err();
+        assert_eq!(err.code(), Some("BUSYGROUP"));
+        let err = ca.xpending("s", "other").unwrap_err();
+        assert_eq!(err.code(), Some("NOGROUP"));
+        assert_eq!(ca.xpending("s", "g").unwrap().count(), 0);
+        for value in ["a", "b", "c"] {
+            ca.xadd("s", "*", &[("f", value)]).unwrap();
+        }
+
+        let options = StreamReadOptions::default().count(2);
+        let read = ca
+            .xreadgroup("g", "c1", &["s"], &[">"], options)
+            .unwrap()
+            .unwrap();
+        let first = read.keys[0].ids[0].id.clone();
+        assert_eq!(read.keys[0].ids.len(), 2);
+        let read = ca
+            .xreadgroup("g", "c2", &["s"], &[">"], StreamReadOptions::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(read.keys[0].ids.len(), 1);
+        let read = ca
+            .xreadgroup("g", "c2", &["s"], &[">"], StreamReadOptions::default())
+            .unwrap();
+        assert!(read.is_none());
+        // Reading from an id returns the consumer's own pending entries.
+        let read = ca
+            .xreadgroup("g", "c1", &["s"], &["0"], StreamReadOptions::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(read.keys[0].ids.len(), 2);
+
+        let StreamPendingReply::Data(pending) = ca.xpending("s", "g").unwrap() else {
+            panic!("no pending entries");
+        };
+        assert_eq!(pending.count, 3);
+        assert_eq!(pending.start_id, first);
+        assert_eq!(pending.consumers[0].name, "c1");
+        assert_eq!(pending.consumers[0].pending, 2);
+
+        assert_eq!(ca.xack("s", "g", &[&first, "9-9"]).unwrap(), 1);
+        let pending = ca.xpending_count("s", "g", "-", "+", 10).unwrap();
+        assert_eq!(pending.ids.len(), 2);
+        assert_eq!(pending.ids[0].consumer, "c1");
+        assert_eq!(pending.ids[0].times_delivered, 1);
+
+        let claim = ca
+            .xautoclaim("s", "g", "c3", Duration::from_secs(60), "0-0", 10)
+            .unwrap();
+        assert!(claim.claimed.is_empty());
+        let claim = ca
+            .xautoclaim("s", "g", "c3", Duration::ZERO, "0-0", 1)
+            .unwrap();
+        assert_eq!(claim.claimed.len(), 1);
+        assert_ne!(claim.next, "0-0");
+        let claim = ca
+            .xautoclaim("s", "g", "c3", Duration::ZERO, &claim.next, 1)
+            .unwrap();
+        assert_eq!(claim.claimed.len(), 1);
+        assert_eq!(claim.next, "0-0");
+        let pending = ca.xpending_count("s", "g", "-", "+", 10).unwrap();
+        assert!(pending.ids.iter().all(|id| id.consumer == "c3"));
+        assert!(pending.ids.iter().all(|id| id.times_delivered == 2));
+    }
+
+    #[tokio::test]
+    async fn test_async_blocking_xread() {
+        let mut ca = AsyncCache::from_connection(MemoryConnection::new());
+        ca.xadd("s", "*", &[("f", "old")]).await.unwrap();
+        let mut producer = ca.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            producer.xadd("s", "*", &[("f", "new")]).await.unwrap();
+        });
+        let read = ca
+            .xread(&["s"], &["$"], StreamReadOptions::default().block(1000))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(read.keys[0].ids.len(), 1);
+        assert_eq!(read.keys[0].ids[0].get::<String>("f").unwrap(), "new");
+        let read = ca
+            .xread(&["s"], &["+"], StreamReadOptions::default().block(20))
+            .await
+            .unwrap();
+        assert!(read.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_async_shared() {
+        let mut ca = AsyncCache::from_connection(MemoryConnection::new());
+        let mut ca2 = ca.clone();
+        ca.set("k1", "v1").await.unwrap();
+        assert_eq!(ca2.get::<_, String>("k1").await.unwrap(), "v1");
+        assert_eq!(ca2.incr("count").await.unwrap(), 1);
+        ca.hsetall("my_hash", &[("f1", "v1"), ("f2", "v2")])
+            .await
+            .unwrap();
+        let map: HashMap<String, String> = ca2.hgetall("my_hash").await.unwrap();
+        assert_eq!(map.len(), 2);
+        ca2.del(&["k1", "count", "my_hash"]).await.unwrap();
+        assert!(!ca.exists("k1").await.unwrap());
+    }
+
+    #[test]
+    fn test_exec_error() {
+        let mut con = MemoryConnection::new();
+        redis::cmd("SET").arg("text").arg("abc").execute(&mut con);
+        redis::cmd("MULTI").execute(&mut con);
+        redis::cmd("SET").arg("k1").arg("v1").execute(&mut con);
+        redis::cmd("INCR").arg("text").execute(&mut con);
+        let err = redis::cmd("EXEC").query::<Value>(&mut con).unwrap_err();
+
+        // What the same reply from a server parses to.
+        let server = b"*2\r\n+OK\r\n-ERR value is not an integer or out of range\r\n";
+        let parsed = redis::parse_redis_value(server).unwrap_err();
+        assert_eq!(err.kind(), parsed.kind());
+        assert_eq!(err.detail(), parsed.detail());
+        let value: String = redis::cmd("GET").arg("k1").query(&mut con).unwrap();
+        assert_eq!(value, "v1");
+    }
+
+    #[tokio::test]
+    async fn test_async_publish() {
+        use futures_util::StreamExt;
+
+        let con = MemoryConnection::new();
+        let mut sub = con.subscriber::<String>();
+        let mut ca = AsyncCache::from_connection(con);
+        assert_eq!(ca.publish("news", "ignored").await.unwrap(), 0);
+        sub.subscribe(&["news"]).await.unwrap();
+        sub.psubscribe(&["n*"]).await.unwrap();
+        assert_eq!(ca.publish("news", "hello").await.unwrap(), 2);
+        assert_eq!(ca.publish("other", "x").await.unwrap(), 0);
+        let msg = sub.next().await.unwrap().unwrap();
+        assert_eq!((msg.channel.as_str(), msg.pattern), ("news", None));
+        assert_eq!(msg.payload, "hello");
+        let msg = sub.next().await.unwrap().unwrap();
+        assert_eq!(msg.pattern.as_deref(), Some("n*"));
+
+        drop(sub);
+        tokio::task::yield_now().await;
+        assert_eq!(ca.publish("news", "gone").await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,327 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use redis::{aio, Cmd, ConnectionLike, Pipeline, RedisError, RedisFuture, RedisResult, Value};
+
+use crate::{resp::parse_command_names, Dedicated};
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: [f64; 11] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+];
+
+/// Counters for the commands sent through `Metered` connections, rendered
+/// in the Prometheus text format. Clones share the counters.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    registry: Arc<Mutex<Registry>>,
+}
+
+#[derive(Default)]
+struct Registry {
+    commands: BTreeMap<String, u64>,
+    /// Latency per request, labelled with its command, or `PIPELINE`.
+    latency: BTreeMap<String, Histogram>,
+    /// Keyed by request label and `ErrorKind`.
+    errors: BTreeMap<(String, String), u64>,
+    hits: BTreeMap<String, u64>,
+    misses: BTreeMap<String, u64>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The share of `GET` and `HGET` replies that were not nil, or `None`
+    /// before the first one.
+    pub fn hit_ratio(&self) -> Option<f64> {
+        let registry = self.registry();
+        let hits: u64 = registry.hits.values().sum();
+        let misses: u64 = registry.misses.values().sum();
+        let total = hits + misses;
+        (total > 0).then(|| hits as f64 / total as f64)
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let registry = self.registry();
+        let mut out = String::new();
+        header(
+            &mut out,
+            "cache_commands_total",
+            "counter",
+            "Commands sent to Redis.",
+        );
+        for (command, count) in &registry.commands {
+            let _ = writeln!(out, "cache_commands_total{{command=\"{command}\"}} {count}");
+        }
+        header(
+            &mut out,
+            "cache_request_duration_seconds",
+            "histogram",
+            "Time from sending a command or pipeline to reading its replies.",
+        );
+        for (command, histogram) in &registry.latency {
+            let name = "cache_request_duration_seconds";
+            let mut cumulative = 0;
+            for (le, count) in BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "{name}_bucket{{command=\"{command}\",le=\"{le}\"}} {cumulative}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{command=\"{command}\",le=\"+Inf\"}} {}",
+                histogram.count
+            );
+            let _ = writeln!(out, "{name}_sum{{command=\"{command}\"}} {}", histogram.sum);
+            let _ = writeln!(
+                out,
+                "{name}_count{{command=\"{command}\"}} {}",
+                histogram.count
+            );
+        }
+        header(
+            &mut out,
+            "cache_errors_total",
+            "counter",
+            "Failed requests by error kind.",
+        );
+        for ((command, kind), count) in &registry.errors {
+            let _ = writeln!(
+                out,
+                "cache_errors_total{{command=\"{command}\",kind=\"{kind}\"}} {count}"
+            );
+        }
+        for (name, help, counts) in [
+            (
+                "cache_hits_total",
+                "Reads that found a value.",
+                &registry.hits,
+            ),
+            (
+                "cache_misses_total",
+                "Reads of missing keys.",
+                &registry.misses,
+            ),
+        ] {
+            header(&mut out, name, "counter", help);
+            for (command, count) in counts {
+                let _ = writeln!(out, "{name}{{command=\"{command}\"}} {count}");
+            }
+        }
+        out
+    }
+
+    fn registry(&self) -> MutexGuard<'_, Registry> {
+        self.registry.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Records one request made of the commands in `packed`, which took
+    /// `elapsed` and returned `result`.
+    fn record(&self, packed: &[u8], elapsed: Duration, result: Result<&[Value], &RedisError>) {
+        let names: Vec<String> = match parse_command_names(packed) {
+            Ok(names) => names
+                .into_iter()
+                .map(|name| String::from_utf8_lossy(&name).into_owned())
+                .collect(),
+            Err(_) => vec!["UNKNOWN".into()],
+        };
+        let label = match names.as_slice() {
+            [name] => name.clone(),
+            _ => "PIPELINE".into(),
+        };
+
+        let mut registry = self.registry();
+        for name in &names {
+            *registry.commands.entry(name.clone()).or_default() += 1;
+        }
+        let histogram = registry.latency.entry(label.clone()).or_default();
+        let secs = elapsed.as_secs_f64();
+        if let Some(bucket) = BUCKETS.iter().position(|&le| secs <= le) {
+            histogram.buckets[bucket] += 1;
+        }
+        histogram.sum += secs;
+        histogram.count += 1;
+
+        let replies = match result {
+            Ok(replies) => replies,
+            Err(err) => {
+                let kind = format!("{:?}", err.kind());
+                *registry.errors.entry((label, kind)).or_default() += 1;
+                return;
+            }
+        };
+        // Replies only line up with commands outside of transactions.
+        if names.len() != replies.len() {
+            return;
+        }
+        for (name, reply) in names.iter().zip(replies) {
+            if name == "GET" || name == "HGET" {
+                let counts = match reply {
+                    Value::Nil => &mut registry.misses,
+                    _ => &mut registry.hits,
+                };
+                *counts.entry(name.clone()).or_default() += 1;
+            }
+        }
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// A connection that records its commands in `Metrics`, for use with
+/// `Cache::from_connection` or `AsyncCache::from_connection`.
+#[derive(Clone)]
+pub struct Metered<C> {
+    con: C,
+    metrics: Metrics,
+}
+
+impl<C> Metered<C> {
+    pub fn new(con: C, metrics: Metrics) -> Self {
+        Metered { con, metrics }
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+}
+
+impl<C: Dedicated> Dedicated for Metered<C> {}
+
+impl<C: ConnectionLike> ConnectionLike for Metered<C> {
+    fn req_packed_command(&mut self, cmd: &[u8]) -> RedisResult<Value> {
+        let start = Instant::now();
+        let result = self.con.req_packed_command(cmd);
+        let replies = result.as_ref().map(std::slice::from_ref);
+        self.metrics.record(cmd, start.elapsed(), replies);
+        result
+    }
+
+    fn req_packed_commands(
+        &mut self,
+        cmd: &[u8],
+        offset: usize,
+        count: usize,
+    ) -> RedisResult<Vec<Value>> {
+        let start = Instant::now();
+        let result = self.con.req_packed_commands(cmd, offset, count);
+        let replies = result.as_deref();
+        self.metrics.record(cmd, start.elapsed(), replies);
+        result
+    }
+
+    fn get_db(&self) -> i64 {
+        self.con.get_db()
+    }
+
+    fn check_connection(&mut self) -> bool {
+        self.con.check_connection()
+    }
+
+    fn is_open(&self) -> bool {
+        self.con.is_open()
+    }
+}
+
+impl<C: aio::ConnectionLike + Send> aio::ConnectionLike for Metered<C> {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        Box::pin(async move {
+            let start = Instant::now();
+            let result = self.con.req_packed_command(cmd).await;
+            let replies = result.as_ref().map(std::slice::from_ref);
+            let packed = cmd.get_packed_command();
+            self.metrics.record(&packed, start.elapsed(), replies);
+            result
+        })
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        Box::pin(async move {
+            let start = Instant::now();
+            let result = self.con.req_packed_commands(cmd, offset, count).await;
+            let replies = result.as_deref();
+            let packed = cmd.get_packed_pipeline();
+            self.metrics.record(&packed, start.elapsed(), replies);
+            result
+        })
+    }
+
+    fn get_db(&self) -> i64 {
+        self.con.get_db()
+    }
+}
+
+#[cfg(test)]
+mod tests_metrics {
+    use super::*;
+    use crate::{AsyncCache, Cache, IAsyncCache, ICache, MemoryConnection};
+
+    #[test]
+    fn test_metrics() {
+        let metrics = Metrics::new();
+        let con = Metered::new(MemoryConnection::new(), metrics.clone());
+        let mut ca = Cache::from_connection(con);
+        assert_eq!(metrics.hit_ratio(), None);
+        ca.set("k1", "v1").unwrap();
+        ca.get::<_, String>("k1").unwrap();
+        ca.get::<_, Option<String>>("k2").unwrap();
+        ca.hget::<_, _, Option<String>>("h", "f").unwrap();
+        ca.incr("k1").unwrap_err();
+        ca.pipeline().get("k1").get("k1").query::<()>().unwrap();
+        assert_eq!(metrics.hit_ratio(), Some(0.6));
+
+        let text = metrics.render();
+        assert!(text.contains("cache_commands_total{command=\"GET\"} 4\n"));
+        assert!(text.contains("cache_request_duration_seconds_count{command=\"GET\"} 2\n"));
+        assert!(text.contains("cache_request_duration_seconds_count{command=\"PIPELINE\"} 1\n"));
+        assert!(
+            text.contains("cache_request_duration_seconds_bucket{command=\"SET\",le=\"+Inf\"} 1\n")
+        );
+        assert!(text.contains("cache_errors_total{command=\"INCR\",kind=\"ResponseError\"} 1\n"));
+        assert!(text.contains("cache_hits_total{command=\"GET\"} 3\n"));
+        assert!(text.contains("cache_misses_total{command=\"HGET\"} 1\n"));
+        assert!(text.contains("# TYPE cache_request_duration_seconds histogram\n"));
+    }
+
+    #[tokio::test]
+    async fn test_async_metrics() {
+        let metrics = Metrics::new();
+        let con = Metered::new(MemoryConnection::new(), metrics.clone());
+        let mut ca = AsyncCache::from_connection(con);
+        ca.get::<_, Option<String>>("k1").await.unwrap();
+        ca.transaction::<_, (), _>(&["k1"], async |pipe| {
+            pipe.set("k1", "v1").ignore();
+            Ok(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(metrics.hit_ratio(), Some(0.0));
+        let text = metrics.render();
+        assert!(text.contains("cache_commands_total{command=\"WATCH\"} 1\n"));
+        assert!(text.contains("cache_commands_total{command=\"EXEC\"} 1\n"));
+    }
+}
@@ -0,0 +1,919 @@
+use std::time::Duration;
+
+use redis::{Direction, FromRedisValue, RedisResult, RedisWrite, ToRedisArgs, Value};
+
+use crate::{AsyncCache, Cache, IAsyncCache, ICache, SetOptions, SetReply};
+
+/// A cache whose keys all live under a prefix, for services sharing a Redis.
+///
+/// The prefix is prepended to every key argument, including each key of a
+/// multi-key argument such as `del(&["a", "b"])`, and stripped from the keys
+/// returned by `scan_match`, `blpop` and `brpop`.
+#[derive(Clone)]
+pub struct Namespaced<C> {
+    inner: C,
+    prefix: Vec<u8>,
+}
+
+impl<C> Namespaced<C> {
+    pub fn new(inner: C, prefix: impl Into<Vec<u8>>) -> Self {
+        Namespaced {
+            inner,
+            prefix: prefix.into(),
+        }
+    }
+
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+
+    pub fn inner(&mut self) -> &mut C {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    /// The scan pattern matching `pattern` below the prefix.
+    fn pattern<P: ToRedisArgs>(&self, pattern: P) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.prefix.len());
+        for &b in &self.prefix {
+            if matches!(b, b'*' | b'?' | b'[' | b']' | b'\\') {
+                out.push(b'\\');
+            }
+            out.push(b);
+        }
+        for arg in pattern.to_redis_args() {
+            out.extend(arg);
+        }
+        out
+    }
+
+    fn strip<V: FromRedisValue>(&self, keys: Vec<Vec<u8>>) -> RedisResult<Vec<V>> {
+        keys.into_iter()
+            .map(|key| {
+                let key = key.strip_prefix(self.prefix.as_slice()).unwrap_or(&key);
+                V::from_redis_value(&Value::Data(key.to_vec()))
+            })
+            .collect()
+    }
+
+    /// Strips the prefix from the key of a `BLPOP` or `BRPOP` reply.
+    fn strip_popped<V: FromRedisValue>(&self, reply: Value) -> RedisResult<V> {
+        match reply {
+            Value::Bulk(mut items) if items.len() == 2 => {
+                if let Value::Data(key) = &mut items[0] {
+                    if key.starts_with(&self.prefix) {
+                        key.drain(..self.prefix.len());
+                    }
+                }
+                V::from_redis_value(&Value::Bulk(items))
+            }
+            reply => V::from_redis_value(&reply),
+        }
+    }
+}
+
+impl<C: redis::ConnectionLike> Namespaced<Cache<C>> {
+    /// Collects the keys below the prefix matching the glob `pattern`,
+    /// without the prefix.
+    pub fn scan_match<P, V>(&mut self, pattern: P) -> RedisResult<Vec<V>>
+    where
+        P: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        let keys = self.inner.scan_match(self.pattern(pattern))?;
+        self.strip(keys)
+    }
+}
+
+impl<C: redis::aio::ConnectionLike + Send> Namespaced<AsyncCache<C>> {
+    /// Collects the keys below the prefix matching the glob `pattern`,
+    /// without the prefix.
+    pub async fn scan_match<P, V>(&mut self, pattern: P) -> RedisResult<Vec<V>>
+    where
+        P: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        let keys = self.inner.scan_match(self.pattern(pattern)).await?;
+        self.strip(keys)
+    }
+}
+
+/// Writes each argument of `K` with the prefix in front.
+struct Prefixed<'a, K>(&'a [u8], K);
+
+impl<K: ToRedisArgs> ToRedisArgs for Prefixed<'_, K> {
+    fn write_redis_args<W>(&self, out: &mut W)
+    where
+        W: ?Sized + RedisWrite,
+    {
+        for arg in self.1.to_redis_args() {
+            let mut key = Vec::with_capacity(self.0.len() + arg.len());
+            key.extend_from_slice(self.0);
+            key.extend(arg);
+            out.write_arg(&key);
+        }
+    }
+
+    fn is_single_arg(&self) -> bool {
+        self.1.is_single_arg()
+    }
+}
+
+impl<C: ICache> ICache for Namespaced<C> {
+    fn get<K, V>(&mut self, key: K) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.inner.get(Prefixed(&self.prefix, key))
+    }
+
+    fn set<K, V>(&mut self, key: K, value: V) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.inner.set(Prefixed(&self.prefix, key), value)
+    }
+
+    fn set_with<K, V, R>(
+        &mut self,
+        key: K,
+        value: V,
+        options: SetOptions,
+    ) -> RedisResult<SetReply<R>>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+        R: FromRedisValue,
+    {
+        self.inner
+            .set_with(Prefixed(&self.prefix, key), value, options)
+    }
+
+    fn del<K>(&mut self, key: K) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+    {
+        self.inner.del(Prefixed(&self.prefix, key))
+    }
+
+    fn incr<K>(&mut self, key: K) -> RedisResult<i32>
+    where
+        K: ToRedisArgs,
+    {
+        self.inner.incr(Prefixed(&self.prefix, key))
+    }
+
+    fn exists<K>(&mut self, key: K) -> RedisResult<bool>
+    where
+        K: ToRedisArgs,
+    {
+        self.inner.exists(Prefixed(&self.prefix, key))
+    }
+
+    fn expire<K>(&mut self, key: K, sec: i32) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+    {
+        self.inner.expire(Prefixed(&self.prefix, key), sec)
+    }
+
+    fn sadd<K, V>(&mut self, key: K, values: &[V]) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.inner.sadd(Prefixed(&self.prefix, key), values)
+    }
+
+    fn smembers<K, V>(&mut self, key: K) -> RedisResult<Vec<V>>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.inner.smembers(Prefixed(&self.prefix, key))
+    }
+
+    fn srem<K, V>(&mut self, key: K, values: &[V]) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.inner.srem(Prefixed(&self.prefix, key), values)
+    }
+
+    fn scard<K>(&mut self, key: K) -> RedisResult<usize>
+    where
+        K: ToRedisArgs,
+    {
+        self.inner.scard(Prefixed(&self.prefix, key))
+    }
+
+    fn sismember<K, V>(&mut self, key: K, value: V) -> RedisResult<bool>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.inner.sismember(Prefixed(&self.prefix, key), value)
+    }
+
+    fn hset<K, F, V>(&mut self, key: K, field: F, value: V) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        F: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.inner.hset(Prefixed(&self.prefix, key), field, value)
+    }
+
+    fn hget<K, F, V>(&mut self, key: K, field: F) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        F: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.inner.hget(Prefixed(&self.prefix, key), field)
+    }
+
+    fn hmset<K, F, V>(&mut self, key: K, values: &[(F, V)]) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        F: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.inner.hmset(Prefixed(&self.prefix, key), values)
+    }
+
+    fn hmget<K, F, V>(&mut self, key: K, fields: &[F]) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        F: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.inner.hmget(Prefixed(&self.prefix, key), fields)
+    }
+
+    fn hsetall<K, V>(&mut self, key: K, value: V) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.inner.hsetall(Prefixed(&self.prefix, key), value)
+    }
+
+    fn hgetall<K, V>(&mut self, key: K) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.inner.hgetall(Prefixed(&self.prefix, key))
+    }
+
+    fn hexists<K, F>(&mut self, key: K, field: F) -> RedisResult<bool>
+    where
+        K: ToRedisArgs,
+        F: ToRedisArgs,
+    {
+        self.inner.hexists(Prefixed(&self.prefix, key), field)
+    }
+
+    fn hdel<K, F>(&mut self, key: K, fields: &[F]) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        F: ToRedisArgs,
+    {
+        self.inner.hdel(Prefixed(&self.prefix, key), fields)
+    }
+
+    fn zadd<K, S, M>(&mut self, key: K, items: &[(S, M)]) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        S: ToRedisArgs,
+        M: ToRedisArgs,
+    {
+        self.inner.zadd(Prefixed(&self.prefix, key), items)
+    }
+
+    fn zrange_by_score<K, M, V>(&mut self, key: K, min: M, max: M) -> RedisResult<Vec<V>>
+    where
+        K: ToRedisArgs,
+        M: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.inner
+            .zrange_by_score(Prefixed(&self.prefix, key), min, max)
+    }
+
+    fn zrevrange_by_score<K, M, V>(&mut self, key: K, max: M, min: M) -> RedisResult<Vec<V>>
+    where
+        K: ToRedisArgs,
+        M: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.inner
+            .zrevrange_by_score(Prefixed(&self.prefix, key), max, min)
+    }
+
+    fn zrem<K, M>(&mut self, key: K, items: &[M]) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        M: ToRedisArgs,
+    {
+        self.inner.zrem(Prefixed(&self.prefix, key), items)
+    }
+
+    fn lpush<K, V>(&mut self, key: K, values: &[V]) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.inner.lpush(Prefixed(&self.prefix, key), values)
+    }
+
+    fn rpush<K, V>(&mut self, key: K, values: &[V]) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.inner.rpush(Prefixed(&self.prefix, key), values)
+    }
+
+    fn lpop<K, V>(&mut self, key: K) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.inner.lpop(Prefixed(&self.prefix, key))
+    }
+
+    fn rpop<K, V>(&mut self, key: K) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.inner.rpop(Prefixed(&self.prefix, key))
+    }
+
+    fn lrange<K, V>(&mut self, key: K, start: isize, stop: isize) -> RedisResult<Vec<V>>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.inner.lrange(Prefixed(&self.prefix, key), start, stop)
+    }
+
+    fn ltrim<K>(&mut self, key: K, start: isize, stop: isize) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+    {
+        self.inner.ltrim(Prefixed(&self.prefix, key), start, stop)
+    }
+
+    fn lindex<K, V>(&mut self, key: K, index: isize) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.inner.lindex(Prefixed(&self.prefix, key), index)
+    }
+
+    fn linsert_before<K, P, V>(&mut self, key: K, pivot: P, value: V) -> RedisResult<isize>
+    where
+        K: ToRedisArgs,
+        P: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.inner
+            .linsert_before(Prefixed(&self.prefix, key), pivot, value)
+    }
+
+    fn linsert_after<K, P, V>(&mut self, key: K, pivot: P, value: V) -> RedisResult<isize>
+    where
+        K: ToRedisArgs,
+        P: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.inner
+            .linsert_after(Prefixed(&self.prefix, key), pivot, value)
+    }
+
+    fn llen<K>(&mut self, key: K) -> RedisResult<usize>
+    where
+        K: ToRedisArgs,
+    {
+        self.inner.llen(Prefixed(&self.prefix, key))
+    }
+
+    fn blpop<K, V>(&mut self, key: K, timeout: Duration) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        let reply = self.inner.blpop(Prefixed(&self.prefix, key), timeout)?;
+        self.strip_popped(reply)
+    }
+
+    fn brpop<K, V>(&mut self, key: K, timeout: Duration) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        let reply = self.inner.brpop(Prefixed(&self.prefix, key), timeout)?;
+        self.strip_popped(reply)
+    }
+
+    fn lmove<K, D, V>(
+        &mut self,
+        key: K,
+        destination: D,
+        from: Direction,
+        to: Direction,
+    ) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        D: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.inner.lmove(
+            Prefixed(&self.prefix, key),
+            Prefixed(&self.prefix, destination),
+            from,
+            to,
+        )
+    }
+
+    fn blmove<K, D, V>(
+        &mut self,
+        key: K,
+        destination: D,
+        from: Direction,
+        to: Direction,
+        timeout: Duration,
+    ) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        D: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.inner.blmove(
+            Prefixed(&self.prefix, key),
+            Prefixed(&self.prefix, destination),
+            from,
+            to,
+            timeout,
+        )
+    }
+}
+
+impl<C: IAsyncCache> IAsyncCache for Namespaced<C> {
+    async fn get<K, V>(&mut self, key: K) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        self.inner.get(Prefixed(&self.prefix, key)).await
+    }
+
+    async fn set<K, V>(&mut self, key: K, value: V) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Send,
+    {
+        self.inner.set(Prefixed(&self.prefix, key), value).await
+    }
+
+    async fn set_with<K, V, R>(
+        &mut self,
+        key: K,
+        value: V,
+        options: SetOptions,
+    ) -> RedisResult<SetReply<R>>
+    where
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Send,
+        R: FromRedisValue,
+    {
+        self.inner
+            .set_with(Prefixed(&self.prefix, key), value, options)
+            .await
+    }
+
+    async fn del<K>(&mut self, key: K) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+    {
+        self.inner.del(Prefixed(&self.prefix, key)).await
+    }
+
+    async fn incr<K>(&mut self, key: K) -> RedisResult<i32>
+    where
+        K: ToRedisArgs + Send,
+    {
+        self.inner.incr(Prefixed(&self.prefix, key)).await
+    }
+
+    async fn exists<K>(&mut self, key: K) -> RedisResult<bool>
+    where
+        K: ToRedisArgs + Send,
+    {
+        self.inner.exists(Prefixed(&self.prefix, key)).await
+    }
+
+    async fn expire<K>(&mut self, key: K, sec: i32) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+    {
+        self.inner.expire(Prefixed(&self.prefix, key), sec).await
+    }
+
+    async fn sadd<K, V>(&mut self, key: K, values: &[V]) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Sync,
+    {
+        self.inner.sadd(Prefixed(&self.prefix, key), values).await
+    }
+
+    async fn smembers<K, V>(&mut self, key: K) -> RedisResult<Vec<V>>
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        self.inner.smembers(Prefixed(&self.prefix, key)).await
+    }
+
+    async fn srem<K, V>(&mut self, key: K, values: &[V]) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Sync,
+    {
+        self.inner.srem(Prefixed(&self.prefix, key), values).await
+    }
+
+    async fn scard<K>(&mut self, key: K) -> RedisResult<usize>
+    where
+        K: ToRedisArgs + Send,
+    {
+        self.inner.scard(Prefixed(&self.prefix, key)).await
+    }
+
+    async fn sismember<K, V>(&mut self, key: K, value: V) -> RedisResult<bool>
+    where
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Send,
+    {
+        self.inner
+            .sismember(Prefixed(&self.prefix, key), value)
+            .await
+    }
+
+    async fn hset<K, F, V>(&mut self, key: K, field: F, value: V) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+        F: ToRedisArgs + Send,
+        V: ToRedisArgs + Send,
+    {
+        self.inner
+            .hset(Prefixed(&self.prefix, key), field, value)
+            .await
+    }
+
+    async fn hget<K, F, V>(&mut self, key: K, field: F) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        F: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        self.inner.hget(Prefixed(&self.prefix, key), field).await
+    }
+
+    async fn hmset<K, F, V>(&mut self, key: K, values: &[(F, V)]) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+        F: ToRedisArgs + Sync,
+        V: ToRedisArgs + Sync,
+    {
+        self.inner.hmset(Prefixed(&self.prefix, key), values).await
+    }
+
+    async fn hmget<K, F, V>(&mut self, key: K, fields: &[F]) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        F: ToRedisArgs + Sync,
+        V: FromRedisValue,
+    {
+        self.inner.hmget(Prefixed(&self.prefix, key), fields).await
+    }
+
+    async fn hsetall<K, V>(&mut self, key: K, value: V) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Send,
+    {
+        self.inner.hsetall(Prefixed(&self.prefix, key), value).await
+    }
+
+    async fn hgetall<K, V>(&mut self, key: K) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        self.inner.hgetall(Prefixed(&self.prefix, key)).await
+    }
+
+    async fn hexists<K, F>(&mut self, key: K, field: F) -> RedisResult<bool>
+    where
+        K: ToRedisArgs + Send,
+        F: ToRedisArgs + Send,
+    {
+        self.inner.hexists(Prefixed(&self.prefix, key), field).await
+    }
+
+    async fn hdel<K, F>(&mut self, key: K, fields: &[F]) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+        F: ToRedisArgs + Sync,
+    {
+        self.inner.hdel(Prefixed(&self.prefix, key), fields).await
+    }
+
+    async fn zadd<K, S, M>(&mut self, key: K, items: &[(S, M)]) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+        S: ToRedisArgs + Sync,
+        M: ToRedisArgs + Sync,
+    {
+        self.inner.zadd(Prefixed(&self.prefix, key), items).await
+    }
+
+    async fn zrange_by_score<K, M, V>(&mut self, key: K, min: M, max: M) -> RedisResult<Vec<V>>
+    where
+        K: ToRedisArgs + Send,
+        M: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        self.inner
+            .zrange_by_score(Prefixed(&self.prefix, key), min, max)
+            .await
+    }
+
+    async fn zrevrange_by_score<K, M, V>(&mut self, key: K, max: M, min: M) -> RedisResult<Vec<V>>
+    where
+        K: ToRedisArgs + Send,
+        M: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        self.inner
+            .zrevrange_by_score(Prefixed(&self.prefix, key), max, min)
+            .await
+    }
+
+    async fn zrem<K, M>(&mut self, key: K, items: &[M]) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+        M: ToRedisArgs + Sync,
+    {
+        self.inner.zrem(Prefixed(&self.prefix, key), items).await
+    }
+
+    async fn lpush<K, V>(&mut self, key: K, values: &[V]) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Sync,
+    {
+        self.inner.lpush(Prefixed(&self.prefix, key), values).await
+    }
+
+    async fn rpush<K, V>(&mut self, key: K, values: &[V]) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Sync,
+    {
+        self.inner.rpush(Prefixed(&self.prefix, key), values).await
+    }
+
+    async fn lpop<K, V>(&mut self, key: K) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        self.inner.lpop(Prefixed(&self.prefix, key)).await
+    }
+
+    async fn rpop<K, V>(&mut self, key: K) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        self.inner.rpop(Prefixed(&self.prefix, key)).await
+    }
+
+    async fn lrange<K, V>(&mut self, key: K, start: isize, stop: isize) -> RedisResult<Vec<V>>
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        self.inner
+            .lrange(Prefixed(&self.prefix, key), start, stop)
+            .await
+    }
+
+    async fn ltrim<K>(&mut self, key: K, start: isize, stop: isize) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+    {
+        self.inner
+            .ltrim(Prefixed(&self.prefix, key), start, stop)
+            .await
+    }
+
+    async fn lindex<K, V>(&mut self, key: K, index: isize) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        self.inner.lindex(Prefixed(&self.prefix, key), index).await
+    }
+
+    async fn linsert_before<K, P, V>(&mut self, key: K, pivot: P, value: V) -> RedisResult<isize>
+    where
+        K: ToRedisArgs + Send,
+        P: ToRedisArgs + Send,
+        V: ToRedisArgs + Send,
+    {
+        self.inner
+            .linsert_before(Prefixed(&self.prefix, key), pivot, value)
+            .await
+    }
+
+    async fn linsert_after<K, P, V>(&mut self, key: K, pivot: P, value: V) -> RedisResult<isize>
+    where
+        K: ToRedisArgs + Send,
+        P: ToRedisArgs + Send,
+        V: ToRedisArgs + Send,
+    {
+        self.inner
+            .linsert_after(Prefixed(&self.prefix, key), pivot, value)
+            .await
+    }
+
+    async fn llen<K>(&mut self, key: K) -> RedisResult<usize>
+    where
+        K: ToRedisArgs + Send,
+    {
+        self.inner.llen(Prefixed(&self.prefix, key)).await
+    }
+
+    async fn blpop<K, V>(&mut self, key: K, timeout: Duration) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        let reply = self
+            .inner
+            .blpop(Prefixed(&self.prefix, key), timeout)
+            .await?;
+        self.strip_popped(reply)
+    }
+
+    async fn brpop<K, V>(&mut self, key: K, timeout: Duration) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        let reply = self
+            .inner
+            .brpop(Prefixed(&self.prefix, key), timeout)
+            .await?;
+        self.strip_popped(reply)
+    }
+
+    async fn lmove<K, D, V>(
+        &mut self,
+        key: K,
+        destination: D,
+        from: Direction,
+        to: Direction,
+    ) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        D: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        self.inner
+            .lmove(
+                Prefixed(&self.prefix, key),
+                Prefixed(&self.prefix, destination),
+                from,
+                to,
+            )
+            .await
+    }
+
+    async fn blmove<K, D, V>(
+        &mut self,
+        key: K,
+        destination: D,
+        from: Direction,
+        to: Direction,
+        timeout: Duration,
+    ) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        D: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        self.inner
+            .blmove(
+                Prefixed(&self.prefix, key),
+                Prefixed(&self.prefix, destination),
+                from,
+                to,
+                timeout,
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests_namespace {
+    use super::*;
+    use crate::MemoryConnection;
+
+    #[test]
+    fn test_prefixed_keys() {
+        let mut con = MemoryConnection::new();
+        let mut ca = Namespaced::new(Cache::from_connection(con.clone()), "svc:");
+        ca.set("k1", "v1").unwrap();
+        ca.sadd("tags", &["a", "b"]).unwrap();
+        assert_eq!(ca.get::<_, String>("k1").unwrap(), "v1");
+        let raw: String = redis::cmd("GET").arg("svc:k1").query(&mut con).unwrap();
+        assert_eq!(raw, "v1");
+        assert!(ca.sismember("tags", "a").unwrap());
+
+        ca.set("k2", "v2").unwrap();
+        ca.del(&["k1", "k2"]).unwrap();
+        let left: i64 = redis::cmd("EXISTS")
+            .arg(&["svc:k1", "svc:k2", "svc:tags"])
+            .query(&mut con)
+            .unwrap();
+        assert_eq!(left, 1);
+
+        ca.rpush("queue", &["job"]).unwrap();
+        let job: String = ca
+            .lmove("queue", "done", Direction::Left, Direction::Left)
+            .unwrap();
+        assert_eq!(job, "job");
+        let raw: Vec<String> = redis::cmd("LRANGE")
+            .arg("svc:done")
+            .arg(0)
+            .arg(-1)
+            .query(&mut con)
+            .unwrap();
+        assert_eq!(raw, ["job"]);
+
+        ca.rpush("queue", &["a", "b"]).unwrap();
+        let popped: (String, String) = ca.blpop("queue", Duration::from_secs(1)).unwrap();
+        assert_eq!(popped, ("queue".into(), "a".into()));
+        let popped: (String, String) = ca.brpop("queue", Duration::from_secs(1)).unwrap();
+        assert_eq!(popped, ("queue".into(), "b".into()));
+    }
+
+    #[test]
+    fn test_scan_match() {
+        let con = MemoryConnection::new();
+        let mut other = Cache::from_connection(con.clone());
+        other.set("user:1", "x").unwrap();
+        let mut ca = Namespaced::new(Cache::from_connection(con), "a*b:");
+        ca.set("user:1", "1").unwrap();
+        ca.set("user:2", "2").unwrap();
+        ca.set("order:1", "3").unwrap();
+        other.set("axb:user:3", "4").unwrap();
+
+        let mut keys: Vec<String> = ca.scan_match("user:*").unwrap();
+        keys.sort();
+        assert_eq!(keys, ["user:1", "user:2"]);
+    }
+
+    #[tokio::test]
+    async fn test_async_namespaced() {
+        let mut ca = Namespaced::new(AsyncCache::from_connection(MemoryConnection::new()), "svc:");
+        ca.hset("h", "f", 1).await.unwrap();
+        assert_eq!(ca.hget::<_, _, i32>("h", "f").await.unwrap(), 1);
+        let keys: Vec<String> = ca.scan_match("*").await.unwrap();
+        assert_eq!(keys, ["h"]);
+        let raw: i32 = ca.inner().hget("svc:h", "f").await.unwrap();
+        assert_eq!(raw, 1);
+
+        ca.rpush("queue", &["a"]).await.unwrap();
+        let timeout = Duration::from_millis(10);
+        let popped: (String, String) = ca.blpop("queue", timeout).await.unwrap();
+        assert_eq!(popped, ("queue".into(), "a".into()));
+        let popped: Option<(String, String)> = ca.brpop("queue", timeout).await.unwrap();
+        assert_eq!(popped, None);
+    }
+}
@@ -0,0 +1,1369 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use futures_util::StreamExt;
+use redis::{Direction, FromRedisValue, RedisResult, ToRedisArgs, Value};
+
+use crate::{
+    config::Connector, AsyncCache, IAsyncCache, ICache, RedisConfig, SetOptions, SetReply,
+};
+
+/// An in-process LRU tier in front of another cache.
+///
+/// String values read with `get` are kept locally for `ttl`, values written
+/// with `set` are stored locally as well, and every other write drops the
+/// local copy of its keys. Clones share the local tier.
+///
+/// A value written with `set_with` is kept until the expiry in its options,
+/// if that comes before `ttl`. The remaining TTL of a key read with `get` is
+/// not known, so without tracking `ttl` should be no longer than the TTL of
+/// the keys read through the near cache.
+///
+/// With `Tracking::Default`, `set` drops the local copy too: Redis stops
+/// tracking a key once it is written, so the next `get` has to read it again.
+#[derive(Clone)]
+pub struct NearCache<C> {
+    inner: C,
+    local: Arc<Local>,
+}
+
+impl<C> NearCache<C> {
+    pub fn new(inner: C, capacity: usize, ttl: Duration) -> Self {
+        Self::with_prefixes(inner, capacity, ttl, Vec::new(), true)
+    }
+
+    fn with_prefixes(
+        inner: C,
+        capacity: usize,
+        ttl: Duration,
+        prefixes: Vec<Vec<u8>>,
+        write_through: bool,
+    ) -> Self {
+        NearCache {
+            inner,
+            local: Arc::new(Local {
+                lru: Mutex::new(Lru::new(capacity)),
+                ttl,
+                prefixes,
+                write_through,
+                hits: AtomicU64::new(0),
+                misses: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.local.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.local.misses.load(Ordering::Relaxed)
+    }
+
+    /// Drops the local copy of `key`, e.g. after it was changed elsewhere.
+    pub fn invalidate<K: ToRedisArgs>(&self, key: K) {
+        self.local.invalidate(&key.to_redis_args());
+    }
+
+    pub fn clear(&self) {
+        self.local.lru().clear();
+    }
+
+    pub fn inner(&mut self) -> &mut C {
+        &mut self.inner
+    }
+}
+
+/// Which keys Redis reports as changed to a tracking near cache.
+pub enum Tracking {
+    /// Keys this client has read.
+    Default,
+    /// Every key starting with one of the prefixes, or every key if empty.
+    Broadcast(Vec<String>),
+}
+
+impl NearCache<AsyncCache> {
+    /// Connects a near cache whose local entries Redis invalidates when any
+    /// client changes them, using Redis 6 client side caching.
+    ///
+    /// Invalidations are redirected to a second connection subscribed to
+    /// `__redis__:invalidate`. If that connection is lost, the local tier is
+    /// cleared and stops caching, and all reads go to Redis.
+    pub async fn with_tracking(
+        config: RedisConfig<'_>,
+        capacity: usize,
+        ttl: Duration,
+        tracking: Tracking,
+    ) -> RedisResult<Self> {
+        let connector = Connector::new(config)?;
+        let mut sub = connector.client.get_tokio_connection().await?;
+        let id: i64 = redis::cmd("CLIENT").arg("ID").query_async(&mut sub).await?;
+        let mut sub = sub.into_pubsub();
+        sub.subscribe("__redis__:invalidate").await?;
+
+        let mut con = connector.connect_async().await?;
+        tracking_cmd(id, &tracking)
+            .query_async::<_, ()>(&mut con)
+            .await?;
+
+        // Only keys under the broadcast prefixes are ever invalidated.
+        let (prefixes, write_through) = match tracking {
+            Tracking::Default => (Vec::new(), false),
+            Tracking::Broadcast(prefixes) => {
+                (prefixes.into_iter().map(String::into_bytes).collect(), true)
+            }
+        };
+        let near = NearCache::with_prefixes(
+            AsyncCache::from_connection(con),
+            capacity,
+            ttl,
+            prefixes,
+            write_through,
+        );
+        let local = Arc::downgrade(&near.local);
+        tokio::spawn(async move {
+            let mut messages = sub.into_on_message();
+            while let Some(msg) = messages.next().await {
+                let Some(local) = local.upgrade() else {
+                    return;
+                };
+                local.apply_invalidation(msg.get_payload().unwrap_or(Value::Nil));
+            }
+            if let Some(local) = local.upgrade() {
+                local.lru().detach();
+            }
+        });
+        Ok(near)
+    }
+}
+
+fn tracking_cmd(redirect: i64, tracking: &Tracking) -> redis::Cmd {
+    let mut cmd = redis::cmd("CLIENT");
+    cmd.arg("TRACKING").arg("ON").arg("REDIRECT").arg(redirect);
+    if let Tracking::Broadcast(prefixes) = tracking {
+        cmd.arg("BCAST");
+        for prefix in prefixes {
+            cmd.arg("PREFIX").arg(prefix);
+        }
+    }
+    // Our own writes already update or drop the local copy.
+    cmd.arg("NOLOOP");
+    cmd
+}
+
+struct Local {
+    lru: Mutex<Lru>,
+    ttl: Duration,
+    /// Prefixes of the keys kept locally, every key if empty.
+    prefixes: Vec<Vec<u8>>,
+    /// Whether `set` values are kept locally, rather than read again.
+    write_through: bool,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Local {
+    fn lru(&self) -> MutexGuard<'_, Lru> {
+        self.lru.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Looks up a single key, returning the generation to pass to `store`
+    /// on a miss.
+    fn lookup(&self, keys: &[Vec<u8>]) -> Result<Value, u64> {
+        let mut lru = self.lru();
+        let hit = match keys {
+            [key] => lru.get(key),
+            _ => None,
+        };
+        match hit {
+            Some(value) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Ok(value)
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                Err(lru.generation)
+            }
+        }
+    }
+
+    /// Stores a value read from the inner cache, unless any key was
+    /// invalidated since the read started.
+    fn store(&self, keys: Vec<Vec<u8>>, value: &Value, generation: u64) {
+        let mut lru = self.lru();
+        if let ([key], Value::Data(_)) = (keys.as_slice(), value) {
+            if lru.generation == generation && self.covers(key) {
+                lru.insert(key.clone(), value.clone(), self.ttl);
+            }
+        }
+    }
+
+    fn write_through(&self, keys: &[Vec<u8>], value: Vec<Vec<u8>>, ttl: Duration) {
+        let mut lru = self.lru();
+        lru.invalidate(keys);
+        if let ([key], [value]) = (keys, value.as_slice()) {
+            if self.write_through && self.covers(key) {
+                lru.insert(key.clone(), Value::Data(value.clone()), ttl);
+            }
+        }
+    }
+
+    /// How long a value written with `options` may be kept locally, or
+    /// `None` if its expiry is not known.
+    fn set_ttl(&self, options: &SetOptions) -> Option<Duration> {
+        let args = options.to_redis_args();
+        if args.iter().any(|arg| arg.as_slice() == b"KEEPTTL") {
+            return None;
+        }
+        let mut ttl = self.ttl;
+        for pair in args.windows(2) {
+            let number = || std::str::from_utf8(&pair[1]).ok()?.parse::<u64>().ok();
+            let at = |since_epoch: Duration| {
+                let at = UNIX_EPOCH + since_epoch;
+                Some(at.duration_since(SystemTime::now()).unwrap_or_default())
+            };
+            let expiry = match pair[0].as_slice() {
+                b"EX" => Duration::from_secs(number()?),
+                b"PX" => Duration::from_millis(number()?),
+                b"EXAT" => at(Duration::from_secs(number()?))?,
+                b"PXAT" => at(Duration::from_millis(number()?))?,
+                _ => continue,
+            };
+            ttl = ttl.min(expiry);
+        }
+        Some(ttl)
+    }
+
+    fn covers(&self, key: &[u8]) -> bool {
+        self.prefixes.is_empty() || self.prefixes.iter().any(|p| key.starts_with(p))
+    }
+
+    fn invalidate(&self, keys: &[Vec<u8>]) {
+        self.lru().invalidate(keys);
+    }
+
+    /// Applies the payload of an invalidation message: a list of keys, or
+    /// nil when the whole database was flushed.
+    fn apply_invalidation(&self, payload: Value) {
+        match Vec::<Vec<u8>>::from_redis_value(&payload) {
+            Ok(keys) if payload != Value::Nil => self.invalidate(&keys),
+            _ => self.lru().clear(),
+        }
+    }
+}
+
+struct Slot {
+    value: Value,
+    expires_at: Instant,
+    tick: u64,
+}
+
+struct Lru {
+    capacity: usize,
+    tick: u64,
+    generation: u64,
+    slots: HashMap<Vec<u8>, Slot>,
+    order: BTreeMap<u64, Vec<u8>>,
+}
+
+impl Lru {
+    fn new(capacity: usize) -> Self {
+        Lru {
+            capacity,
+            tick: 0,
+            generation: 0,
+            slots: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    fn get(&mut self, key: &[u8]) -> Option<Value> {
+        let slot = self.slots.get_mut(key)?;
+        self.order.remove(&slot.tick);
+        if slot.expires_at <= Instant::now() {
+            self.slots.remove(key);
+            return None;
+        }
+        self.tick += 1;
+        slot.tick = self.tick;
+        self.order.insert(self.tick, key.to_vec());
+        Some(slot.value.clone())
+    }
+
+    fn insert(&mut self, key: Vec<u8>, value: Value, ttl: Duration) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.remove(&key);
+        while self.slots.len() >= self.capacity {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            self.slots.remove(&oldest);
+        }
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        let slot = Slot {
+            value,
+            expires_at: Instant::now() + ttl,
+            tick: self.tick,
+        };
+        self.slots.insert(key, slot);
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        if let Some(slot) = self.slots.remove(key) {
+            self.order.remove(&slot.tick);
+        }
+    }
+
+    fn invalidate(&mut self, keys: &[Vec<u8>]) {
+        self.generation += 1;
+        for key in keys {
+            self.remove(key);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.generation += 1;
+        self.slots.clear();
+        self.order.clear();
+    }
+
+    /// Stops caching for good, once entries can no longer be invalidated.
+    fn detach(&mut self) {
+        self.capacity = 0;
+        self.clear();
+    }
+}
+
+impl<C: ICache> ICache for NearCache<C> {
+    fn get<K, V>(&mut self, key: K) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        let keys = key.to_redis_args();
+        let value = match self.local.lookup(&keys) {
+            Ok(value) => value,
+            Err(generation) => {
+                let value: Value = self.inner.get(key)?;
+                self.local.store(keys, &value, generation);
+                value
+            }
+        };
+        V::from_redis_value(&value)
+    }
+
+    fn set<K, V>(&mut self, key: K, value: V) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        let (keys, args) = (key.to_redis_args(), value.to_redis_args());
+        let result = self.inner.set(key, value);
+        match result {
+            Ok(()) => self.local.write_through(&keys, args, self.local.ttl),
+            Err(_) => self.local.invalidate(&keys),
+        }
+        result
+    }
+
+    fn set_with<K, V, R>(
+        &mut self,
+        key: K,
+        value: V,
+        options: SetOptions,
+    ) -> RedisResult<SetReply<R>>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+        R: FromRedisValue,
+    {
+        let (keys, args) = (key.to_redis_args(), value.to_redis_args());
+        let ttl = self.local.set_ttl(&options);
+        let result = self.inner.set_with(key, value, options);
+        match (&result, ttl) {
+            (Ok(reply), Some(ttl)) if reply.written => self.local.write_through(&keys, args, ttl),
+            _ => self.local.invalidate(&keys),
+        }
+        result
+    }
+
+    fn del<K>(&mut self, key: K) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.del(key);
+        self.local.invalidate(&keys);
+        result
+    }
+
+    fn incr<K>(&mut self, key: K) -> RedisResult<i32>
+    where
+        K: ToRedisArgs,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.incr(key);
+        self.local.invalidate(&keys);
+        result
+    }
+
+    fn exists<K>(&mut self, key: K) -> RedisResult<bool>
+    where
+        K: ToRedisArgs,
+    {
+        self.inner.exists(key)
+    }
+
+    fn expire<K>(&mut self, key: K, sec: i32) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.expire(key, sec);
+        self.local.invalidate(&keys);
+        result
+    }
+
+    fn sadd<K, V>(&mut self, key: K, values: &[V]) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.sadd(key, values);
+        self.local.invalidate(&keys);
+        result
+    }
+
+    fn smembers<K, V>(&mut self, key: K) -> RedisResult<Vec<V>>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.inner.smembers(key)
+    }
+
+    fn srem<K, V>(&mut self, key: K, values: &[V]) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.srem(key, values);
+        self.local.invalidate(&keys);
+        result
+    }
+
+    fn scard<K>(&mut self, key: K) -> RedisResult<usize>
+    where
+        K: ToRedisArgs,
+    {
+        self.inner.scard(key)
+    }
+
+    fn sismember<K, V>(&mut self, key: K, value: V) -> RedisResult<bool>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.inner.sismember(key, value)
+    }
+
+    fn hset<K, F, V>(&mut self, key: K, field: F, value: V) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        F: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.hset(key, field, value);
+        self.local.invalidate(&keys);
+        result
+    }
+
+    fn hget<K, F, V>(&mut self, key: K, field: F) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        F: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.inner.hget(key, field)
+    }
+
+    fn hmset<K, F, V>(&mut self, key: K, values: &[(F, V)]) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        F: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.hmset(key, values);
+        self.local.invalidate(&keys);
+        result
+    }
+
+    fn hmget<K, F, V>(&mut self, key: K, fields: &[F]) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        F: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.inner.hmget(key, fields)
+    }
+
+    fn hsetall<K, V>(&mut self, key: K, value: V) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.hsetall(key, value);
+        self.local.invalidate(&keys);
+        result
+    }
+
+    fn hgetall<K, V>(&mut self, key: K) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.inner.hgetall(key)
+    }
+
+    fn hexists<K, F>(&mut self, key: K, field: F) -> RedisResult<bool>
+    where
+        K: ToRedisArgs,
+        F: ToRedisArgs,
+    {
+        self.inner.hexists(key, field)
+    }
+
+    fn hdel<K, F>(&mut self, key: K, fields: &[F]) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        F: ToRedisArgs,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.hdel(key, fields);
+        self.local.invalidate(&keys);
+        result
+    }
+
+    fn zadd<K, S, M>(&mut self, key: K, items: &[(S, M)]) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        S: ToRedisArgs,
+        M: ToRedisArgs,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.zadd(key, items);
+        self.local.invalidate(&keys);
+        result
+    }
+
+    fn zrange_by_score<K, M, V>(&mut self, key: K, min: M, max: M) -> RedisResult<Vec<V>>
+    where
+        K: ToRedisArgs,
+        M: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.inner.zrange_by_score(key, min, max)
+    }
+
+    fn zrevrange_by_score<K, M, V>(&mut self, key: K, max: M, min: M) -> RedisResult<Vec<V>>
+    where
+        K: ToRedisArgs,
+        M: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.inner.zrevrange_by_score(key, max, min)
+    }
+
+    fn zrem<K, M>(&mut self, key: K, items: &[M]) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        M: ToRedisArgs,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.zrem(key, items);
+        self.local.invalidate(&keys);
+        result
+    }
+
+    fn lpush<K, V>(&mut self, key: K, values: &[V]) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.lpush(key, values);
+        self.local.invalidate(&keys);
+        result
+    }
+
+    fn rpush<K, V>(&mut self, key: K, values: &[V]) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.rpush(key, values);
+        self.local.invalidate(&keys);
+        result
+    }
+
+    fn lpop<K, V>(&mut self, key: K) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.lpop(key);
+        self.local.invalidate(&keys);
+        result
+    }
+
+    fn rpop<K, V>(&mut self, key: K) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.rpop(key);
+        self.local.invalidate(&keys);
+        result
+    }
+
+    fn lrange<K, V>(&mut self, key: K, start: isize, stop: isize) -> RedisResult<Vec<V>>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.inner.lrange(key, start, stop)
+    }
+
+    fn ltrim<K>(&mut self, key: K, start: isize, stop: isize) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.ltrim(key, start, stop);
+        self.local.invalidate(&keys);
+        result
+    }
+
+    fn lindex<K, V>(&mut self, key: K, index: isize) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.inner.lindex(key, index)
+    }
+
+    fn linsert_before<K, P, V>(&mut self, key: K, pivot: P, value: V) -> RedisResult<isize>
+    where
+        K: ToRedisArgs,
+        P: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.linsert_before(key, pivot, value);
+        self.local.invalidate(&keys);
+        result
+    }
+
+    fn linsert_after<K, P, V>(&mut self, key: K, pivot: P, value: V) -> RedisResult<isize>
+    where
+        K: ToRedisArgs,
+        P: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.linsert_after(key, pivot, value);
+        self.local.invalidate(&keys);
+        result
+    }
+
+    fn llen<K>(&mut self, key: K) -> RedisResult<usize>
+    where
+        K: ToRedisArgs,
+    {
+        self.inner.llen(key)
+    }
+
+    fn blpop<K, V>(&mut self, key: K, timeout: Duration) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.blpop(key, timeout);
+        self.local.invalidate(&keys);
+        result
+    }
+
+    fn brpop<K, V>(&mut self, key: K, timeout: Duration) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.brpop(key, timeout);
+        self.local.invalidate(&keys);
+        result
+    }
+
+    fn lmove<K, D, V>(
+        &mut self,
+        key: K,
+        destination: D,
+        from: Direction,
+        to: Direction,
+    ) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        D: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        let mut keys = key.to_redis_args();
+        keys.extend(destination.to_redis_args());
+        let result = self.inner.lmove(key, destination, from, to);
+        self.local.invalidate(&keys);
+        result
+    }
+
+    fn blmove<K, D, V>(
+        &mut self,
+        key: K,
+        destination: D,
+        from: Direction,
+        to: Direction,
+        timeout: Duration,
+    ) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        D: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        let mut keys = key.to_redis_args();
+        keys.extend(destination.to_redis_args());
+        let result = self.inner.blmove(key, destination, from, to, timeout);
+        self.local.invalidate(&keys);
+        result
+    }
+}
+
+impl<C: IAsyncCache> IAsyncCache for NearCache<C> {
+    async fn get<K, V>(&mut self, key: K) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        let keys = key.to_redis_args();
+        let value = match self.local.lookup(&keys) {
+            Ok(value) => value,
+            Err(generation) => {
+                let value: Value = self.inner.get(key).await?;
+                self.local.store(keys, &value, generation);
+                value
+            }
+        };
+        V::from_redis_value(&value)
+    }
+
+    async fn set<K, V>(&mut self, key: K, value: V) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Send,
+    {
+        let (keys, args) = (key.to_redis_args(), value.to_redis_args());
+        let result = self.inner.set(key, value).await;
+        match result {
+            Ok(()) => self.local.write_through(&keys, args, self.local.ttl),
+            Err(_) => self.local.invalidate(&keys),
+        }
+        result
+    }
+
+    async fn set_with<K, V, R>(
+        &mut self,
+        key: K,
+        value: V,
+        options: SetOptions,
+    ) -> RedisResult<SetReply<R>>
+    where
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Send,
+        R: FromRedisValue,
+    {
+        let (keys, args) = (key.to_redis_args(), value.to_redis_args());
+        let ttl = self.local.set_ttl(&options);
+        let result = self.inner.set_with(key, value, options).await;
+        match (&result, ttl) {
+            (Ok(reply), Some(ttl)) if reply.written => self.local.write_through(&keys, args, ttl),
+            _ => self.local.invalidate(&keys),
+        }
+        result
+    }
+
+    async fn del<K>(&mut self, key: K) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.del(key).await;
+        self.local.invalidate(&keys);
+        result
+    }
+
+    async fn incr<K>(&mut self, key: K) -> RedisResult<i32>
+    where
+        K: ToRedisArgs + Send,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.incr(key).await;
+        self.local.invalidate(&keys);
+        result
+    }
+
+    async fn exists<K>(&mut self, key: K) -> RedisResult<bool>
+    where
+        K: ToRedisArgs + Send,
+    {
+        self.inner.exists(key).await
+    }
+
+    async fn expire<K>(&mut self, key: K, sec: i32) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.expire(key, sec).await;
+        self.local.invalidate(&keys);
+        result
+    }
+
+    async fn sadd<K, V>(&mut self, key: K, values: &[V]) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Sync,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.sadd(key, values).await;
+        self.local.invalidate(&keys);
+        result
+    }
+
+    async fn smembers<K, V>(&mut self, key: K) -> RedisResult<Vec<V>>
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        self.inner.smembers(key).await
+    }
+
+    async fn srem<K, V>(&mut self, key: K, values: &[V]) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Sync,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.srem(key, values).await;
+        self.local.invalidate(&keys);
+        result
+    }
+
+    async fn scard<K>(&mut self, key: K) -> RedisResult<usize>
+    where
+        K: ToRedisArgs + Send,
+    {
+        self.inner.scard(key).await
+    }
+
+    async fn sismember<K, V>(&mut self, key: K, value: V) -> RedisResult<bool>
+    where
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Send,
+    {
+        self.inner.sismember(key, value).await
+    }
+
+    async fn hset<K, F, V>(&mut self, key: K, field: F, value: V) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+        F: ToRedisArgs + Send,
+        V: ToRedisArgs + Send,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.hset(key, field, value).await;
+        self.local.invalidate(&keys);
+        result
+    }
+
+    async fn hget<K, F, V>(&mut self, key: K, field: F) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        F: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        self.inner.hget(key, field).await
+    }
+
+    async fn hmset<K, F, V>(&mut self, key: K, values: &[(F, V)]) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+        F: ToRedisArgs + Sync,
+        V: ToRedisArgs + Sync,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.hmset(key, values).await;
+        self.local.invalidate(&keys);
+        result
+    }
+
+    async fn hmget<K, F, V>(&mut self, key: K, fields: &[F]) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        F: ToRedisArgs + Sync,
+        V: FromRedisValue,
+    {
+        self.inner.hmget(key, fields).await
+    }
+
+    async fn hsetall<K, V>(&mut self, key: K, value: V) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Send,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.hsetall(key, value).await;
+        self.local.invalidate(&keys);
+        result
+    }
+
+    async fn hgetall<K, V>(&mut self, key: K) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        self.inner.hgetall(key).await
+    }
+
+    async fn hexists<K, F>(&mut self, key: K, field: F) -> RedisResult<bool>
+    where
+        K: ToRedisArgs + Send,
+        F: ToRedisArgs + Send,
+    {
+        self.inner.hexists(key, field).await
+    }
+
+    async fn hdel<K, F>(&mut self, key: K, fields: &[F]) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+        F: ToRedisArgs + Sync,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.hdel(key, fields).await;
+        self.local.invalidate(&keys);
+        result
+    }
+
+    async fn zadd<K, S, M>(&mut self, key: K, items: &[(S, M)]) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+        S: ToRedisArgs + Sync,
+        M: ToRedisArgs + Sync,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.zadd(key, items).await;
+        self.local.invalidate(&keys);
+        result
+    }
+
+    async fn zrange_by_score<K, M, V>(&mut self, key: K, min: M, max: M) -> RedisResult<Vec<V>>
+    where
+        K: ToRedisArgs + Send,
+        M: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        self.inner.zrange_by_score(key, min, max).await
+    }
+
+    async fn zrevrange_by_score<K, M, V>(&mut self, key: K, max: M, min: M) -> RedisResult<Vec<V>>
+    where
+        K: ToRedisArgs + Send,
+        M: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        self.inner.zrevrange_by_score(key, max, min).await
+    }
+
+    async fn zrem<K, M>(&mut self, key: K, items: &[M]) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+        M: ToRedisArgs + Sync,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.zrem(key, items).await;
+        self.local.invalidate(&keys);
+        result
+    }
+
+    async fn lpush<K, V>(&mut self, key: K, values: &[V]) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Sync,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.lpush(key, values).await;
+        self.local.invalidate(&keys);
+        result
+    }
+
+    async fn rpush<K, V>(&mut self, key: K, values: &[V]) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+        V: ToRedisArgs + Sync,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.rpush(key, values).await;
+        self.local.invalidate(&keys);
+        result
+    }
+
+    async fn lpop<K, V>(&mut self, key: K) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.lpop(key).await;
+        self.local.invalidate(&keys);
+        result
+    }
+
+    async fn rpop<K, V>(&mut self, key: K) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.rpop(key).await;
+        self.local.invalidate(&keys);
+        result
+    }
+
+    async fn lrange<K, V>(&mut self, key: K, start: isize, stop: isize) -> RedisResult<Vec<V>>
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        self.inner.lrange(key, start, stop).await
+    }
+
+    async fn ltrim<K>(&mut self, key: K, start: isize, stop: isize) -> RedisResult<()>
+    where
+        K: ToRedisArgs + Send,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.ltrim(key, start, stop).await;
+        self.local.invalidate(&keys);
+        result
+    }
+
+    async fn lindex<K, V>(&mut self, key: K, index: isize) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        self.inner.lindex(key, index).await
+    }
+
+    async fn linsert_before<K, P, V>(&mut self, key: K, pivot: P, value: V) -> RedisResult<isize>
+    where
+        K: ToRedisArgs + Send,
+        P: ToRedisArgs + Send,
+        V: ToRedisArgs + Send,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.linsert_before(key, pivot, value).await;
+        self.local.invalidate(&keys);
+        result
+    }
+
+    async fn linsert_after<K, P, V>(&mut self, key: K, pivot: P, value: V) -> RedisResult<isize>
+    where
+        K: ToRedisArgs + Send,
+        P: ToRedisArgs + Send,
+        V: ToRedisArgs + Send,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.linsert_after(key, pivot, value).await;
+        self.local.invalidate(&keys);
+        result
+    }
+
+    async fn llen<K>(&mut self, key: K) -> RedisResult<usize>
+    where
+        K: ToRedisArgs + Send,
+    {
+        self.inner.llen(key).await
+    }
+
+    async fn blpop<K, V>(&mut self, key: K, timeout: Duration) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.blpop(key, timeout).await;
+        self.local.invalidate(&keys);
+        result
+    }
+
+    async fn brpop<K, V>(&mut self, key: K, timeout: Duration) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        let keys = key.to_redis_args();
+        let result = self.inner.brpop(key, timeout).await;
+        self.local.invalidate(&keys);
+        result
+    }
+
+    async fn lmove<K, D, V>(
+        &mut self,
+        key: K,
+        destination: D,
+        from: Direction,
+        to: Direction,
+    ) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        D: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        let mut keys = key.to_redis_args();
+        keys.extend(destination.to_redis_args());
+        let result = self.inner.lmove(key, destination, from, to).await;
+        self.local.invalidate(&keys);
+        result
+    }
+
+    async fn blmove<K, D, V>(
+        &mut self,
+        key: K,
+        destination: D,
+        from: Direction,
+        to: Direction,
+        timeout: Duration,
+    ) -> RedisResult<V>
+    where
+        K: ToRedisArgs + Send,
+        D: ToRedisArgs + Send,
+        V: FromRedisValue,
+    {
+        let mut keys = key.to_redis_args();
+        keys.extend(destination.to_redis_args());
+        let result = self.inner.blmove(key, destination, from, to, timeout).await;
+        self.local.invalidate(&keys);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests_near_cache {
+    use redis::{ExistenceCheck, SetExpiry};
+
+    use super::*;
+    use crate::{AsyncCache, Cache, MemoryConnection};
+
+    #[test]
+    fn test_hits_and_invalidation() {
+        let con = MemoryConnection::new();
+        let mut other = Cache::from_connection(con.clone());
+        let mut ca = NearCache::new(Cache::from_connection(con), 16, Duration::from_secs(60));
+
+        other.set("k1", "v1").unwrap();
+        assert_eq!(ca.get::<_, String>("k1").unwrap(), "v1");
+        assert_eq!(ca.get::<_, String>("k1").unwrap(), "v1");
+        assert_eq!((ca.hits(), ca.misses()), (1, 1));
+
+        // Changes made by other clients are not seen until invalidated.
+        other.set("k1", "v2").unwrap();
+        assert_eq!(ca.get::<_, String>("k1").unwrap(), "v1");
+        ca.invalidate("k1");
+        assert_eq!(ca.get::<_, String>("k1").unwrap(), "v2");
+
+        ca.set("k1", 3).unwrap();
+        assert_eq!(ca.get::<_, i32>("k1").unwrap(), 3);
+        assert_eq!((ca.hits(), ca.misses()), (3, 2));
+
+        ca.incr("k1").unwrap();
+        assert_eq!(ca.get::<_, i32>("k1").unwrap(), 4);
+        ca.del("k1").unwrap();
+        assert_eq!(ca.get::<_, Option<i32>>("k1").unwrap(), None);
+        assert_eq!((ca.hits(), ca.misses()), (3, 4));
+    }
+
+    #[test]
+    fn test_eviction_and_ttl() {
+        let mut ca = NearCache::new(
+            Cache::from_connection(MemoryConnection::new()),
+            2,
+            Duration::from_millis(20),
+        );
+        ca.set("k1", "v1").unwrap();
+        ca.set("k2", "v2").unwrap();
+        ca.get::<_, String>("k1").unwrap();
+        ca.set("k3", "v3").unwrap();
+        assert_eq!(ca.local.lru().slots.len(), 2);
+        assert!(ca.local.lru().slots.contains_key(b"k1".as_slice()));
+        assert!(!ca.local.lru().slots.contains_key(b"k2".as_slice()));
+
+        std::thread::sleep(Duration::from_millis(30));
+        let misses = ca.misses();
+        assert_eq!(ca.get::<_, String>("k1").unwrap(), "v1");
+        assert_eq!(ca.misses(), misses + 1);
+    }
+
+    #[test]
+    fn test_invalidation_messages() {
+        let mut ca = NearCache::new(
+            Cache::from_connection(MemoryConnection::new()),
+            16,
+            Duration::from_secs(60),
+        );
+        ca.set("k1", "v1").unwrap();
+        ca.set("k2", "v2").unwrap();
+        ca.set("k3", "v3").unwrap();
+
+        let keys = Value::Bulk(vec![
+            Value::Data(b"k1".to_vec()),
+            Value::Data(b"k2".to_vec()),
+        ]);
+        ca.local.apply_invalidation(keys);
+        assert_eq!(ca.local.lru().slots.len(), 1);
+        ca.local.apply_invalidation(Value::Nil);
+        assert!(ca.local.lru().slots.is_empty());
+
+        ca.local.lru().detach();
+        ca.set("k1", "v1").unwrap();
+        assert_eq!(ca.get::<_, String>("k1").unwrap(), "v1");
+        assert!(ca.local.lru().slots.is_empty());
+    }
+
+    #[test]
+    fn test_tracking_cmd() {
+        let cmd = tracking_cmd(7, &Tracking::Default);
+        assert_eq!(
+            cmd.get_packed_command(),
+            redis::cmd("CLIENT")
+                .arg(&["TRACKING", "ON", "REDIRECT", "7", "NOLOOP"])
+                .get_packed_command()
+        );
+        let cmd = tracking_cmd(7, &Tracking::Broadcast(vec!["user:".into()]));
+        assert_eq!(
+            cmd.get_packed_command(),
+            redis::cmd("CLIENT")
+                .arg(&["TRACKING", "ON", "REDIRECT", "7", "BCAST", "PREFIX", "user:", "NOLOOP"])
+                .get_packed_command()
+        );
+    }
+
+    #[test]
+    fn test_write_through() {
+        let con = MemoryConnection::new();
+        let mut other = Cache::from_connection(con.clone());
+
+        // Like `Tracking::Default`: Redis sends nothing for a key written
+        // by this client until it is read again.
+        let mut ca = NearCache::with_prefixes(
+            Cache::from_connection(con.clone()),
+            16,
+            Duration::from_secs(60),
+            Vec::new(),
+            false,
+        );
+        ca.set("k1", "v1").unwrap();
+        other.set("k1", "v2").unwrap();
+        assert_eq!(ca.get::<_, String>("k1").unwrap(), "v2");
+
+        let mut ca = NearCache::with_prefixes(
+            Cache::from_connection(con),
+            16,
+            Duration::from_secs(60),
+            vec![b"user:".to_vec()],
+            true,
+        );
+        ca.set("user:1", "a").unwrap();
+        ca.set("k2", "b").unwrap();
+        assert!(ca.local.lru().slots.contains_key(b"user:1".as_slice()));
+        assert!(!ca.local.lru().slots.contains_key(b"k2".as_slice()));
+
+        // Redis broadcasts nothing for keys outside the prefixes.
+        other.set("k3", "v1").unwrap();
+        assert_eq!(ca.get::<_, String>("k3").unwrap(), "v1");
+        other.set("k3", "v2").unwrap();
+        assert_eq!(ca.get::<_, String>("k3").unwrap(), "v2");
+        assert!(!ca.local.lru().slots.contains_key(b"k3".as_slice()));
+    }
+
+    #[test]
+    fn test_set_with_ttl() {
+        let mut ca = NearCache::new(
+            Cache::from_connection(MemoryConnection::new()),
+            16,
+            Duration::from_secs(60),
+        );
+        let px = SetOptions::default().with_expiration(SetExpiry::PX(20));
+        ca.set_with::<_, _, ()>("k1", "v1", px).unwrap();
+        let ex = SetOptions::default().with_expiration(SetExpiry::EX(10));
+        ca.set_with::<_, _, ()>("k2", "v2", ex).unwrap();
+        let keep = SetOptions::default().with_expiration(SetExpiry::KEEPTTL);
+        ca.set_with::<_, _, ()>("k2", "v3", keep).unwrap();
+        {
+            let lru = ca.local.lru();
+            let k1 = &lru.slots[b"k1".as_slice()];
+            assert!(k1.expires_at <= Instant::now() + Duration::from_millis(20));
+            assert!(!lru.slots.contains_key(b"k2".as_slice()));
+        }
+        let nx = SetOptions::default().conditional_set(ExistenceCheck::NX);
+        ca.set_with::<_, _, ()>("k2", "v4", nx).unwrap();
+        assert!(!ca.local.lru().slots.contains_key(b"k2".as_slice()));
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(ca.get::<_, Option<String>>("k1").unwrap(), None);
+        let ex = SetOptions::default().with_expiration(SetExpiry::EX(10));
+        ca.set_with::<_, _, ()>("k1", "v1", ex).unwrap();
+        let k1 = ca.local.lru().slots[b"k1".as_slice()].expires_at;
+        assert!(k1 <= Instant::now() + Duration::from_secs(10));
+        assert!(k1 > Instant::now() + Duration::from_secs(9));
+    }
+
+    #[tokio::test]
+    async fn test_async_near_cache() {
+        let ca = NearCache::new(
+            AsyncCache::from_connection(MemoryConnection::new()),
+            16,
+            Duration::from_secs(60),
+        );
+        let mut ca2 = ca.clone();
+        let mut ca = ca;
+        ca.set("k1", "v1").await.unwrap();
+        assert_eq!(ca2.get::<_, String>("k1").await.unwrap(), "v1");
+        assert_eq!(ca.hits(), 1);
+        ca2.expire("k1", 0).await.unwrap();
+        assert_eq!(ca.get::<_, Option<String>>("k1").await.unwrap(), None);
+    }
+}
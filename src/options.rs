@@ -0,0 +1,106 @@
+use redis::{FromRedisValue, RedisResult, ToRedisArgs, Value};
+
+/// Options for `set_with`, written after the key and value of a `SET`.
+pub use redis::SetOptions;
+
+/// What a `set_with` call did.
+#[derive(Debug, PartialEq)]
+pub struct SetReply<V> {
+    /// Whether the value was written; false when an `NX`/`XX` check failed.
+    pub written: bool,
+    /// The previous value, only requested with `get(true)`.
+    pub previous: Option<V>,
+}
+
+impl<V: FromRedisValue> SetReply<V> {
+    pub(crate) fn from_reply(reply: &Value, options: &SetOptions) -> RedisResult<Self> {
+        // `SetOptions` keeps its fields private, so read back what it sends.
+        let args = options.to_redis_args();
+        let has = |flag: &[u8]| args.iter().any(|arg| arg.as_slice() == flag);
+        if !has(b"GET") {
+            return Ok(SetReply {
+                written: !matches!(reply, Value::Nil),
+                previous: None,
+            });
+        }
+        let previous: Option<V> = FromRedisValue::from_redis_value(reply)?;
+        let written = if has(b"NX") {
+            previous.is_none()
+        } else if has(b"XX") {
+            previous.is_some()
+        } else {
+            true
+        };
+        Ok(SetReply { written, previous })
+    }
+}
+
+#[cfg(test)]
+mod tests_options {
+    use redis::{ExistenceCheck, SetExpiry};
+
+    use super::*;
+    use crate::{AsyncCache, Cache, IAsyncCache, ICache, MemoryConnection};
+
+    #[test]
+    fn test_set_with() {
+        let mut con = MemoryConnection::new();
+        let mut ca = Cache::from_connection(con.clone());
+
+        let opts = SetOptions::default()
+            .conditional_set(ExistenceCheck::NX)
+            .with_expiration(SetExpiry::EX(60));
+        let reply: SetReply<String> = ca.set_with("k1", "v1", opts).unwrap();
+        assert_eq!(
+            reply,
+            SetReply {
+                written: true,
+                previous: None
+            }
+        );
+        let ttl: i64 = redis::cmd("TTL").arg("k1").query(&mut con).unwrap();
+        assert_eq!(ttl, 60);
+
+        let opts = SetOptions::default().conditional_set(ExistenceCheck::NX);
+        let reply: SetReply<String> = ca.set_with("k1", "v2", opts).unwrap();
+        assert!(!reply.written);
+        assert_eq!(ca.get::<_, String>("k1").unwrap(), "v1");
+
+        let opts = SetOptions::default()
+            .get(true)
+            .with_expiration(SetExpiry::KEEPTTL);
+        let reply: SetReply<String> = ca.set_with("k1", "v3", opts).unwrap();
+        assert_eq!(
+            reply,
+            SetReply {
+                written: true,
+                previous: Some("v1".into())
+            }
+        );
+        let ttl: i64 = redis::cmd("TTL").arg("k1").query(&mut con).unwrap();
+        assert_eq!(ttl, 60);
+
+        let opts = SetOptions::default()
+            .conditional_set(ExistenceCheck::XX)
+            .get(true);
+        let reply: SetReply<String> = ca.set_with("k2", "v1", opts).unwrap();
+        assert_eq!(
+            reply,
+            SetReply {
+                written: false,
+                previous: None
+            }
+        );
+        assert!(!ca.exists("k2").unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_async_set_with() {
+        let mut ca = AsyncCache::from_connection(MemoryConnection::new());
+        let opts = SetOptions::default().with_expiration(SetExpiry::PX(20));
+        let reply: SetReply<String> = ca.set_with("k1", "v1", opts).await.unwrap();
+        assert!(reply.written);
+        tokio::time::sleep(std::time::Duration::from_millis(30)).await;
+        assert!(!ca.exists("k1").await.unwrap());
+    }
+}
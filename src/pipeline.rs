@@ -0,0 +1,480 @@
+use redis::{Direction, ErrorKind, RedisError, ToRedisArgs};
+
+use crate::SetOptions;
+
+/// How often `transaction` runs `build` before giving up on watched keys
+/// that keep changing.
+pub(crate) const TRANSACTION_ATTEMPTS: usize = 16;
+
+pub(crate) fn transaction_conflict() -> RedisError {
+    (
+        ErrorKind::ExecAbortError,
+        "Transaction aborted",
+        format!("watched keys changed during all {TRANSACTION_ATTEMPTS} attempts"),
+    )
+        .into()
+}
+
+/// Queues cache operations to send to the server in one round trip.
+///
+/// Created by `Cache::pipeline` or `AsyncCache::pipeline`. Every queued
+/// operation adds its reply to the result of `query`, in order, unless it is
+/// followed by `ignore`. The result can be read as a tuple or a `Vec`.
+pub struct Pipeline<'a, T> {
+    pub(crate) cache: &'a mut T,
+    pub(crate) pipe: redis::Pipeline,
+}
+
+impl<'a, T> Pipeline<'a, T> {
+    pub(crate) fn new(cache: &'a mut T) -> Self {
+        Pipeline {
+            cache,
+            pipe: redis::pipe(),
+        }
+    }
+
+    /// Wraps the queued operations in `MULTI`/`EXEC`, so they run without
+    /// other clients' commands in between. The result is nil if the
+    /// transaction was aborted because a watched key changed.
+    pub fn atomic(&mut self) -> &mut Self {
+        self.pipe.atomic();
+        self
+    }
+
+    /// The cache the pipeline runs on, e.g. to read values inside a
+    /// `transaction` before queueing the writes.
+    pub fn cache(&mut self) -> &mut T {
+        self.cache
+    }
+
+    /// Leaves the reply of the last queued operation out of the result.
+    pub fn ignore(&mut self) -> &mut Self {
+        self.pipe.ignore();
+        self
+    }
+
+    pub fn get<K: ToRedisArgs>(&mut self, key: K) -> &mut Self {
+        self.pipe.cmd("GET").arg(key);
+        self
+    }
+
+    pub fn set<K: ToRedisArgs, V: ToRedisArgs>(&mut self, key: K, value: V) -> &mut Self {
+        self.pipe.cmd("SET").arg(key).arg(value);
+        self
+    }
+
+    /// Queues a `SET` with options. Its reply is the raw one: the previous
+    /// value with `get(true)`, otherwise `OK`, or nil if nothing was written.
+    pub fn set_with<K, V>(&mut self, key: K, value: V, options: SetOptions) -> &mut Self
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.pipe.cmd("SET").arg(key).arg(value).arg(options);
+        self
+    }
+
+    pub fn del<K: ToRedisArgs>(&mut self, key: K) -> &mut Self {
+        self.pipe.cmd("DEL").arg(key);
+        self
+    }
+
+    pub fn incr<K: ToRedisArgs>(&mut self, key: K) -> &mut Self {
+        self.pipe.cmd("INCR").arg(key);
+        self
+    }
+
+    pub fn exists<K: ToRedisArgs>(&mut self, key: K) -> &mut Self {
+        self.pipe.cmd("EXISTS").arg(key);
+        self
+    }
+
+    pub fn expire<K: ToRedisArgs>(&mut self, key: K, sec: i32) -> &mut Self {
+        self.pipe.cmd("EXPIRE").arg(key).arg(sec);
+        self
+    }
+
+    pub fn sadd<K: ToRedisArgs, V: ToRedisArgs>(&mut self, key: K, values: &[V]) -> &mut Self {
+        self.pipe.cmd("SADD").arg(key).arg(values);
+        self
+    }
+
+    pub fn smembers<K: ToRedisArgs>(&mut self, key: K) -> &mut Self {
+        self.pipe.cmd("SMEMBERS").arg(key);
+        self
+    }
+
+    pub fn srem<K: ToRedisArgs, V: ToRedisArgs>(&mut self, key: K, values: &[V]) -> &mut Self {
+        self.pipe.cmd("SREM").arg(key).arg(values);
+        self
+    }
+
+    pub fn scard<K: ToRedisArgs>(&mut self, key: K) -> &mut Self {
+        self.pipe.cmd("SCARD").arg(key);
+        self
+    }
+
+    pub fn sismember<K: ToRedisArgs, V: ToRedisArgs>(&mut self, key: K, value: V) -> &mut Self {
+        self.pipe.cmd("SISMEMBER").arg(key).arg(value);
+        self
+    }
+
+    pub fn hset<K, F, V>(&mut self, key: K, field: F, value: V) -> &mut Self
+    where
+        K: ToRedisArgs,
+        F: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.pipe.cmd("HSET").arg(key).arg(field).arg(value);
+        self
+    }
+
+    pub fn hget<K: ToRedisArgs, F: ToRedisArgs>(&mut self, key: K, field: F) -> &mut Self {
+        self.pipe.cmd("HGET").arg(key).arg(field);
+        self
+    }
+
+    pub fn hmset<K, F, V>(&mut self, key: K, values: &[(F, V)]) -> &mut Self
+    where
+        K: ToRedisArgs,
+        F: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.pipe.cmd("HMSET").arg(key).arg(values);
+        self
+    }
+
+    pub fn hmget<K: ToRedisArgs, F: ToRedisArgs>(&mut self, key: K, fields: &[F]) -> &mut Self {
+        self.pipe.cmd("HMGET").arg(key).arg(fields);
+        self
+    }
+
+    pub fn hsetall<K: ToRedisArgs, V: ToRedisArgs>(&mut self, key: K, value: V) -> &mut Self {
+        self.pipe.cmd("HMSET").arg(key).arg(value);
+        self
+    }
+
+    pub fn hgetall<K: ToRedisArgs>(&mut self, key: K) -> &mut Self {
+        self.pipe.cmd("HGETALL").arg(key);
+        self
+    }
+
+    pub fn hexists<K: ToRedisArgs, F: ToRedisArgs>(&mut self, key: K, field: F) -> &mut Self {
+        self.pipe.cmd("HEXISTS").arg(key).arg(field);
+        self
+    }
+
+    pub fn hdel<K: ToRedisArgs, F: ToRedisArgs>(&mut self, key: K, fields: &[F]) -> &mut Self {
+        self.pipe.cmd("HDEL").arg(key).arg(fields);
+        self
+    }
+
+    pub fn zadd<K, S, M>(&mut self, key: K, items: &[(S, M)]) -> &mut Self
+    where
+        K: ToRedisArgs,
+        S: ToRedisArgs,
+        M: ToRedisArgs,
+    {
+        self.pipe.cmd("ZADD").arg(key).arg(items);
+        self
+    }
+
+    pub fn zrange_by_score<K: ToRedisArgs, M: ToRedisArgs>(
+        &mut self,
+        key: K,
+        min: M,
+        max: M,
+    ) -> &mut Self {
+        self.pipe.cmd("ZRANGEBYSCORE").arg(key).arg(min).arg(max);
+        self
+    }
+
+    pub fn zrevrange_by_score<K: ToRedisArgs, M: ToRedisArgs>(
+        &mut self,
+        key: K,
+        max: M,
+        min: M,
+    ) -> &mut Self {
+        self.pipe.cmd("ZREVRANGEBYSCORE").arg(key).arg(max).arg(min);
+        self
+    }
+
+    pub fn zrem<K: ToRedisArgs, M: ToRedisArgs>(&mut self, key: K, items: &[M]) -> &mut Self {
+        self.pipe.cmd("ZREM").arg(key).arg(items);
+        self
+    }
+
+    pub fn lpush<K: ToRedisArgs, V: ToRedisArgs>(&mut self, key: K, values: &[V]) -> &mut Self {
+        self.pipe.cmd("LPUSH").arg(key).arg(values);
+        self
+    }
+
+    pub fn rpush<K: ToRedisArgs, V: ToRedisArgs>(&mut self, key: K, values: &[V]) -> &mut Self {
+        self.pipe.cmd("RPUSH").arg(key).arg(values);
+        self
+    }
+
+    pub fn lpop<K: ToRedisArgs>(&mut self, key: K) -> &mut Self {
+        self.pipe.cmd("LPOP").arg(key);
+        self
+    }
+
+    pub fn rpop<K: ToRedisArgs>(&mut self, key: K) -> &mut Self {
+        self.pipe.cmd("RPOP").arg(key);
+        self
+    }
+
+    pub fn lrange<K: ToRedisArgs>(&mut self, key: K, start: isize, stop: isize) -> &mut Self {
+        self.pipe.cmd("LRANGE").arg(key).arg(start).arg(stop);
+        self
+    }
+
+    pub fn ltrim<K: ToRedisArgs>(&mut self, key: K, start: isize, stop: isize) -> &mut Self {
+        self.pipe.cmd("LTRIM").arg(key).arg(start).arg(stop);
+        self
+    }
+
+    pub fn lindex<K: ToRedisArgs>(&mut self, key: K, index: isize) -> &mut Self {
+        self.pipe.cmd("LINDEX").arg(key).arg(index);
+        self
+    }
+
+    pub fn linsert_before<K, P, V>(&mut self, key: K, pivot: P, value: V) -> &mut Self
+    where
+        K: ToRedisArgs,
+        P: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.pipe
+            .cmd("LINSERT")
+            .arg(key)
+            .arg("BEFORE")
+            .arg(pivot)
+            .arg(value);
+        self
+    }
+
+    pub fn linsert_after<K, P, V>(&mut self, key: K, pivot: P, value: V) -> &mut Self
+    where
+        K: ToRedisArgs,
+        P: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.pipe
+            .cmd("LINSERT")
+            .arg(key)
+            .arg("AFTER")
+            .arg(pivot)
+            .arg(value);
+        self
+    }
+
+    pub fn llen<K: ToRedisArgs>(&mut self, key: K) -> &mut Self {
+        self.pipe.cmd("LLEN").arg(key);
+        self
+    }
+
+    pub fn lmove<K: ToRedisArgs, D: ToRedisArgs>(
+        &mut self,
+        key: K,
+        destination: D,
+        from: Direction,
+        to: Direction,
+    ) -> &mut Self {
+        self.pipe
+            .cmd("LMOVE")
+            .arg(key)
+            .arg(destination)
+            .arg(from)
+            .arg(to);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests_pipeline {
+    use std::collections::HashMap;
+
+    use redis::{ErrorKind, SetExpiry};
+
+    use crate::{AsyncCache, Cache, IAsyncCache, ICache, MemoryConnection};
+
+    use super::*;
+
+    #[test]
+    fn test_pipeline() {
+        let mut ca = Cache::from_connection(MemoryConnection::new());
+        let (v1, count, members, fields): (String, i32, Vec<String>, HashMap<String, i32>) = ca
+            .pipeline()
+            .set("k1", "v1")
+            .ignore()
+            .get("k1")
+            .incr("count")
+            .sadd("s", &["a"])
+            .ignore()
+            .smembers("s")
+            .hmset("h", &[("f1", 1), ("f2", 2)])
+            .ignore()
+            .hgetall("h")
+            .query()
+            .unwrap();
+        assert_eq!(v1, "v1");
+        assert_eq!(count, 1);
+        assert_eq!(members, vec!["a"]);
+        assert_eq!(fields, HashMap::from([("f1".into(), 1), ("f2".into(), 2)]));
+
+        let mut pipe = ca.pipeline();
+        for i in 0..100 {
+            pipe.set(format!("key:{i}"), i).ignore();
+        }
+        for i in 0..100 {
+            pipe.get(format!("key:{i}"));
+        }
+        let values: Vec<i32> = pipe.query().unwrap();
+        assert_eq!(values, (0..100).collect::<Vec<_>>());
+
+        let err = ca
+            .pipeline()
+            .set("k1", "v1")
+            .incr("k1")
+            .query::<(String, i32)>()
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ResponseError);
+    }
+
+    #[test]
+    fn test_atomic() {
+        let mut ca = Cache::from_connection(MemoryConnection::new());
+        ca.sadd("from", &["a", "b"]).unwrap();
+        let (removed, added): (i32, i32) = ca
+            .pipeline()
+            .atomic()
+            .srem("from", &["a"])
+            .sadd("to", &["a"])
+            .query()
+            .unwrap();
+        assert_eq!((removed, added), (1, 1));
+        assert_eq!(ca.smembers::<_, String>("to").unwrap(), ["a"]);
+
+        // A failing command does not stop the others.
+        ca.set("text", "abc").unwrap();
+        let err = ca
+            .pipeline()
+            .atomic()
+            .incr("text")
+            .set("k1", "v1")
+            .query::<(i32, String)>()
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ResponseError);
+        assert_eq!(ca.get::<_, String>("k1").unwrap(), "v1");
+    }
+
+    #[test]
+    fn test_transaction_retry() {
+        let con = MemoryConnection::new();
+        let mut other = Cache::from_connection(con.clone());
+        let mut ca = Cache::from_connection(con);
+        ca.set("balance", 10).unwrap();
+
+        let mut attempts = 0;
+        let (balance,): (i32,) = ca
+            .transaction(&["balance"], |tx| {
+                attempts += 1;
+                let balance: i32 = tx.cache().get("balance")?;
+                if attempts == 1 {
+                    other.incr("balance").unwrap();
+                }
+                tx.set("balance", balance * 2).ignore().get("balance");
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(attempts, 2);
+        assert_eq!(balance, 22);
+
+        let err = ca
+            .transaction::<_, (), _>(&["balance"], |_| {
+                Err((ErrorKind::ClientError, "insufficient balance").into())
+            })
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ClientError);
+        // The failed attempt no longer watches the key.
+        other.incr("balance").unwrap();
+        let () = ca
+            .transaction(&["balance"], |tx| {
+                tx.incr("balance").ignore();
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(ca.get::<_, i32>("balance").unwrap(), 24);
+
+        attempts = 0;
+        let err = ca
+            .transaction::<_, (), _>(&["balance"], |tx| {
+                attempts += 1;
+                other.incr("balance").unwrap();
+                tx.incr("balance").ignore();
+                Ok(())
+            })
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ExecAbortError);
+        assert_eq!(attempts, TRANSACTION_ATTEMPTS);
+    }
+
+    #[tokio::test]
+    async fn test_async_transaction() {
+        let task = tokio::spawn(async {
+            let mut ca = AsyncCache::from_connection(MemoryConnection::new());
+            ca.sadd("from", &["a"]).await.unwrap();
+            ca.transaction::<_, (), _>(&["from"], async |tx| {
+                let members: Vec<String> = tx.cache().smembers("from").await?;
+                tx.srem("from", &members)
+                    .ignore()
+                    .sadd("to", &members)
+                    .ignore();
+                Ok(())
+            })
+            .await
+            .unwrap();
+            ca.smembers::<_, String>("to").await.unwrap()
+        });
+        assert_eq!(task.await.unwrap(), ["a"]);
+    }
+
+    #[tokio::test]
+    async fn test_async_pipeline() {
+        let mut ca = AsyncCache::from_connection(MemoryConnection::new());
+        let opts = SetOptions::default().with_expiration(SetExpiry::EX(60));
+        let (written, exists, missing): (bool, bool, Option<String>) = ca
+            .pipeline()
+            .set_with("k1", "v1", opts)
+            .exists("k1")
+            .del("k1")
+            .ignore()
+            .get("k1")
+            .query()
+            .await
+            .unwrap();
+        assert!(written);
+        assert!(exists);
+        assert_eq!(missing, None);
+        assert!(!ca.exists("k1").await.unwrap());
+
+        let (len, items): (usize, Vec<String>) = ca
+            .pipeline()
+            .rpush("l", &["a", "b", "c"])
+            .ltrim("l", 1, -1)
+            .ignore()
+            .llen("l")
+            .ignore()
+            .lmove("l", "l", Direction::Left, Direction::Right)
+            .ignore()
+            .rpush("l", &["d"])
+            .ignore()
+            .lrange("l", 0, -1)
+            .query()
+            .await
+            .unwrap();
+        assert_eq!(len, 3);
+        assert_eq!(items, ["c", "b", "d"]);
+    }
+}
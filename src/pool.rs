@@ -0,0 +1,777 @@
+use std::{
+    mem,
+    ops::{Deref, DerefMut},
+    sync::{Condvar, Mutex, MutexGuard, PoisonError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use redis::{
+    Connection, ConnectionLike, Direction, ErrorKind, FromRedisValue, RedisResult, ToRedisArgs,
+};
+
+use crate::{config::Connector, Cache, ICache, RedisConfig, SetOptions, SetReply};
+
+type Connect<C> = Box<dyn Fn() -> RedisResult<C> + Send + Sync>;
+
+/// Sizing and checkout behaviour of a `CachePool`.
+pub struct PoolConfig {
+    /// The fewest connections kept open, idle or checked out. They are
+    /// opened up front, and connections that are closed or fail a health
+    /// check are replaced right away while the pool is below this.
+    pub min_idle: usize,
+    pub max_size: usize,
+    /// How long `checkout` waits for a connection when all are in use.
+    pub checkout_timeout: Duration,
+    /// Whether to `PING` idle connections before handing them out.
+    pub test_on_borrow: bool,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            min_idle: 0,
+            max_size: 10,
+            checkout_timeout: Duration::from_secs(30),
+            test_on_borrow: true,
+        }
+    }
+}
+
+/// A thread safe pool of blocking connections.
+///
+/// `ICache` is implemented for `&CachePool`, so a shared pool can be used
+/// like a `Cache` from many threads; every call checks out a connection for
+/// its duration. Use `checkout` to run several calls on one connection.
+pub struct CachePool<C = Connection> {
+    connect: Connect<C>,
+    config: PoolConfig,
+    state: Mutex<State<C>>,
+    returned: Condvar,
+}
+
+struct State<C> {
+    idle: Vec<C>,
+    /// Idle plus checked out connections, and those being opened.
+    size: usize,
+}
+
+impl CachePool {
+    pub fn new(config: RedisConfig, pool: PoolConfig) -> RedisResult<Self> {
+        let connector = Connector::new(config)?;
+        CachePool::with_connect(pool, move || connector.connect())
+    }
+}
+
+impl<C: ConnectionLike> CachePool<C> {
+    /// Creates a pool that opens its connections with `connect`.
+    pub fn with_connect<F>(config: PoolConfig, connect: F) -> RedisResult<Self>
+    where
+        F: Fn() -> RedisResult<C> + Send + Sync + 'static,
+    {
+        let idle = (0..config.min_idle)
+            .map(|_| connect())
+            .collect::<RedisResult<Vec<_>>>()?;
+        Ok(CachePool {
+            connect: Box::new(connect),
+            state: Mutex::new(State {
+                size: idle.len(),
+                idle,
+            }),
+            config,
+            returned: Condvar::new(),
+        })
+    }
+
+    /// Borrows a connection until the returned guard is dropped.
+    ///
+    /// Waits up to `checkout_timeout` when `max_size` connections are in use.
+    pub fn checkout(&self) -> RedisResult<PooledCache<'_, C>> {
+        let deadline = Instant::now() + self.config.checkout_timeout;
+        let mut state = self.state();
+        loop {
+            if let Some(mut con) = state.idle.pop() {
+                if !self.config.test_on_borrow {
+                    return Ok(self.pooled(con));
+                }
+                // Ping without holding the lock, so one slow node does not
+                // hold up every other checkout and return.
+                drop(state);
+                let slot = Slot(self);
+                if con.check_connection() {
+                    mem::forget(slot);
+                    return Ok(self.pooled(con));
+                }
+                drop(slot);
+                self.refill();
+                state = self.state();
+                continue;
+            }
+            if state.size < self.config.max_size {
+                state.size += 1;
+                drop(state);
+                let slot = Slot(self);
+                let con = (self.connect)()?;
+                mem::forget(slot);
+                return Ok(self.pooled(con));
+            }
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                return Err((
+                    ErrorKind::IoError,
+                    "Timed out waiting for a pooled connection",
+                )
+                    .into());
+            }
+            state = self
+                .returned
+                .wait_timeout(state, timeout)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+
+    /// Connections currently open, whether idle or checked out.
+    pub fn size(&self) -> usize {
+        self.state().size
+    }
+
+    pub fn idle(&self) -> usize {
+        self.state().idle.len()
+    }
+
+    /// Opens idle connections until there are `min_idle`, stopping at the
+    /// first that fails.
+    fn refill(&self) {
+        loop {
+            let mut state = self.state();
+            if state.size >= self.config.min_idle {
+                return;
+            }
+            state.size += 1;
+            drop(state);
+            let slot = Slot(self);
+            let Ok(con) = (self.connect)() else {
+                return;
+            };
+            mem::forget(slot);
+            self.state().idle.push(con);
+            self.returned.notify_one();
+        }
+    }
+
+    fn pooled(&self, con: C) -> PooledCache<'_, C> {
+        PooledCache {
+            pool: self,
+            cache: Some(Cache::from_connection(con)),
+        }
+    }
+}
+
+impl<C> CachePool<C> {
+    fn state(&self) -> MutexGuard<'_, State<C>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Frees a slot taken for a connection that never made it into the pool.
+struct Slot<'a, C>(&'a CachePool<C>);
+
+impl<C> Drop for Slot<'_, C> {
+    fn drop(&mut self) {
+        self.0.state().size -= 1;
+        self.0.returned.notify_one();
+    }
+}
+
+/// A connection checked out of a `CachePool`, returned to it on drop.
+///
+/// If the thread is panicking the connection is closed instead, as it may
+/// be left inside a transaction or still watching keys.
+pub struct PooledCache<'a, C: ConnectionLike> {
+    pool: &'a CachePool<C>,
+    cache: Option<Cache<C>>,
+}
+
+impl<C: ConnectionLike> Deref for PooledCache<'_, C> {
+    type Target = Cache<C>;
+
+    fn deref(&self) -> &Cache<C> {
+        self.cache.as_ref().expect("connection already returned")
+    }
+}
+
+impl<C: ConnectionLike> DerefMut for PooledCache<'_, C> {
+    fn deref_mut(&mut self) -> &mut Cache<C> {
+        self.cache.as_mut().expect("connection already returned")
+    }
+}
+
+impl<C: ConnectionLike> Drop for PooledCache<'_, C> {
+    fn drop(&mut self) {
+        let Some(cache) = self.cache.take() else {
+            return;
+        };
+        let con = cache.into_connection();
+        let mut state = self.pool.state();
+        let open = con.is_open();
+        if open && !thread::panicking() {
+            state.idle.push(con);
+        } else {
+            state.size -= 1;
+        }
+        drop(state);
+        self.pool.returned.notify_one();
+        if !open && !thread::panicking() {
+            self.pool.refill();
+        }
+    }
+}
+
+impl<C: ConnectionLike> ICache for &CachePool<C> {
+    fn get<K, V>(&mut self, key: K) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.checkout()?.get(key)
+    }
+
+    fn set<K, V>(&mut self, key: K, value: V) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.checkout()?.set(key, value)
+    }
+
+    fn set_with<K, V, R>(
+        &mut self,
+        key: K,
+        value: V,
+        options: SetOptions,
+    ) -> RedisResult<SetReply<R>>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+        R: FromRedisValue,
+    {
+        self.checkout()?.set_with(key, value, options)
+    }
+
+    fn del<K>(&mut self, key: K) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+    {
+        self.checkout()?.del(key)
+    }
+
+    fn incr<K>(&mut self, key: K) -> RedisResult<i32>
+    where
+        K: ToRedisArgs,
+    {
+        self.checkout()?.incr(key)
+    }
+
+    fn exists<K>(&mut self, key: K) -> RedisResult<bool>
+    where
+        K: ToRedisArgs,
+    {
+        self.checkout()?.exists(key)
+    }
+
+    fn expire<K>(&mut self, key: K, sec: i32) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+    {
+        self.checkout()?.expire(key, sec)
+    }
+
+    fn sadd<K, V>(&mut self, key: K, values: &[V]) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.checkout()?.sadd(key, values)
+    }
+
+    fn smembers<K, V>(&mut self, key: K) -> RedisResult<Vec<V>>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.checkout()?.smembers(key)
+    }
+
+    fn srem<K, V>(&mut self, key: K, values: &[V]) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.checkout()?.srem(key, values)
+    }
+
+    fn scard<K>(&mut self, key: K) -> RedisResult<usize>
+    where
+        K: ToRedisArgs,
+    {
+        self.checkout()?.scard(key)
+    }
+
+    fn sismember<K, V>(&mut self, key: K, value: V) -> RedisResult<bool>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.checkout()?.sismember(key, value)
+    }
+
+    fn hset<K, F, V>(&mut self, key: K, field: F, value: V) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        F: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.checkout()?.hset(key, field, value)
+    }
+
+    fn hget<K, F, V>(&mut self, key: K, field: F) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        F: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.checkout()?.hget(key, field)
+    }
+
+    fn hmset<K, F, V>(&mut self, key: K, values: &[(F, V)]) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        F: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.checkout()?.hmset(key, values)
+    }
+
+    fn hmget<K, F, V>(&mut self, key: K, fields: &[F]) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        F: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.checkout()?.hmget(key, fields)
+    }
+
+    fn hsetall<K, V>(&mut self, key: K, value: V) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.checkout()?.hsetall(key, value)
+    }
+
+    fn hgetall<K, V>(&mut self, key: K) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.checkout()?.hgetall(key)
+    }
+
+    fn hexists<K, F>(&mut self, key: K, field: F) -> RedisResult<bool>
+    where
+        K: ToRedisArgs,
+        F: ToRedisArgs,
+    {
+        self.checkout()?.hexists(key, field)
+    }
+
+    fn hdel<K, F>(&mut self, key: K, fields: &[F]) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        F: ToRedisArgs,
+    {
+        self.checkout()?.hdel(key, fields)
+    }
+
+    fn zadd<K, S, M>(&mut self, key: K, items: &[(S, M)]) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        S: ToRedisArgs,
+        M: ToRedisArgs,
+    {
+        self.checkout()?.zadd(key, items)
+    }
+
+    fn zrange_by_score<K, M, V>(&mut self, key: K, min: M, max: M) -> RedisResult<Vec<V>>
+    where
+        K: ToRedisArgs,
+        M: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.checkout()?.zrange_by_score(key, min, max)
+    }
+
+    fn zrevrange_by_score<K, M, V>(&mut self, key: K, max: M, min: M) -> RedisResult<Vec<V>>
+    where
+        K: ToRedisArgs,
+        M: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.checkout()?.zrevrange_by_score(key, max, min)
+    }
+
+    fn zrem<K, M>(&mut self, key: K, items: &[M]) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        M: ToRedisArgs,
+    {
+        self.checkout()?.zrem(key, items)
+    }
+
+    fn lpush<K, V>(&mut self, key: K, values: &[V]) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.checkout()?.lpush(key, values)
+    }
+
+    fn rpush<K, V>(&mut self, key: K, values: &[V]) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.checkout()?.rpush(key, values)
+    }
+
+    fn lpop<K, V>(&mut self, key: K) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.checkout()?.lpop(key)
+    }
+
+    fn rpop<K, V>(&mut self, key: K) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.checkout()?.rpop(key)
+    }
+
+    fn lrange<K, V>(&mut self, key: K, start: isize, stop: isize) -> RedisResult<Vec<V>>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.checkout()?.lrange(key, start, stop)
+    }
+
+    fn ltrim<K>(&mut self, key: K, start: isize, stop: isize) -> RedisResult<()>
+    where
+        K: ToRedisArgs,
+    {
+        self.checkout()?.ltrim(key, start, stop)
+    }
+
+    fn lindex<K, V>(&mut self, key: K, index: isize) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.checkout()?.lindex(key, index)
+    }
+
+    fn linsert_before<K, P, V>(&mut self, key: K, pivot: P, value: V) -> RedisResult<isize>
+    where
+        K: ToRedisArgs,
+        P: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.checkout()?.linsert_before(key, pivot, value)
+    }
+
+    fn linsert_after<K, P, V>(&mut self, key: K, pivot: P, value: V) -> RedisResult<isize>
+    where
+        K: ToRedisArgs,
+        P: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.checkout()?.linsert_after(key, pivot, value)
+    }
+
+    fn llen<K>(&mut self, key: K) -> RedisResult<usize>
+    where
+        K: ToRedisArgs,
+    {
+        self.checkout()?.llen(key)
+    }
+
+    fn blpop<K, V>(&mut self, key: K, timeout: Duration) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.checkout()?.blpop(key, timeout)
+    }
+
+    fn brpop<K, V>(&mut self, key: K, timeout: Duration) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.checkout()?.brpop(key, timeout)
+    }
+
+    fn lmove<K, D, V>(
+        &mut self,
+        key: K,
+        destination: D,
+        from: Direction,
+        to: Direction,
+    ) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        D: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.checkout()?.lmove(key, destination, from, to)
+    }
+
+    fn blmove<K, D, V>(
+        &mut self,
+        key: K,
+        destination: D,
+        from: Direction,
+        to: Direction,
+        timeout: Duration,
+    ) -> RedisResult<V>
+    where
+        K: ToRedisArgs,
+        D: ToRedisArgs,
+        V: FromRedisValue,
+    {
+        self.checkout()?.blmove(key, destination, from, to, timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests_pool {
+    use std::{
+        panic::{self, AssertUnwindSafe},
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            mpsc, Arc,
+        },
+        thread,
+    };
+
+    use super::*;
+    use crate::MemoryConnection;
+
+    fn pool(config: PoolConfig) -> (CachePool<MemoryConnection>, Arc<AtomicUsize>) {
+        let con = MemoryConnection::new();
+        let opened = Arc::new(AtomicUsize::new(0));
+        let counter = opened.clone();
+        let pool = CachePool::with_connect(config, move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(con.clone())
+        })
+        .unwrap();
+        (pool, opened)
+    }
+
+    #[test]
+    fn test_shared_pool() {
+        let config = PoolConfig {
+            min_idle: 1,
+            max_size: 4,
+            ..PoolConfig::default()
+        };
+        let (pool, opened) = pool(config);
+        assert_eq!((pool.size(), pool.idle()), (1, 1));
+
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for _ in 0..100 {
+                        (&pool).incr("count").unwrap();
+                    }
+                });
+            }
+        });
+        let mut ca = &pool;
+        assert_eq!(ca.get::<_, i32>("count").unwrap(), 800);
+        assert!(opened.load(Ordering::SeqCst) <= 4);
+        assert_eq!(pool.idle(), pool.size());
+    }
+
+    /// Reports each health check on `pings` and waits for its result on
+    /// `replies`.
+    struct Checked {
+        con: MemoryConnection,
+        pings: mpsc::Sender<()>,
+        replies: Arc<Mutex<mpsc::Receiver<bool>>>,
+        open: Arc<AtomicBool>,
+    }
+
+    fn checked_pool(config: PoolConfig) -> (CachePool<Checked>, Checks) {
+        let (pings, pinged) = mpsc::channel();
+        let (reply, replies) = mpsc::channel();
+        let replies = Arc::new(Mutex::new(replies));
+        let open = Arc::new(AtomicBool::new(true));
+        let checks = Checks {
+            pinged,
+            reply,
+            open: open.clone(),
+        };
+        let pool = CachePool::with_connect(config, move || {
+            Ok(Checked {
+                con: MemoryConnection::new(),
+                pings: pings.clone(),
+                replies: replies.clone(),
+                open: open.clone(),
+            })
+        })
+        .unwrap();
+        (pool, checks)
+    }
+
+    struct Checks {
+        pinged: mpsc::Receiver<()>,
+        reply: mpsc::Sender<bool>,
+        open: Arc<AtomicBool>,
+    }
+
+    impl ConnectionLike for Checked {
+        fn req_packed_command(&mut self, cmd: &[u8]) -> RedisResult<redis::Value> {
+            self.con.req_packed_command(cmd)
+        }
+
+        fn req_packed_commands(
+            &mut self,
+            cmd: &[u8],
+            offset: usize,
+            count: usize,
+        ) -> RedisResult<Vec<redis::Value>> {
+            self.con.req_packed_commands(cmd, offset, count)
+        }
+
+        fn get_db(&self) -> i64 {
+            0
+        }
+
+        fn check_connection(&mut self) -> bool {
+            self.pings.send(()).unwrap();
+            self.replies.lock().unwrap().recv().unwrap()
+        }
+
+        fn is_open(&self) -> bool {
+            self.open.load(Ordering::SeqCst)
+        }
+    }
+
+    #[test]
+    fn test_check_unlocked() {
+        let config = PoolConfig {
+            min_idle: 1,
+            max_size: 1,
+            ..PoolConfig::default()
+        };
+        let (pool, checks) = checked_pool(config);
+
+        thread::scope(|s| {
+            let checkout = s.spawn(|| pool.checkout().map(|_| ()));
+            checks.pinged.recv().unwrap();
+            // The pool stays usable while the ping is in flight.
+            assert_eq!((pool.size(), pool.idle()), (1, 0));
+            checks.reply.send(false).unwrap();
+            // Its replacement is checked in turn.
+            checks.pinged.recv().unwrap();
+            checks.reply.send(true).unwrap();
+            checkout.join().unwrap().unwrap();
+        });
+        assert_eq!((pool.size(), pool.idle()), (1, 1));
+    }
+
+    #[test]
+    fn test_min_idle() {
+        let config = PoolConfig {
+            min_idle: 2,
+            test_on_borrow: false,
+            ..PoolConfig::default()
+        };
+        let (pool, checks) = checked_pool(config);
+        assert_eq!((pool.size(), pool.idle()), (2, 2));
+        let held = pool.checkout().unwrap();
+        checks.open.store(false, Ordering::SeqCst);
+        drop(held);
+        assert_eq!((pool.size(), pool.idle()), (2, 2));
+
+        // Closed connections are only replaced up to `min_idle`.
+        checks.open.store(true, Ordering::SeqCst);
+        let held: Vec<_> = (0..3).map(|_| pool.checkout().unwrap()).collect();
+        checks.open.store(false, Ordering::SeqCst);
+        drop(held);
+        assert_eq!((pool.size(), pool.idle()), (2, 2));
+    }
+
+    #[test]
+    fn test_checkout_timeout() {
+        let config = PoolConfig {
+            max_size: 1,
+            checkout_timeout: Duration::from_millis(20),
+            ..PoolConfig::default()
+        };
+        let (pool, _) = pool(config);
+        let mut held = pool.checkout().unwrap();
+        held.set("k1", "v1").unwrap();
+        let err = pool.checkout().err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::IoError);
+
+        thread::scope(|s| {
+            let waiter = s.spawn(|| pool.checkout().map(|mut ca| ca.get::<_, String>("k1")));
+            thread::sleep(Duration::from_millis(5));
+            drop(held);
+            assert_eq!(waiter.join().unwrap().unwrap().unwrap(), "v1");
+        });
+        assert_eq!(pool.size(), 1);
+    }
+
+    #[test]
+    fn test_connect_error() {
+        let config = PoolConfig {
+            max_size: 1,
+            ..PoolConfig::default()
+        };
+        let pool = CachePool::<MemoryConnection>::with_connect(config, || {
+            Err((ErrorKind::IoError, "Connection refused").into())
+        })
+        .unwrap();
+        assert!((&pool).exists("k1").is_err());
+        assert_eq!(pool.size(), 0);
+    }
+
+    #[test]
+    fn test_panics() {
+        let config = PoolConfig {
+            max_size: 1,
+            ..PoolConfig::default()
+        };
+        let failing =
+            CachePool::<MemoryConnection>::with_connect(config, || panic!("connect")).unwrap();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| failing.checkout()));
+        assert!(result.is_err());
+        assert_eq!(failing.size(), 0);
+
+        let (pool, _) = pool(PoolConfig::default());
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut ca = pool.checkout().unwrap();
+            let _: () = ca.transaction(&["k1"], |_| panic!("build")).unwrap();
+        }));
+        assert!(result.is_err());
+        assert_eq!((pool.size(), pool.idle()), (0, 0));
+    }
+}
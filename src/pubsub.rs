@@ -0,0 +1,526 @@
+use std::{
+    collections::{BTreeSet, VecDeque},
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_util::{FutureExt, Stream, StreamExt};
+use redis::{ErrorKind, FromRedisValue, Msg, RedisError, RedisResult};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{config::Connector, RedisConfig, RetryPolicy};
+
+/// A channel, or a glob pattern over channel names.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Topic {
+    Channel(String),
+    Pattern(String),
+}
+
+/// A message received by a `Subscriber`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message<T> {
+    pub channel: String,
+    /// The pattern that matched `channel`, for pattern subscriptions.
+    pub pattern: Option<String>,
+    pub payload: T,
+}
+
+struct Change {
+    subscribe: bool,
+    topics: Vec<Topic>,
+    done: oneshot::Sender<RedisResult<()>>,
+}
+
+type Messages = Pin<Box<dyn Stream<Item = Msg> + Send>>;
+
+type Connect =
+    dyn Fn(Vec<Topic>) -> Pin<Box<dyn Future<Output = RedisResult<Messages>> + Send>> + Send + Sync;
+
+/// A `Stream` of the messages published to a changing set of channels and
+/// patterns, with payloads decoded as `T`.
+///
+/// A background task holds the subscriptions on a dedicated connection.
+/// redis-rs cannot change the subscriptions of a connection while reading
+/// from it, so every change opens a new connection subscribed to the whole
+/// set. The old one is read until the new one is subscribed, then drained
+/// and dropped, so a message published meanwhile may arrive twice.
+///
+/// When the connection drops, the task reconnects following its
+/// `RetryPolicy` and subscribes again. Messages published while it is
+/// disconnected are lost, as with any Redis subscriber. If reconnecting
+/// fails, the stream yields the error and ends.
+pub struct Subscriber<T> {
+    changes: mpsc::UnboundedSender<Change>,
+    messages: mpsc::Receiver<RedisResult<Msg>>,
+    payload: PhantomData<fn() -> T>,
+}
+
+impl<T> Subscriber<T> {
+    /// Creates a subscriber without any subscriptions. Must be called from
+    /// within a Tokio runtime.
+    pub fn new(config: RedisConfig<'_>, policy: RetryPolicy) -> RedisResult<Self> {
+        let client = Connector::new(config)?.client;
+        Ok(Subscriber::with_connect(policy, move |topics| {
+            let client = client.clone();
+            async move {
+                let mut pubsub = client.get_tokio_connection().await?.into_pubsub();
+                for topic in &topics {
+                    match topic {
+                        Topic::Channel(channel) => pubsub.subscribe(channel).await?,
+                        Topic::Pattern(pattern) => pubsub.psubscribe(pattern).await?,
+                    }
+                }
+                Ok(pubsub.into_on_message())
+            }
+        }))
+    }
+
+    /// Creates a subscriber that calls `connect` for a stream of the
+    /// messages published to the given topics, whenever they change and
+    /// after the previous stream ends.
+    pub fn with_connect<F, Fut, S>(policy: RetryPolicy, connect: F) -> Self
+    where
+        F: Fn(Vec<Topic>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = RedisResult<S>> + Send + 'static,
+        S: Stream<Item = Msg> + Send + 'static,
+    {
+        let connect: Box<Connect> = Box::new(move |topics| {
+            let messages = connect(topics);
+            Box::pin(async move { Ok(Box::pin(messages.await?) as Messages) })
+        });
+        let (changes, changes_rx) = mpsc::unbounded_channel();
+        let (messages_tx, messages) = mpsc::channel(64);
+        tokio::spawn(run(connect, policy, changes_rx, messages_tx));
+        Subscriber {
+            changes,
+            messages,
+            payload: PhantomData,
+        }
+    }
+
+    pub async fn subscribe<C: AsRef<str>>(&self, channels: &[C]) -> RedisResult<()> {
+        self.change(true, channels, Topic::Channel).await
+    }
+
+    pub async fn unsubscribe<C: AsRef<str>>(&self, channels: &[C]) -> RedisResult<()> {
+        self.change(false, channels, Topic::Channel).await
+    }
+
+    pub async fn psubscribe<P: AsRef<str>>(&self, patterns: &[P]) -> RedisResult<()> {
+        self.change(true, patterns, Topic::Pattern).await
+    }
+
+    pub async fn punsubscribe<P: AsRef<str>>(&self, patterns: &[P]) -> RedisResult<()> {
+        self.change(false, patterns, Topic::Pattern).await
+    }
+
+    /// Resolves once the background task is subscribed to the new set.
+    async fn change<N: AsRef<str>>(
+        &self,
+        subscribe: bool,
+        names: &[N],
+        topic: fn(String) -> Topic,
+    ) -> RedisResult<()> {
+        let (done, result) = oneshot::channel();
+        let change = Change {
+            subscribe,
+            topics: names
+                .iter()
+                .map(|name| topic(name.as_ref().into()))
+                .collect(),
+            done,
+        };
+        self.changes.send(change).map_err(|_| closed())?;
+        result.await.map_err(|_| closed())?
+    }
+}
+
+impl<T: FromRedisValue> Stream for Subscriber<T> {
+    type Item = RedisResult<Message<T>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut()
+            .messages
+            .poll_recv(cx)
+            .map(|msg| msg.map(|msg| msg.and_then(|msg| decode(&msg))))
+    }
+}
+
+fn decode<T: FromRedisValue>(msg: &Msg) -> RedisResult<Message<T>> {
+    Ok(Message {
+        channel: msg.get_channel_name().into(),
+        pattern: match msg.from_pattern() {
+            true => Some(msg.get_pattern()?),
+            false => None,
+        },
+        payload: msg.get_payload()?,
+    })
+}
+
+fn closed() -> RedisError {
+    (ErrorKind::IoError, "Subscriber is closed").into()
+}
+
+/// How many received messages the task holds while the stream lags behind.
+const BUFFERED: usize = 64;
+
+/// A connection being opened for `topics`, on behalf of `done` if it was
+/// requested by a change rather than after the previous one dropped.
+struct Opening<'a> {
+    topics: BTreeSet<Topic>,
+    done: Option<oneshot::Sender<RedisResult<()>>>,
+    opened: Pin<Box<dyn Future<Output = RedisResult<Option<Messages>>> + Send + 'a>>,
+}
+
+impl<'a> Opening<'a> {
+    fn new(
+        connect: &'a Connect,
+        policy: &'a RetryPolicy,
+        topics: BTreeSet<Topic>,
+        done: Option<oneshot::Sender<RedisResult<()>>>,
+    ) -> Self {
+        Opening {
+            opened: Box::pin(open(connect, policy, topics.clone())),
+            topics,
+            done,
+        }
+    }
+}
+
+async fn run(
+    connect: Box<Connect>,
+    policy: RetryPolicy,
+    mut changes: mpsc::UnboundedReceiver<Change>,
+    messages: mpsc::Sender<RedisResult<Msg>>,
+) {
+    let mut topics = BTreeSet::new();
+    let mut stream: Option<Messages> = None;
+    let mut opening: Option<Opening> = None;
+    let mut pending = VecDeque::new();
+    let mut failed = false;
+    while !failed || !pending.is_empty() {
+        let is_opening = opening.is_some();
+        let read = stream.is_some() && pending.len() < BUFFERED && !failed;
+        let next = async {
+            match &mut stream {
+                Some(stream) => stream.next().await,
+                None => None,
+            }
+        };
+        let opened = async {
+            match &mut opening {
+                Some(opening) => opening.opened.as_mut().await,
+                None => Ok(None),
+            }
+        };
+        tokio::select! {
+            permit = messages.reserve(), if !pending.is_empty() => {
+                let (Ok(permit), Some(msg)) = (permit, pending.pop_front()) else {
+                    return;
+                };
+                permit.send(msg);
+            }
+            change = changes.recv(), if !is_opening && !failed => {
+                let Some(change) = change else {
+                    return;
+                };
+                let mut wanted = topics.clone();
+                for topic in change.topics {
+                    match change.subscribe {
+                        true => wanted.insert(topic),
+                        false => wanted.remove(&topic),
+                    };
+                }
+                if wanted == topics {
+                    let _ = change.done.send(Ok(()));
+                    continue;
+                }
+                opening = Some(Opening::new(&connect, &policy, wanted, Some(change.done)));
+            }
+            opened = opened, if is_opening => {
+                let Some(Opening { topics: wanted, done, .. }) = opening.take() else {
+                    continue;
+                };
+                match (opened, done) {
+                    (Ok(opened), done) => {
+                        // Whatever the old connection already received was
+                        // published before the new one subscribed.
+                        if let Some(old) = &mut stream {
+                            while let Some(Some(msg)) = old.next().now_or_never() {
+                                pending.push_back(Ok(msg));
+                            }
+                        }
+                        stream = opened;
+                        topics = wanted;
+                        if let Some(done) = done {
+                            let _ = done.send(Ok(()));
+                        }
+                    }
+                    (Err(err), Some(done)) => {
+                        let _ = done.send(Err(err));
+                        if stream.is_none() {
+                            opening = Some(Opening::new(&connect, &policy, topics.clone(), None));
+                        }
+                    }
+                    (Err(err), None) => {
+                        pending.push_back(Err(err));
+                        failed = true;
+                    }
+                }
+            }
+            msg = next, if read => match msg {
+                Some(msg) => pending.push_back(Ok(msg)),
+                None => {
+                    stream = None;
+                    if opening.is_none() {
+                        opening = Some(Opening::new(&connect, &policy, topics.clone(), None));
+                    }
+                }
+            },
+        }
+    }
+}
+
+/// Connects and subscribes to `topics`, or returns `None` if there are none.
+async fn open(
+    connect: &Connect,
+    policy: &RetryPolicy,
+    topics: BTreeSet<Topic>,
+) -> RedisResult<Option<Messages>> {
+    if topics.is_empty() {
+        return Ok(None);
+    }
+    let mut attempt = 0;
+    loop {
+        let err = match connect(topics.iter().cloned().collect()).await {
+            Ok(messages) => return Ok(Some(messages)),
+            Err(err) => err,
+        };
+        if !policy.should_retry(&err, attempt, true) {
+            return Err(err);
+        }
+        attempt += 1;
+        tokio::time::sleep(policy.delay(attempt)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests_pubsub {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
+
+    use redis::Value;
+    use tokio::sync::broadcast;
+
+    use super::*;
+    use crate::memory::glob_match;
+
+    /// Stands in for a server: `publish` delivers to every stream opened by
+    /// `connect`, and `drop_connections` ends them all. A `hold` delays the
+    /// next connection until it is released.
+    #[derive(Clone)]
+    struct Server {
+        sender: broadcast::Sender<Option<(String, String)>>,
+        connects: Arc<AtomicUsize>,
+        failures: Arc<AtomicUsize>,
+        hold: Arc<Mutex<Option<oneshot::Receiver<()>>>>,
+    }
+
+    impl Server {
+        fn new() -> Self {
+            Server {
+                sender: broadcast::channel(256).0,
+                connects: Arc::new(AtomicUsize::new(0)),
+                failures: Arc::new(AtomicUsize::new(0)),
+                hold: Arc::default(),
+            }
+        }
+
+        fn publish(&self, channel: &str, payload: &str) {
+            let _ = self.sender.send(Some((channel.into(), payload.into())));
+        }
+
+        fn drop_connections(&self) {
+            let _ = self.sender.send(None);
+        }
+
+        fn connect(&self, topics: Vec<Topic>) -> RedisResult<impl Stream<Item = Msg>> {
+            if self.failures.load(Ordering::SeqCst) > 0 {
+                self.failures.fetch_sub(1, Ordering::SeqCst);
+                return Err((ErrorKind::IoError, "connection refused").into());
+            }
+            self.connects.fetch_add(1, Ordering::SeqCst);
+            let mut receiver = self.sender.subscribe();
+            Ok(async_stream(move |tx| async move {
+                while let Ok(Some((channel, payload))) = receiver.recv().await {
+                    for topic in &topics {
+                        let msg = match topic {
+                            Topic::Channel(name) if *name == channel => vec!["message", name],
+                            Topic::Pattern(pattern)
+                                if glob_match(pattern.as_bytes(), channel.as_bytes()) =>
+                            {
+                                vec!["pmessage", pattern, &channel]
+                            }
+                            _ => continue,
+                        };
+                        let mut values: Vec<Value> = msg
+                            .into_iter()
+                            .map(|part| Value::Data(part.as_bytes().to_vec()))
+                            .collect();
+                        values.push(Value::Data(payload.as_bytes().to_vec()));
+                        let _ = tx.send(Msg::from_value(&Value::Bulk(values)).unwrap());
+                    }
+                }
+            }))
+        }
+    }
+
+    /// A stream of what `producer` sends, ending when it returns.
+    fn async_stream<F, Fut>(producer: F) -> impl Stream<Item = Msg>
+    where
+        F: FnOnce(mpsc::UnboundedSender<Msg>) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        tokio::spawn(producer(tx));
+        futures_util::stream::poll_fn(move |cx| rx.poll_recv(cx))
+    }
+
+    fn subscriber(server: &Server, policy: RetryPolicy) -> Subscriber<String> {
+        let server = server.clone();
+        Subscriber::with_connect(policy, move |topics| {
+            let server = server.clone();
+            let hold = server.hold.lock().unwrap().take();
+            async move {
+                if let Some(hold) = hold {
+                    let _ = hold.await;
+                }
+                server.connect(topics)
+            }
+        })
+    }
+
+    fn message(channel: &str, pattern: Option<&str>, payload: &str) -> Message<String> {
+        Message {
+            channel: channel.into(),
+            pattern: pattern.map(Into::into),
+            payload: payload.into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_subscribe() {
+        let server = Server::new();
+        let mut sub = subscriber(&server, RetryPolicy::default());
+        sub.subscribe(&["news"]).await.unwrap();
+        sub.psubscribe(&["user:*"]).await.unwrap();
+        server.publish("other", "x");
+        server.publish("news", "hello");
+        server.publish("user:1", "login");
+        assert_eq!(
+            sub.next().await.unwrap().unwrap(),
+            message("news", None, "hello")
+        );
+        assert_eq!(
+            sub.next().await.unwrap().unwrap(),
+            message("user:1", Some("user:*"), "login")
+        );
+
+        sub.unsubscribe(&["news"]).await.unwrap();
+        server.publish("news", "ignored");
+        server.publish("user:2", "logout");
+        assert_eq!(
+            sub.next().await.unwrap().unwrap(),
+            message("user:2", Some("user:*"), "logout")
+        );
+        // Unchanged subscriptions keep the connection.
+        sub.psubscribe(&["user:*"]).await.unwrap();
+        assert_eq!(server.connects.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_resubscribe() {
+        let server = Server::new();
+        let policy = RetryPolicy {
+            base_delay: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        };
+        let mut sub = subscriber(&server, policy);
+        sub.subscribe(&["news"]).await.unwrap();
+
+        server.failures.store(1, Ordering::SeqCst);
+        server.drop_connections();
+        while server.connects.load(Ordering::SeqCst) < 2 {
+            tokio::task::yield_now().await;
+        }
+        server.publish("news", "back");
+        assert_eq!(
+            sub.next().await.unwrap().unwrap(),
+            message("news", None, "back")
+        );
+
+        server.failures.store(3, Ordering::SeqCst);
+        server.drop_connections();
+        let err = sub.next().await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::IoError);
+        assert!(sub.next().await.is_none());
+        assert!(sub.subscribe(&["other"]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_slow_reader() {
+        let server = Server::new();
+        let mut sub = subscriber(&server, RetryPolicy::default());
+        sub.subscribe(&["news"]).await.unwrap();
+        for i in 0..100 {
+            server.publish("news", &i.to_string());
+        }
+        // Changes go through while nobody reads the messages.
+        tokio::time::timeout(Duration::from_secs(1), sub.subscribe(&["other"]))
+            .await
+            .unwrap()
+            .unwrap();
+        for i in 0..100 {
+            assert_eq!(
+                sub.next().await.unwrap().unwrap(),
+                message("news", None, &i.to_string())
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_publish_during_change() {
+        let server = Server::new();
+        let mut sub = subscriber(&server, RetryPolicy::default());
+        sub.subscribe(&["news"]).await.unwrap();
+
+        let (release, hold) = oneshot::channel();
+        *server.hold.lock().unwrap() = Some(hold);
+        let publish = async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            // Only the old connection is subscribed at this point.
+            server.publish("news", "during");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            let _ = release.send(());
+        };
+        let (subscribed, ()) = tokio::join!(sub.subscribe(&["other"]), publish);
+        subscribed.unwrap();
+        server.publish("news", "after");
+        assert_eq!(
+            sub.next().await.unwrap().unwrap(),
+            message("news", None, "during")
+        );
+        assert_eq!(
+            sub.next().await.unwrap().unwrap(),
+            message("news", None, "after")
+        );
+    }
+}
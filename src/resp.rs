@@ -0,0 +1,118 @@
+use redis::{ErrorKind, RedisError, RedisResult};
+
+/// The keys a command operates on, for the commands this crate sends, given
+/// the upper-cased command name.
+#[cfg(any(feature = "cluster", feature = "tracing"))]
+pub(crate) fn command_keys<'a>(name: &[u8], args: &'a [Vec<u8>]) -> &'a [Vec<u8>] {
+    match name {
+        b"DEL" | b"EXISTS" | b"UNLINK" | b"TOUCH" | b"WATCH" | b"MGET" => &args[1..],
+        b"PING" | b"MULTI" | b"EXEC" | b"DISCARD" | b"UNWATCH" | b"FLUSHDB" | b"FLUSHALL"
+        | b"CLIENT" | b"SCAN" | b"PUBLISH" => &[],
+        b"LMOVE" | b"BLMOVE" => args.get(1..3).unwrap_or_default(),
+        b"BLPOP" | b"BRPOP" => args
+            .get(1..args.len().saturating_sub(1))
+            .unwrap_or_default(),
+        b"XGROUP" => args.get(2..3).unwrap_or_default(),
+        b"XREAD" | b"XREADGROUP" => {
+            match args
+                .iter()
+                .position(|arg| arg.eq_ignore_ascii_case(b"STREAMS"))
+            {
+                Some(at) => {
+                    let streams = &args[at + 1..];
+                    &streams[..streams.len() / 2]
+                }
+                None => &[],
+            }
+        }
+        _ => args.get(1..2).unwrap_or_default(),
+    }
+}
+
+pub(crate) fn parse_commands(mut input: &[u8]) -> RedisResult<Vec<Vec<Vec<u8>>>> {
+    let mut commands = Vec::new();
+    while !input.is_empty() {
+        let count = parse_header(&mut input, b'*')?;
+        let mut args = Vec::with_capacity(count);
+        for _ in 0..count {
+            let len = parse_header(&mut input, b'$')?;
+            if input.len() < len + 2 {
+                return Err(invalid_command());
+            }
+            args.push(input[..len].to_vec());
+            input = &input[len + 2..];
+        }
+        commands.push(args);
+    }
+    Ok(commands)
+}
+
+/// The upper-cased name of each command in `input`, without copying the
+/// other arguments.
+#[cfg(feature = "metrics")]
+pub(crate) fn parse_command_names(mut input: &[u8]) -> RedisResult<Vec<Vec<u8>>> {
+    let mut names = Vec::new();
+    while !input.is_empty() {
+        let count = parse_header(&mut input, b'*')?;
+        for i in 0..count {
+            let len = parse_header(&mut input, b'$')?;
+            if input.len() < len + 2 {
+                return Err(invalid_command());
+            }
+            if i == 0 {
+                names.push(input[..len].to_ascii_uppercase());
+            }
+            input = &input[len + 2..];
+        }
+    }
+    Ok(names)
+}
+
+fn parse_header(input: &mut &[u8], prefix: u8) -> RedisResult<usize> {
+    let end = input
+        .windows(2)
+        .position(|w| w == b"\r\n")
+        .ok_or_else(invalid_command)?;
+    let (line, rest) = (&input[..end], &input[end + 2..]);
+    *input = rest;
+    match line.split_first() {
+        Some((p, n)) if *p == prefix => std::str::from_utf8(n)
+            .ok()
+            .and_then(|n| n.parse().ok())
+            .ok_or_else(invalid_command),
+        _ => Err(invalid_command()),
+    }
+}
+
+fn invalid_command() -> RedisError {
+    (ErrorKind::ClientError, "Invalid packed command").into()
+}
+
+#[cfg(test)]
+mod tests_resp {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        let pipe = redis::pipe()
+            .get("a")
+            .cmd("del")
+            .arg("b")
+            .arg("c")
+            .get_packed_pipeline();
+        let commands = parse_commands(&pipe).unwrap();
+        assert_eq!(
+            commands,
+            [
+                vec![b"GET".to_vec(), b"a".to_vec()],
+                vec![b"del".to_vec(), b"b".to_vec(), b"c".to_vec()]
+            ]
+        );
+        #[cfg(feature = "metrics")]
+        assert_eq!(
+            parse_command_names(&pipe).unwrap(),
+            [b"GET".to_vec(), b"DEL".to_vec()]
+        );
+        assert!(parse_commands(b"*1\r\n$3\r\nGE").is_err());
+    }
+}
@@ -0,0 +1,494 @@
+use std::{
+    collections::hash_map::RandomState,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
+
+use redis::{
+    aio::{self, MultiplexedConnection},
+    Cmd, Connection, ConnectionLike, ErrorKind, Pipeline, RedisError, RedisFuture, RedisResult,
+    Value,
+};
+
+use crate::{config::Connector, resp::parse_commands, RedisConfig};
+
+/// When and how often a `Reconnecting` connection retries a failed command.
+pub struct RetryPolicy {
+    /// Attempts per command, including the first one.
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for every retry after it.
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Whether to pick a random delay up to the computed one.
+    pub jitter: bool,
+    /// Errors worth retrying; anything else is returned right away.
+    pub retryable: Vec<ErrorKind>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(2),
+            jitter: true,
+            retryable: vec![
+                ErrorKind::IoError,
+                ErrorKind::BusyLoadingError,
+                ErrorKind::TryAgain,
+                ErrorKind::ClusterDown,
+                ErrorKind::MasterDown,
+                ErrorKind::ReadOnly,
+            ],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The delay before retry number `retry`, counting from one.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        let delay = self.base_delay.saturating_mul(factor).min(self.max_delay);
+        if !self.jitter {
+            return delay;
+        }
+        let random = RandomState::new().build_hasher().finish();
+        delay.mul_f64((random >> 11) as f64 / (1u64 << 53) as f64)
+    }
+
+    /// Commands that were not sent because connecting failed can always be
+    /// retried.
+    pub(crate) fn should_retry(&self, err: &RedisError, attempt: u32, safe: bool) -> bool {
+        safe && attempt + 1 < self.max_attempts && self.retryable.contains(&err.kind())
+    }
+}
+
+/// A connection that reopens itself after it was dropped and retries
+/// failed commands, for use with `Cache::from_connection`.
+///
+/// Commands are only sent again if doing so cannot apply them twice with a
+/// different outcome: reads, plain `SET`s, deletes and other writes that
+/// leave the same state when repeated. `INCR`, conditional `SET`s and
+/// transactions fail on the first error. A broken connection is replaced on
+/// the next command either way.
+pub struct Reconnecting<C = Connection> {
+    connect: Box<dyn Fn() -> RedisResult<C> + Send + Sync>,
+    con: Option<C>,
+    policy: RetryPolicy,
+}
+
+impl Reconnecting {
+    pub fn new(config: RedisConfig, policy: RetryPolicy) -> RedisResult<Self> {
+        let connector = Connector::new(config)?;
+        Reconnecting::with_connect(policy, move || connector.connect())
+    }
+}
+
+impl<C: ConnectionLike> Reconnecting<C> {
+    /// Opens the first connection with `connect`, and later ones as needed.
+    pub fn with_connect<F>(policy: RetryPolicy, connect: F) -> RedisResult<Self>
+    where
+        F: Fn() -> RedisResult<C> + Send + Sync + 'static,
+    {
+        let con = connect()?;
+        Ok(Reconnecting {
+            connect: Box::new(connect),
+            con: Some(con),
+            policy,
+        })
+    }
+
+    fn retry<T>(
+        &mut self,
+        idempotent: bool,
+        mut f: impl FnMut(&mut C) -> RedisResult<T>,
+    ) -> RedisResult<T> {
+        let mut attempt = 0;
+        loop {
+            let con = match &mut self.con {
+                Some(con) => Ok(con),
+                None => (self.connect)().map(|con| self.con.insert(con)),
+            };
+            let (err, sent) = match con.map(&mut f) {
+                Ok(Ok(reply)) => return Ok(reply),
+                Ok(Err(err)) => (err, true),
+                Err(err) => (err, false),
+            };
+            if is_broken(&err) || !self.con.as_ref().is_some_and(C::is_open) {
+                self.con = None;
+            }
+            if !self.policy.should_retry(&err, attempt, idempotent || !sent) {
+                return Err(err);
+            }
+            attempt += 1;
+            std::thread::sleep(self.policy.delay(attempt));
+        }
+    }
+}
+
+impl<C: ConnectionLike> ConnectionLike for Reconnecting<C> {
+    fn req_packed_command(&mut self, cmd: &[u8]) -> RedisResult<Value> {
+        self.retry(is_idempotent(cmd), |con| con.req_packed_command(cmd))
+    }
+
+    fn req_packed_commands(
+        &mut self,
+        cmd: &[u8],
+        offset: usize,
+        count: usize,
+    ) -> RedisResult<Vec<Value>> {
+        self.retry(is_idempotent(cmd), |con| {
+            con.req_packed_commands(cmd, offset, count)
+        })
+    }
+
+    fn get_db(&self) -> i64 {
+        self.con.as_ref().map_or(0, C::get_db)
+    }
+
+    fn check_connection(&mut self) -> bool {
+        self.retry(true, |con| Ok(con.check_connection()))
+            .unwrap_or(false)
+    }
+
+    fn is_open(&self) -> bool {
+        true
+    }
+}
+
+type AsyncConnect<C> =
+    dyn Fn() -> Pin<Box<dyn Future<Output = RedisResult<C>> + Send>> + Send + Sync;
+
+/// The async counterpart of `Reconnecting`, for `AsyncCache::from_connection`.
+///
+/// Clones share the way to connect but reconnect independently.
+pub struct AsyncReconnecting<C = MultiplexedConnection> {
+    connect: Arc<AsyncConnect<C>>,
+    con: Option<C>,
+    policy: Arc<RetryPolicy>,
+}
+
+impl AsyncReconnecting {
+    pub async fn new(config: RedisConfig<'_>, policy: RetryPolicy) -> RedisResult<Self> {
+        let connector = Connector::new(config)?;
+        AsyncReconnecting::with_connect(policy, move || {
+            let connector = connector.clone();
+            async move { connector.connect_async().await }
+        })
+        .await
+    }
+}
+
+impl<C: aio::ConnectionLike + Send> AsyncReconnecting<C> {
+    pub async fn with_connect<F, Fut>(policy: RetryPolicy, connect: F) -> RedisResult<Self>
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = RedisResult<C>> + Send + 'static,
+    {
+        let connect: Arc<AsyncConnect<C>> = Arc::new(move || Box::pin(connect()));
+        let con = connect().await?;
+        Ok(AsyncReconnecting {
+            connect,
+            con: Some(con),
+            policy: Arc::new(policy),
+        })
+    }
+
+    async fn send(&mut self, request: Request<'_>) -> RedisResult<Vec<Value>> {
+        let idempotent = match request {
+            Request::Command(cmd) => is_idempotent(&cmd.get_packed_command()),
+            Request::Pipeline(pipe, ..) => is_idempotent(&pipe.get_packed_pipeline()),
+        };
+        let mut attempt = 0;
+        loop {
+            let con = match &mut self.con {
+                Some(con) => Ok(con),
+                None => (self.connect)().await.map(|con| self.con.insert(con)),
+            };
+            let reply = match (con, request) {
+                (Err(err), _) => Err((err, false)),
+                (Ok(con), Request::Command(cmd)) => con
+                    .req_packed_command(cmd)
+                    .await
+                    .map(|value| vec![value])
+                    .map_err(|err| (err, true)),
+                (Ok(con), Request::Pipeline(pipe, offset, count)) => con
+                    .req_packed_commands(pipe, offset, count)
+                    .await
+                    .map_err(|err| (err, true)),
+            };
+            let (err, sent) = match reply {
+                Ok(reply) => return Ok(reply),
+                Err(err) => err,
+            };
+            if is_broken(&err) {
+                self.con = None;
+            }
+            if !self.policy.should_retry(&err, attempt, idempotent || !sent) {
+                return Err(err);
+            }
+            attempt += 1;
+            tokio::time::sleep(self.policy.delay(attempt)).await;
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Request<'a> {
+    Command(&'a Cmd),
+    Pipeline(&'a Pipeline, usize, usize),
+}
+
+impl<C: Clone> Clone for AsyncReconnecting<C> {
+    fn clone(&self) -> Self {
+        AsyncReconnecting {
+            connect: self.connect.clone(),
+            con: self.con.clone(),
+            policy: self.policy.clone(),
+        }
+    }
+}
+
+impl<C: aio::ConnectionLike + Send> aio::ConnectionLike for AsyncReconnecting<C> {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        Box::pin(async move {
+            let mut replies = self.send(Request::Command(cmd)).await?;
+            Ok(replies.pop().unwrap_or(Value::Nil))
+        })
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        Box::pin(self.send(Request::Pipeline(cmd, offset, count)))
+    }
+
+    fn get_db(&self) -> i64 {
+        self.con.as_ref().map_or(0, C::get_db)
+    }
+}
+
+/// Whether the connection that returned `err` has to be replaced; a
+/// `READONLY` error means the node was demoted to a replica.
+fn is_broken(err: &RedisError) -> bool {
+    matches!(err.kind(), ErrorKind::IoError | ErrorKind::ReadOnly) || err.is_connection_dropped()
+}
+
+/// Whether every command in `packed` can safely be sent again.
+fn is_idempotent(packed: &[u8]) -> bool {
+    let Ok(commands) = parse_commands(packed) else {
+        return false;
+    };
+    commands.iter().all(|args| {
+        let Some((name, args)) = args.split_first() else {
+            return false;
+        };
+        match name.to_ascii_uppercase().as_slice() {
+            b"SET" => !args.iter().skip(2).any(|option| {
+                matches!(
+                    option.to_ascii_uppercase().as_slice(),
+                    b"NX" | b"XX" | b"GET"
+                )
+            }),
+            b"PING" | b"GET" | b"DEL" | b"EXISTS" | b"EXPIRE" | b"PEXPIRE" | b"PERSIST"
+            | b"TTL" | b"PTTL" | b"SADD" | b"SMEMBERS" | b"SREM" | b"SCARD" | b"SISMEMBER"
+            | b"HSET" | b"HMSET" | b"HGET" | b"HMGET" | b"HGETALL" | b"HEXISTS" | b"HDEL"
+            | b"HLEN" | b"ZADD" | b"ZRANGEBYSCORE" | b"ZREVRANGEBYSCORE" | b"ZREM" | b"ZCARD"
+            | b"ZSCORE" | b"LRANGE" | b"LINDEX" | b"LLEN" | b"SCAN" | b"XRANGE" | b"XLEN"
+            | b"XPENDING" => true,
+            _ => false,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests_retry {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    };
+
+    use super::*;
+    use crate::{AsyncCache, Cache, IAsyncCache, ICache, MemoryConnection};
+
+    /// A connection that breaks for good after `healthy` commands.
+    struct Flaky {
+        con: MemoryConnection,
+        healthy: usize,
+    }
+
+    impl Flaky {
+        fn call(&mut self) -> RedisResult<()> {
+            if self.healthy == 0 {
+                return Err((ErrorKind::IoError, "Connection reset").into());
+            }
+            self.healthy -= 1;
+            Ok(())
+        }
+    }
+
+    impl ConnectionLike for Flaky {
+        fn req_packed_command(&mut self, cmd: &[u8]) -> RedisResult<Value> {
+            self.call()?;
+            self.con.req_packed_command(cmd)
+        }
+
+        fn req_packed_commands(
+            &mut self,
+            cmd: &[u8],
+            offset: usize,
+            count: usize,
+        ) -> RedisResult<Vec<Value>> {
+            self.call()?;
+            self.con.req_packed_commands(cmd, offset, count)
+        }
+
+        fn get_db(&self) -> i64 {
+            0
+        }
+
+        fn check_connection(&mut self) -> bool {
+            self.healthy > 0
+        }
+
+        fn is_open(&self) -> bool {
+            self.healthy > 0
+        }
+    }
+
+    impl aio::ConnectionLike for Flaky {
+        fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+            let reply = ConnectionLike::req_packed_command(self, &cmd.get_packed_command());
+            Box::pin(std::future::ready(reply))
+        }
+
+        fn req_packed_commands<'a>(
+            &'a mut self,
+            cmd: &'a Pipeline,
+            offset: usize,
+            count: usize,
+        ) -> RedisFuture<'a, Vec<Value>> {
+            let packed = cmd.get_packed_pipeline();
+            let replies = ConnectionLike::req_packed_commands(self, &packed, offset, count);
+            Box::pin(std::future::ready(replies))
+        }
+
+        fn get_db(&self) -> i64 {
+            0
+        }
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            base_delay: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        }
+    }
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy {
+            jitter: false,
+            ..RetryPolicy::default()
+        };
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(3), Duration::from_millis(400));
+        assert_eq!(policy.delay(40), Duration::from_secs(2));
+        let policy = RetryPolicy::default();
+        assert!((1..10).all(|retry| policy.delay(retry) <= Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn test_idempotent() {
+        let packed = |cmd: &mut Cmd| cmd.get_packed_command();
+        assert!(is_idempotent(&packed(redis::cmd("GET").arg("k1"))));
+        assert!(is_idempotent(&packed(
+            redis::cmd("SET").arg("k1").arg("NX").arg("EX").arg(10)
+        )));
+        assert!(!is_idempotent(&packed(
+            redis::cmd("SET").arg("k1").arg("v1").arg("NX")
+        )));
+        assert!(!is_idempotent(&packed(redis::cmd("INCR").arg("k1"))));
+        let pipe = redis::pipe().get("k1").set("k2", 1).get_packed_pipeline();
+        assert!(is_idempotent(&pipe));
+        let pipe = redis::pipe().atomic().get("k1").get_packed_pipeline();
+        assert!(!is_idempotent(&pipe));
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let mem = MemoryConnection::new();
+        let connects = Arc::new(AtomicUsize::new(0));
+        let counter = connects.clone();
+        // The first connection breaks after two commands.
+        let budgets = Mutex::new(vec![usize::MAX, 2]);
+        let con = Reconnecting::with_connect(policy(), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let healthy = budgets.lock().unwrap().pop().unwrap_or(usize::MAX);
+            Ok(Flaky {
+                con: mem.clone(),
+                healthy,
+            })
+        })
+        .unwrap();
+        let mut ca = Cache::from_connection(con);
+        ca.set("k1", "v1").unwrap();
+        assert_eq!(ca.incr("count").unwrap(), 1);
+        assert_eq!(ca.get::<_, String>("k1").unwrap(), "v1");
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_no_retry() {
+        let mem = MemoryConnection::new();
+        let budgets = Mutex::new(vec![usize::MAX, 0, 1]);
+        let con = Reconnecting::with_connect(policy(), move || {
+            let healthy = budgets.lock().unwrap().pop().unwrap_or(usize::MAX);
+            Ok(Flaky {
+                con: mem.clone(),
+                healthy,
+            })
+        })
+        .unwrap();
+        let mut ca = Cache::from_connection(con);
+        ca.set("k1", "v1").unwrap();
+        // The failed INCR is not sent again on the new connection...
+        let err = ca.incr("count").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::IoError);
+        // ...which breaks as well, but GET may be retried on a third one.
+        assert_eq!(ca.get::<_, String>("k1").unwrap(), "v1");
+
+        ca.set("text", "abc").unwrap();
+        let err = ca.incr("text").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ResponseError);
+    }
+
+    #[tokio::test]
+    async fn test_async_reconnect() {
+        let mem = MemoryConnection::new();
+        let budgets = Arc::new(Mutex::new(vec![usize::MAX, 1]));
+        let con = AsyncReconnecting::with_connect(policy(), move || {
+            let healthy = budgets.lock().unwrap().pop().unwrap_or(usize::MAX);
+            let con = mem.clone();
+            async move { Ok(Flaky { con, healthy }) }
+        })
+        .await
+        .unwrap();
+        let mut ca = AsyncCache::from_connection(con);
+        ca.set("k1", "v1").await.unwrap();
+        assert_eq!(ca.get::<_, String>("k1").await.unwrap(), "v1");
+        let err = AsyncReconnecting::<Flaky>::with_connect(policy(), || async {
+            Err((ErrorKind::IoError, "Connection refused").into())
+        })
+        .await
+        .err()
+        .unwrap();
+        assert_eq!(err.kind(), ErrorKind::IoError);
+    }
+}
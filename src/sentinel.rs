@@ -0,0 +1,139 @@
+use std::sync::{Arc, Mutex, PoisonError};
+
+use redis::{
+    sentinel::{Sentinel, SentinelNodeConnectionInfo},
+    Client, RedisConnectionInfo, RedisResult,
+};
+
+use crate::{AsyncReconnecting, Reconnecting, RetryPolicy};
+
+/// Where to find a primary, or its replicas, through Redis Sentinel.
+///
+/// Connections are made with `Reconnecting::sentinel` or
+/// `AsyncReconnecting::sentinel`, which ask the sentinels for the current
+/// primary whenever they (re)connect, so a failover is picked up as soon as
+/// the old connection breaks.
+pub struct SentinelConfig<'a> {
+    /// Sentinel addresses as `host:port`.
+    pub sentinels: Vec<&'a str>,
+    pub master_name: &'a str,
+    /// Credentials and database of the data nodes, not the sentinels.
+    pub username: &'a str,
+    pub password: &'a str,
+    pub db: u8,
+    /// Connect to a replica instead of the primary, moving on to another one
+    /// on every reconnect. Writes through such a connection fail.
+    pub replica_reads: bool,
+}
+
+impl<'a> SentinelConfig<'a> {
+    pub fn new(sentinels: Vec<&'a str>, master_name: &'a str, db: u8) -> Self {
+        SentinelConfig {
+            sentinels,
+            master_name,
+            username: "",
+            password: "",
+            db,
+            replica_reads: false,
+        }
+    }
+    pub fn set_username(&mut self, username: &'a str) {
+        self.username = username;
+    }
+    pub fn set_password(&mut self, password: &'a str) {
+        self.password = password;
+    }
+    pub fn set_replica_reads(&mut self, replica_reads: bool) {
+        self.replica_reads = replica_reads;
+    }
+
+    fn into_target(self) -> RedisResult<Target> {
+        let urls = self.sentinels.iter().map(|s| format!("redis://{s}"));
+        let non_empty = |s: &str| (!s.is_empty()).then(|| s.to_string());
+        Ok(Target {
+            sentinel: Sentinel::build(urls.collect())?,
+            master_name: self.master_name.to_string(),
+            node: SentinelNodeConnectionInfo {
+                tls_mode: None,
+                redis_connection_info: Some(RedisConnectionInfo {
+                    db: self.db.into(),
+                    username: non_empty(self.username),
+                    password: non_empty(self.password),
+                }),
+            },
+            replica_reads: self.replica_reads,
+        })
+    }
+}
+
+struct Target {
+    sentinel: Sentinel,
+    master_name: String,
+    node: SentinelNodeConnectionInfo,
+    replica_reads: bool,
+}
+
+impl Target {
+    fn client(&mut self) -> RedisResult<Client> {
+        let (name, node) = (&self.master_name, Some(&self.node));
+        if self.replica_reads {
+            self.sentinel.replica_rotate_for(name, node)
+        } else {
+            self.sentinel.master_for(name, node)
+        }
+    }
+
+    async fn async_client(&mut self) -> RedisResult<Client> {
+        let (name, node) = (&self.master_name, Some(&self.node));
+        if self.replica_reads {
+            self.sentinel.async_replica_rotate_for(name, node).await
+        } else {
+            self.sentinel.async_master_for(name, node).await
+        }
+    }
+}
+
+impl Reconnecting {
+    pub fn sentinel(config: SentinelConfig, policy: RetryPolicy) -> RedisResult<Self> {
+        let target = Mutex::new(config.into_target()?);
+        Reconnecting::with_connect(policy, move || {
+            let mut target = target.lock().unwrap_or_else(PoisonError::into_inner);
+            target.client()?.get_connection()
+        })
+    }
+}
+
+impl AsyncReconnecting {
+    pub async fn sentinel(config: SentinelConfig<'_>, policy: RetryPolicy) -> RedisResult<Self> {
+        let target = Arc::new(tokio::sync::Mutex::new(config.into_target()?));
+        AsyncReconnecting::with_connect(policy, move || {
+            let target = target.clone();
+            async move {
+                let client = target.lock().await.async_client().await?;
+                client.get_multiplexed_tokio_connection().await
+            }
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests_sentinel {
+    use super::*;
+
+    #[test]
+    fn test_into_target() {
+        let mut config = SentinelConfig::new(vec!["10.0.0.1:26379", "10.0.0.2:26379"], "main", 3);
+        config.set_password("password");
+        let target = config.into_target().unwrap();
+        assert_eq!(target.master_name, "main");
+        assert!(!target.replica_reads);
+        let info = target.node.redis_connection_info.unwrap();
+        assert_eq!(info.db, 3);
+        assert_eq!(info.username, None);
+        assert_eq!(info.password.as_deref(), Some("password"));
+
+        let config = SentinelConfig::new(vec![], "main", 0);
+        assert!(config.into_target().is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use std::{
+    any::Any,
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use tokio::sync::OnceCell;
+
+type Call = Arc<dyn Any + Send + Sync>;
+
+/// Coalesces concurrent work on the same key into a single execution.
+///
+/// While a call for a key is in flight, later callers wait for it and receive
+/// a clone of its output instead of running their own. If the running call is
+/// dropped before finishing, one of the waiters takes over. Clones share the
+/// set of in-flight calls.
+#[derive(Clone, Default)]
+pub struct SingleFlight {
+    calls: Arc<Mutex<HashMap<Vec<Vec<u8>>, Call>>>,
+}
+
+impl SingleFlight {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn work<T, F, Fut>(&self, key: Vec<Vec<u8>>, f: F) -> T
+    where
+        T: Clone + Send + Sync + 'static,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let call = self
+            .calls()
+            .entry(key.clone())
+            .or_insert_with(|| Arc::new(OnceCell::<T>::new()))
+            .clone();
+        let Ok(cell) = call.downcast::<OnceCell<T>>() else {
+            // The key is in flight with a different output type.
+            return f().await;
+        };
+        let flight = Flight {
+            flights: self,
+            key,
+            cell,
+        };
+        flight.cell.get_or_init(f).await.clone()
+    }
+
+    fn calls(&self) -> MutexGuard<'_, HashMap<Vec<Vec<u8>>, Call>> {
+        self.calls.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Forgets the call once it has finished, or once its last caller gave up.
+struct Flight<'a, T> {
+    flights: &'a SingleFlight,
+    key: Vec<Vec<u8>>,
+    cell: Arc<OnceCell<T>>,
+}
+
+impl<T> Drop for Flight<'_, T> {
+    fn drop(&mut self) {
+        let mut calls = self.flights.calls();
+        let current = calls
+            .get(&self.key)
+            .is_some_and(|c| Arc::as_ptr(c) as *const () == Arc::as_ptr(&self.cell) as *const ());
+        // One reference is held by the map and one by this flight.
+        if current && (self.cell.initialized() || Arc::strong_count(&self.cell) <= 2) {
+            calls.remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_single_flight {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use redis::RedisError;
+
+    use crate::{AsyncCache, IAsyncCache, MemoryConnection};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_coalesced_load() {
+        let ca = AsyncCache::from_connection(MemoryConnection::new());
+        let loads = Arc::new(AtomicUsize::new(0));
+        let mut tasks = Vec::new();
+        for _ in 0..10 {
+            let mut ca = ca.clone();
+            let loads = loads.clone();
+            tasks.push(tokio::spawn(async move {
+                ca.get_or_set_coalesced("hot", Duration::from_secs(60), || async move {
+                    loads.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    Ok::<_, RedisError>("value".to_string())
+                })
+                .await
+            }));
+        }
+        for task in tasks {
+            assert_eq!(task.await.unwrap().unwrap(), "value");
+        }
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+        let mut ca = ca.clone();
+        assert_eq!(ca.get::<_, String>("hot").await.unwrap(), "value");
+    }
+
+    #[tokio::test]
+    async fn test_shared_error() {
+        #[derive(Debug, PartialEq)]
+        struct LoadError;
+
+        impl From<RedisError> for LoadError {
+            fn from(_: RedisError) -> Self {
+                LoadError
+            }
+        }
+
+        let ca = AsyncCache::from_connection(MemoryConnection::new());
+        let loads = Arc::new(AtomicUsize::new(0));
+        let mut tasks = Vec::new();
+        for _ in 0..5 {
+            let mut ca = ca.clone();
+            let loads = loads.clone();
+            tasks.push(tokio::spawn(async move {
+                ca.get_or_set_coalesced("bad", Duration::from_secs(60), || async move {
+                    loads.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    Err::<String, _>(LoadError)
+                })
+                .await
+            }));
+        }
+        for task in tasks {
+            assert_eq!(*task.await.unwrap().unwrap_err(), LoadError);
+        }
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_leader_dropped() {
+        let flights = SingleFlight::new();
+        let key = vec![b"k".to_vec()];
+        let leader = flights.work(key.clone(), std::future::pending::<u32>);
+        let leader = tokio::time::timeout(Duration::from_millis(10), leader);
+        assert!(leader.await.is_err());
+        assert!(flights.calls().is_empty());
+        assert_eq!(flights.work(key, || async { 7 }).await, 7);
+        assert!(flights.calls().is_empty());
+    }
+}
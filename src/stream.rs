@@ -0,0 +1,438 @@
+use std::time::Duration;
+
+use redis::{
+    aio::{Connection, ConnectionLike},
+    streams::{StreamId, StreamMaxlen, StreamRangeReply, StreamReadOptions},
+    Cmd, FromRedisValue, RedisResult, ToRedisArgs, Value,
+};
+
+use crate::{AsyncCache, Dedicated};
+
+/// The reply to `XAUTOCLAIM`.
+#[derive(Clone, Debug, Default)]
+pub struct StreamAutoClaimReply {
+    /// Where the next call should resume, `0-0` once every pending entry
+    /// was scanned.
+    pub next: String,
+    pub claimed: Vec<StreamId>,
+    /// Pending ids whose entries were deleted from the stream, which the
+    /// server dropped from the pending list. Always empty before Redis 7.
+    pub deleted: Vec<String>,
+}
+
+impl FromRedisValue for StreamAutoClaimReply {
+    fn from_redis_value(v: &Value) -> RedisResult<Self> {
+        let items: Vec<Value> = FromRedisValue::from_redis_value(v)?;
+        let [next, claimed, rest @ ..] = items.as_slice() else {
+            return Err((
+                redis::ErrorKind::TypeError,
+                "Response was of incompatible type",
+                "XAUTOCLAIM reply has fewer than two items".to_string(),
+            )
+                .into());
+        };
+        Ok(StreamAutoClaimReply {
+            next: FromRedisValue::from_redis_value(next)?,
+            claimed: StreamRangeReply::from_redis_value(claimed)?.ids,
+            deleted: match rest.first() {
+                Some(deleted) => FromRedisValue::from_redis_value(deleted)?,
+                None => Vec::new(),
+            },
+        })
+    }
+}
+
+pub(crate) fn xadd<K, ID, F, V>(key: K, id: ID, items: &[(F, V)]) -> Cmd
+where
+    K: ToRedisArgs,
+    ID: ToRedisArgs,
+    F: ToRedisArgs,
+    V: ToRedisArgs,
+{
+    let mut cmd = redis::cmd("XADD");
+    cmd.arg(key).arg(id).arg(items);
+    cmd
+}
+
+pub(crate) fn xrange<K, S, E>(key: K, start: S, end: E, count: Option<usize>) -> Cmd
+where
+    K: ToRedisArgs,
+    S: ToRedisArgs,
+    E: ToRedisArgs,
+{
+    let mut cmd = redis::cmd("XRANGE");
+    cmd.arg(key).arg(start).arg(end);
+    if let Some(count) = count {
+        cmd.arg("COUNT").arg(count);
+    }
+    cmd
+}
+
+/// Builds `XREADGROUP` instead of `XREAD` when `options` name a group.
+pub(crate) fn xread<K, ID>(keys: &[K], ids: &[ID], options: &StreamReadOptions) -> Cmd
+where
+    K: ToRedisArgs,
+    ID: ToRedisArgs,
+{
+    let name = match options.read_only() {
+        true => "XREAD",
+        false => "XREADGROUP",
+    };
+    let mut cmd = redis::cmd(name);
+    cmd.arg(options).arg("STREAMS").arg(keys).arg(ids);
+    cmd
+}
+
+pub(crate) fn xack<K, G, I>(key: K, group: G, ids: &[I]) -> Cmd
+where
+    K: ToRedisArgs,
+    G: ToRedisArgs,
+    I: ToRedisArgs,
+{
+    let mut cmd = redis::cmd("XACK");
+    cmd.arg(key).arg(group).arg(ids);
+    cmd
+}
+
+pub(crate) fn xpending<K, G>(key: K, group: G) -> Cmd
+where
+    K: ToRedisArgs,
+    G: ToRedisArgs,
+{
+    let mut cmd = redis::cmd("XPENDING");
+    cmd.arg(key).arg(group);
+    cmd
+}
+
+pub(crate) fn xpending_count<K, G, S, E>(key: K, group: G, start: S, end: E, count: usize) -> Cmd
+where
+    K: ToRedisArgs,
+    G: ToRedisArgs,
+    S: ToRedisArgs,
+    E: ToRedisArgs,
+{
+    let mut cmd = xpending(key, group);
+    cmd.arg(start).arg(end).arg(count);
+    cmd
+}
+
+pub(crate) fn xautoclaim<K, G, Cn, S>(
+    key: K,
+    group: G,
+    consumer: Cn,
+    min_idle: Duration,
+    start: S,
+    count: usize,
+) -> Cmd
+where
+    K: ToRedisArgs,
+    G: ToRedisArgs,
+    Cn: ToRedisArgs,
+    S: ToRedisArgs,
+{
+    let mut cmd = redis::cmd("XAUTOCLAIM");
+    cmd.arg(key)
+        .arg(group)
+        .arg(consumer)
+        .arg(min_idle.as_millis() as u64)
+        .arg(start)
+        .arg("COUNT")
+        .arg(count);
+    cmd
+}
+
+pub(crate) fn xtrim<K: ToRedisArgs>(key: K, maxlen: StreamMaxlen) -> Cmd {
+    let mut cmd = redis::cmd("XTRIM");
+    cmd.arg(key).arg(maxlen);
+    cmd
+}
+
+pub(crate) fn xgroup_create<K, G, ID>(key: K, group: G, id: ID) -> Cmd
+where
+    K: ToRedisArgs,
+    G: ToRedisArgs,
+    ID: ToRedisArgs,
+{
+    let mut cmd = redis::cmd("XGROUP");
+    cmd.arg("CREATE")
+        .arg(key)
+        .arg(group)
+        .arg(id)
+        .arg("MKSTREAM");
+    cmd
+}
+
+/// Processes the entries of a stream as one consumer of a consumer group.
+///
+/// Each step first claims entries that have been pending for longer than
+/// `min_idle`, whether left by another consumer or an earlier run of this
+/// one, and only reads new entries when there are none. An entry is
+/// acknowledged once its handler returns `Ok`; otherwise it stays pending
+/// and is claimed again later, so handlers must cope with seeing an entry
+/// more than once. Claiming needs Redis 6.2 or later.
+///
+/// An entry whose handler has failed `max_deliveries` times, as counted by
+/// `XPENDING`, is given up on: it is copied to the dead-letter stream if one
+/// is set, then acknowledged so that it is not claimed again.
+///
+/// Reads block the connection for up to `block`, so the worker needs a
+/// connection of its own, such as one from `AsyncCache::dedicated`:
+///
+/// ```no_run
+/// # async fn run() -> redis::RedisResult<()> {
+/// use cache_ru::{AsyncCache, RedisConfig, StreamWorker};
+///
+/// let cache = AsyncCache::dedicated(RedisConfig::new("127.0.0.1:6379", 0)).await?;
+/// let mut worker = StreamWorker::new(cache, "jobs", "mailer", "worker-1");
+/// worker
+///     .run(async |entry: &redis::streams::StreamId| {
+///         println!("job {}", entry.id);
+///         Ok::<_, ()>(())
+///     })
+///     .await
+/// # }
+/// ```
+pub struct StreamWorker<C = Connection> {
+    cache: AsyncCache<C>,
+    key: String,
+    group: String,
+    consumer: String,
+    count: usize,
+    block: Duration,
+    min_idle: Duration,
+    max_deliveries: usize,
+    dead_letter: Option<String>,
+    /// Where the next `XAUTOCLAIM` resumes scanning the pending entries.
+    cursor: String,
+}
+
+impl<C: ConnectionLike + Dedicated + Send> StreamWorker<C> {
+    pub fn new(cache: AsyncCache<C>, key: &str, group: &str, consumer: &str) -> Self {
+        StreamWorker {
+            cache,
+            key: key.into(),
+            group: group.into(),
+            consumer: consumer.into(),
+            count: 10,
+            block: Duration::from_secs(2),
+            min_idle: Duration::from_secs(30),
+            max_deliveries: 5,
+            dead_letter: None,
+            cursor: "0-0".into(),
+        }
+    }
+
+    /// The most entries handled per step.
+    pub fn set_count(&mut self, count: usize) {
+        self.count = count;
+    }
+
+    /// How long a step waits for new entries, zero meaning forever.
+    pub fn set_block(&mut self, block: Duration) {
+        self.block = block;
+    }
+
+    /// How long an entry stays pending before this worker claims it.
+    pub fn set_min_idle(&mut self, min_idle: Duration) {
+        self.min_idle = min_idle;
+    }
+
+    /// How many times an entry is handled before it is given up on, zero
+    /// meaning it is retried forever.
+    pub fn set_max_deliveries(&mut self, max_deliveries: usize) {
+        self.max_deliveries = max_deliveries;
+    }
+
+    /// The stream that entries given up on are added to, with their fields
+    /// but a new id.
+    pub fn set_dead_letter(&mut self, key: &str) {
+        self.dead_letter = Some(key.into());
+    }
+
+    /// Creates the group, and the stream if needed, delivering every entry
+    /// already in the stream. Does nothing if the group exists.
+    pub async fn create_group(&mut self) -> RedisResult<()> {
+        match self.cache.xgroup_create(&self.key, &self.group, "0").await {
+            Err(err) if err.code() == Some("BUSYGROUP") => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Claims stale entries, or else waits for new ones, runs `handler` on
+    /// each and acknowledges those it succeeded on, or gave up on. Returns how
+    /// many entries were acknowledged.
+    pub async fn step<E, F>(&mut self, handler: &mut F) -> RedisResult<usize>
+    where
+        F: AsyncFnMut(&StreamId) -> Result<(), E>,
+    {
+        let claim = self
+            .cache
+            .xautoclaim(
+                &self.key,
+                &self.group,
+                &self.consumer,
+                self.min_idle,
+                &self.cursor,
+                self.count,
+            )
+            .await?;
+        self.cursor = claim.next;
+        let mut entries = claim.claimed;
+        if entries.is_empty() {
+            let options = StreamReadOptions::default()
+                .count(self.count)
+                .block(self.block.as_millis() as usize);
+            let reply = self
+                .cache
+                .xreadgroup(&self.group, &self.consumer, &[&self.key], &[">"], options)
+                .await?;
+            entries = reply
+                .into_iter()
+                .flat_map(|reply| reply.keys)
+                .flat_map(|key| key.ids)
+                .collect();
+        }
+        let mut acked = 0;
+        for entry in &entries {
+            if handler(entry).await.is_ok() || self.give_up(entry).await? {
+                acked += self
+                    .cache
+                    .xack(&self.key, &self.group, &[&entry.id])
+                    .await?;
+            }
+        }
+        Ok(acked)
+    }
+
+    /// Whether the failed `entry` has been delivered `max_deliveries` times,
+    /// in which case it is copied to the dead-letter stream.
+    async fn give_up(&mut self, entry: &StreamId) -> RedisResult<bool> {
+        if self.max_deliveries == 0 {
+            return Ok(false);
+        }
+        let reply = self
+            .cache
+            .xpending_count(&self.key, &self.group, &entry.id, &entry.id, 1)
+            .await?;
+        let deliveries = reply
+            .ids
+            .first()
+            .map_or(0, |pending| pending.times_delivered);
+        if deliveries < self.max_deliveries {
+            return Ok(false);
+        }
+        if let Some(dead_letter) = &self.dead_letter {
+            let mut fields = Vec::with_capacity(entry.map.len());
+            for (field, value) in &entry.map {
+                fields.push((field, Vec::<u8>::from_redis_value(value)?));
+            }
+            self.cache.xadd(dead_letter, "*", &fields).await?;
+        }
+        Ok(true)
+    }
+
+    /// Creates the group if needed, then runs `step` until it fails.
+    pub async fn run<E, F>(&mut self, mut handler: F) -> RedisResult<()>
+    where
+        F: AsyncFnMut(&StreamId) -> Result<(), E>,
+    {
+        self.create_group().await?;
+        loop {
+            self.step(&mut handler).await?;
+        }
+    }
+
+    pub fn into_cache(self) -> AsyncCache<C> {
+        self.cache
+    }
+}
+
+#[cfg(test)]
+mod tests_stream {
+    use super::*;
+    use crate::MemoryConnection;
+
+    #[test]
+    fn test_autoclaim_reply() {
+        let data = |s: &str| Value::Data(s.as_bytes().to_vec());
+        let entry = Value::Bulk(vec![data("1-0"), Value::Bulk(vec![data("f"), data("v")])]);
+        let reply: StreamAutoClaimReply = FromRedisValue::from_redis_value(&Value::Bulk(vec![
+            data("2-0"),
+            Value::Bulk(vec![entry.clone()]),
+        ]))
+        .unwrap();
+        assert_eq!(reply.next, "2-0");
+        assert_eq!(reply.claimed[0].id, "1-0");
+        assert_eq!(reply.claimed[0].get::<String>("f").unwrap(), "v");
+        assert!(reply.deleted.is_empty());
+
+        let reply: StreamAutoClaimReply = FromRedisValue::from_redis_value(&Value::Bulk(vec![
+            data("0-0"),
+            Value::Bulk(vec![entry]),
+            Value::Bulk(vec![data("3-0")]),
+        ]))
+        .unwrap();
+        assert_eq!(reply.deleted, ["3-0"]);
+    }
+
+    #[tokio::test]
+    async fn test_worker() {
+        let con = MemoryConnection::new();
+        let mut ca = AsyncCache::from_connection(con.clone());
+        let mut worker = StreamWorker::new(AsyncCache::from_connection(con), "jobs", "g", "w1");
+        worker.set_block(Duration::from_millis(20));
+        worker.set_min_idle(Duration::from_millis(30));
+        worker.create_group().await.unwrap();
+        worker.create_group().await.unwrap();
+        for job in ["a", "b", "c"] {
+            ca.xadd("jobs", "*", &[("job", job)]).await.unwrap();
+        }
+
+        let mut seen = Vec::new();
+        let mut handler = async |entry: &StreamId| {
+            let job: String = entry.get("job").unwrap();
+            seen.push(job.clone());
+            match job.as_str() {
+                "b" if seen.len() == 2 => Err(()),
+                _ => Ok(()),
+            }
+        };
+        assert_eq!(worker.step(&mut handler).await.unwrap(), 2);
+        // Nothing new and "b" is not idle for long enough yet.
+        assert_eq!(worker.step(&mut handler).await.unwrap(), 0);
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        assert_eq!(worker.step(&mut handler).await.unwrap(), 1);
+        assert_eq!(seen, ["a", "b", "c", "b"]);
+        let pending = ca.xpending("jobs", "g").await.unwrap();
+        assert_eq!(pending.count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_worker_dead_letter() {
+        let con = MemoryConnection::new();
+        let mut ca = AsyncCache::from_connection(con.clone());
+        let mut worker = StreamWorker::new(AsyncCache::from_connection(con), "jobs", "g", "w1");
+        worker.set_block(Duration::from_millis(20));
+        worker.set_min_idle(Duration::ZERO);
+        worker.set_max_deliveries(3);
+        worker.set_dead_letter("jobs:dead");
+        worker.create_group().await.unwrap();
+        ca.xadd("jobs", "*", &[("job", "poison")]).await.unwrap();
+
+        let mut attempts = 0;
+        let mut handler = async |_: &StreamId| {
+            attempts += 1;
+            Err(())
+        };
+        assert_eq!(worker.step(&mut handler).await.unwrap(), 0);
+        assert_eq!(worker.step(&mut handler).await.unwrap(), 0);
+        assert_eq!(worker.step(&mut handler).await.unwrap(), 1);
+        assert_eq!(worker.step(&mut handler).await.unwrap(), 0);
+        assert_eq!(attempts, 3);
+        let pending = ca.xpending("jobs", "g").await.unwrap();
+        assert_eq!(pending.count(), 0);
+        let dead = ca.xrange("jobs:dead", "-", "+", None).await.unwrap();
+        assert_eq!(dead.ids.len(), 1);
+        assert_eq!(dead.ids[0].get::<String>("job").unwrap(), "poison");
+    }
+}
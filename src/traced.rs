@@ -0,0 +1,293 @@
+use std::time::Instant;
+
+use redis::{aio, Cmd, ConnectionLike, Pipeline, RedisError, RedisFuture, RedisResult, Value};
+use tracing::{field::Empty, Span};
+
+use crate::{
+    resp::{command_keys, parse_commands},
+    Dedicated,
+};
+
+/// A connection that wraps every request in a `tracing` span, for use with
+/// `Cache::from_connection` or `AsyncCache::from_connection`.
+///
+/// Spans are named after the command, or `PIPELINE`, and carry the
+/// OpenTelemetry database attributes `db.system`, `db.operation`,
+/// `db.statement` and `db.redis.database_index`. The statement holds the
+/// command names and keys but never values; keys can be redacted as well.
+/// On failure `otel.status_code` is set to `ERROR` and the error recorded.
+#[derive(Clone)]
+pub struct Traced<C> {
+    con: C,
+    redact_keys: bool,
+}
+
+impl<C> Traced<C> {
+    pub fn new(con: C) -> Self {
+        Traced {
+            con,
+            redact_keys: false,
+        }
+    }
+
+    /// Writes `?` instead of each key in `db.statement`.
+    pub fn set_redact_keys(&mut self, redact_keys: bool) {
+        self.redact_keys = redact_keys;
+    }
+
+    fn span(&self, packed: &[u8], db: i64) -> Span {
+        let span = tracing::info_span!(
+            "redis",
+            otel.name = Empty,
+            otel.kind = "client",
+            otel.status_code = Empty,
+            db.system = "redis",
+            db.operation = Empty,
+            db.statement = Empty,
+            db.redis.database_index = db,
+            duration_ms = Empty,
+            "error.type" = Empty,
+            error.message = Empty,
+        );
+        if span.is_disabled() {
+            return span;
+        }
+        let commands = parse_commands(packed).unwrap_or_default();
+        let mut operation = String::new();
+        let mut statement = String::new();
+        for args in &commands {
+            let Some(name) = args.first() else {
+                continue;
+            };
+            let name = name.to_ascii_uppercase();
+            if !statement.is_empty() {
+                statement.push('\n');
+            }
+            statement.push_str(&String::from_utf8_lossy(&name));
+            for key in command_keys(&name, args) {
+                statement.push(' ');
+                match self.redact_keys {
+                    true => statement.push('?'),
+                    false => statement.push_str(&String::from_utf8_lossy(key)),
+                }
+            }
+            operation = match commands.len() {
+                1 => String::from_utf8_lossy(&name).into_owned(),
+                _ => "PIPELINE".into(),
+            };
+        }
+        span.record("otel.name", operation.as_str());
+        span.record("db.operation", operation.as_str());
+        span.record("db.statement", statement.as_str());
+        span
+    }
+}
+
+fn finish<T>(span: &Span, start: Instant, result: &Result<T, RedisError>) {
+    span.record("duration_ms", start.elapsed().as_secs_f64() * 1000.0);
+    if let Err(err) = result {
+        span.record("otel.status_code", "ERROR");
+        span.record("error.type", format!("{:?}", err.kind()));
+        span.record("error.message", err.to_string());
+    }
+}
+
+impl<C: Dedicated> Dedicated for Traced<C> {}
+
+impl<C: ConnectionLike> ConnectionLike for Traced<C> {
+    fn req_packed_command(&mut self, cmd: &[u8]) -> RedisResult<Value> {
+        let span = self.span(cmd, self.con.get_db());
+        let _enter = span.enter();
+        let start = Instant::now();
+        let result = self.con.req_packed_command(cmd);
+        finish(&span, start, &result);
+        result
+    }
+
+    fn req_packed_commands(
+        &mut self,
+        cmd: &[u8],
+        offset: usize,
+        count: usize,
+    ) -> RedisResult<Vec<Value>> {
+        let span = self.span(cmd, self.con.get_db());
+        let _enter = span.enter();
+        let start = Instant::now();
+        let result = self.con.req_packed_commands(cmd, offset, count);
+        finish(&span, start, &result);
+        result
+    }
+
+    fn get_db(&self) -> i64 {
+        self.con.get_db()
+    }
+
+    fn check_connection(&mut self) -> bool {
+        self.con.check_connection()
+    }
+
+    fn is_open(&self) -> bool {
+        self.con.is_open()
+    }
+}
+
+impl<C: aio::ConnectionLike + Send> aio::ConnectionLike for Traced<C> {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        let span = self.span(&cmd.get_packed_command(), self.con.get_db());
+        Box::pin(tracing::Instrument::instrument(
+            async move {
+                let start = Instant::now();
+                let result = self.con.req_packed_command(cmd).await;
+                finish(&Span::current(), start, &result);
+                result
+            },
+            span,
+        ))
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        let span = self.span(&cmd.get_packed_pipeline(), self.con.get_db());
+        Box::pin(tracing::Instrument::instrument(
+            async move {
+                let start = Instant::now();
+                let result = self.con.req_packed_commands(cmd, offset, count).await;
+                finish(&Span::current(), start, &result);
+                result
+            },
+            span,
+        ))
+    }
+
+    fn get_db(&self) -> i64 {
+        self.con.get_db()
+    }
+}
+
+#[cfg(test)]
+mod tests_traced {
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+    };
+
+    use tracing::{
+        field::{Field, Visit},
+        span, Event, Metadata, Subscriber,
+    };
+
+    use super::*;
+    use crate::{AsyncCache, Cache, IAsyncCache, ICache, MemoryConnection};
+
+    #[derive(Default)]
+    struct Fields(HashMap<&'static str, String>);
+
+    impl std::ops::Deref for Fields {
+        type Target = HashMap<&'static str, String>;
+
+        fn deref(&self) -> &Self::Target {
+            &self.0
+        }
+    }
+
+    /// Collects the fields of every span, in creation order.
+    #[derive(Clone, Default)]
+    struct Recorder {
+        spans: Arc<Mutex<Vec<Fields>>>,
+        next_id: Arc<AtomicU64>,
+    }
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0.insert(field.name(), format!("{value:?}"));
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name(), value.to_string());
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+            let mut fields = Fields::default();
+            span.record(&mut fields);
+            self.spans.lock().unwrap().push(fields);
+            span::Id::from_u64(self.next_id.fetch_add(1, Ordering::SeqCst) + 1)
+        }
+
+        fn record(&self, span: &span::Id, values: &span::Record<'_>) {
+            let mut spans = self.spans.lock().unwrap();
+            values.record(&mut spans[span.into_u64() as usize - 1]);
+        }
+
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+        fn event(&self, _: &Event<'_>) {}
+
+        fn enter(&self, _: &span::Id) {}
+
+        fn exit(&self, _: &span::Id) {}
+    }
+
+    #[test]
+    fn test_spans() {
+        let recorder = Recorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+        let mut ca = Cache::from_connection(Traced::new(MemoryConnection::new()));
+        ca.set("user:1", "secret").unwrap();
+        ca.incr("user:1").unwrap_err();
+        ca.pipeline()
+            .get("a")
+            .del(&["b", "c"])
+            .query::<()>()
+            .unwrap();
+
+        let spans = recorder.spans.lock().unwrap();
+        assert_eq!(spans.len(), 3);
+        assert_eq!(spans[0]["db.system"], "redis");
+        assert_eq!(spans[0]["db.operation"], "SET");
+        assert_eq!(spans[0]["db.statement"], "SET user:1");
+        assert_eq!(spans[0]["db.redis.database_index"], "0");
+        assert!(spans[0].contains_key("duration_ms"));
+        assert!(!spans[0].contains_key("otel.status_code"));
+
+        assert_eq!(spans[1]["otel.status_code"], "ERROR");
+        assert_eq!(spans[1]["error.type"], "ResponseError");
+
+        assert_eq!(spans[2]["otel.name"], "PIPELINE");
+        assert_eq!(spans[2]["db.statement"], "GET a\nDEL b c");
+    }
+
+    #[test]
+    fn test_disabled() {
+        let _guard = tracing::subscriber::set_default(tracing::subscriber::NoSubscriber::default());
+        let con = Traced::new(MemoryConnection::new());
+        let packed = redis::cmd("GET").arg("a").get_packed_command();
+        assert!(con.span(&packed, 0).is_disabled());
+    }
+
+    #[tokio::test]
+    async fn test_async_redacted() {
+        let recorder = Recorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+        let mut con = Traced::new(MemoryConnection::new());
+        con.set_redact_keys(true);
+        let mut ca = AsyncCache::from_connection(con);
+        ca.hset("user:1", "name", "abc").await.unwrap();
+
+        let spans = recorder.spans.lock().unwrap();
+        assert_eq!(spans[0]["db.operation"], "HSET");
+        assert_eq!(spans[0]["db.statement"], "HSET ?");
+    }
+}